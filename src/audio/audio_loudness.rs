@@ -0,0 +1,344 @@
+use std::{f64::consts::PI, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::playback::{Biquad, Decoder, PlaybackError, StreamSpec};
+
+/// Loudness of silence, and the absolute gate of ITU-R BS.1770.
+const SILENCE_LUFS: f64 = -70.0;
+
+/// The loudness of some audio, per EBU R128.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS
+    pub integrated: f64,
+    /// Highest true peak, linear with 1 at full scale
+    pub true_peak: f64,
+    /// Loudness range in LU
+    pub range: f64,
+}
+
+impl Loudness {
+    /// Combine the measurements of several tracks as if they were one, eg.
+    /// for an album. Audio with nothing above the absolute gate, such as
+    /// silence or anything shorter than a gating block, has no loudness to
+    /// speak of, and gets `None`.
+    pub fn of(measurements: &[&Measurement]) -> Option<Self> {
+        let blocks = measurements
+            .iter()
+            .flat_map(|x| &x.blocks)
+            .copied()
+            .collect::<Vec<_>>();
+        let mut short_term = measurements
+            .iter()
+            .flat_map(|x| &x.short_term)
+            .copied()
+            .collect::<Vec<_>>();
+        let gated = gated_loudness(&blocks, -10.0)?;
+        Some(Self {
+            integrated: energy_to_lufs(mean(&gated)),
+            true_peak: measurements.iter().map(|x| x.true_peak).fold(0.0, f64::max),
+            range: loudness_range(&mut short_term),
+        })
+    }
+
+    /// ReplayGain 2.0 gain in dB, which brings the audio to -18 LUFS.
+    pub fn replay_gain(&self) -> f64 {
+        -18.0 - self.integrated
+    }
+}
+
+/// What is needed of a track to work out its loudness, alone or as part of
+/// an album.
+#[derive(Debug, Clone, Default)]
+pub struct Measurement {
+    /// Mean square of each 400 ms gating block, weighted across channels
+    blocks: Vec<f64>,
+    /// The same for each 3 s short-term window
+    short_term: Vec<f64>,
+    true_peak: f64,
+}
+
+/// Decode a file and measure it.
+pub fn measure_file(path: &Path) -> Result<Measurement, PlaybackError> {
+    let mut decoder = Decoder::open(path)?;
+    let mut meter = LoudnessMeter::new(decoder.spec());
+    while let Some(chunk) = decoder.next_chunk()? {
+        meter.process(&chunk);
+    }
+    Ok(meter.finish())
+}
+
+/// The two stages of the K-weighting filter, worked out for any sample rate
+/// so that they match the 48 kHz coefficients of BS.1770.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    // high shelf, for the acoustic effect of the head
+    let k = (PI * 1681.974450955533 / rate).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    // high pass, the RLB weighting
+    let k = (PI * 38.13547087602444 / rate).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    [shelf, high_pass]
+}
+
+/// Finds the peaks between samples by oversampling with a windowed sinc.
+#[derive(Debug, Clone)]
+struct TruePeak {
+    factor: usize,
+    /// Coefficients of each phase, newest sample first
+    phases: Vec<Vec<f64>>,
+    /// The last samples of each channel, newest first
+    history: Vec<Vec<f64>>,
+    peak: f64,
+}
+
+impl TruePeak {
+    /// Input samples each phase looks at
+    const TAPS: usize = 12;
+
+    fn new(spec: StreamSpec) -> Self {
+        // enough to get within a fraction of a dB, as BS.1770 asks for
+        let factor = match spec.sample_rate {
+            ..96000 => 4,
+            96000..192000 => 2,
+            _ => 1,
+        };
+        let len = Self::TAPS * factor;
+        let center = (len - 1) as f64 / 2.0;
+        let coef = |x: usize| {
+            let t = (x as f64 - center) / factor as f64;
+            let sinc = if t == 0.0 {
+                1.0
+            } else {
+                (PI * t).sin() / (PI * t)
+            };
+            let hann = 0.5 - 0.5 * (2.0 * PI * (x as f64 + 0.5) / len as f64).cos();
+            sinc * hann
+        };
+        let phases = (0..factor)
+            .map(|p| (0..Self::TAPS).map(|x| coef(x * factor + p)).collect())
+            .collect();
+        Self {
+            factor,
+            phases,
+            history: vec![vec![0.0; Self::TAPS]; spec.channels.max(1) as usize],
+            peak: 0.0,
+        }
+    }
+
+    fn process(&mut self, channel: usize, x: f64) {
+        self.peak = self.peak.max(x.abs());
+        if self.factor == 1 {
+            return;
+        }
+        let history = &mut self.history[channel];
+        history.rotate_right(1);
+        history[0] = x;
+        for phase in &self.phases {
+            let y = phase
+                .iter()
+                .zip(history.iter())
+                .map(|(a, b)| a * b)
+                .sum::<f64>();
+            self.peak = self.peak.max(y.abs());
+        }
+    }
+}
+
+/// Measures loudness per ITU-R BS.1770-4 and EBU Tech 3342, from
+/// interleaved samples.
+#[derive(Debug, Clone)]
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    /// Frames in 100 ms, which both gating blocks and short-term windows
+    /// are made of
+    step: usize,
+    /// Frames into the current step
+    at: usize,
+    /// Weighted sum of squares over the current step
+    sum: f64,
+    /// Mean square of each step so far
+    steps: Vec<f64>,
+    true_peak: TruePeak,
+}
+
+impl LoudnessMeter {
+    pub fn new(spec: StreamSpec) -> Self {
+        let channels = spec.channels.max(1) as usize;
+        // 5.1 has an LFE channel, which is left out, and louder surrounds
+        let weights = match channels {
+            6 => vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41],
+            _ => vec![1.0; channels],
+        };
+        Self {
+            channels,
+            filters: vec![k_weighting(spec.sample_rate); channels],
+            weights,
+            step: (spec.sample_rate as usize / 10).max(1),
+            at: 0,
+            sum: 0.0,
+            steps: vec![],
+            true_peak: TruePeak::new(spec),
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (c, x) in frame.iter().enumerate() {
+                let x = *x as f64;
+                self.true_peak.process(c, x);
+                let [shelf, high_pass] = &mut self.filters[c];
+                let y = high_pass.process(shelf.process(x));
+                self.sum += self.weights[c] * y * y;
+            }
+            self.at += 1;
+            if self.at == self.step {
+                self.steps.push(self.sum / self.step as f64);
+                self.at = 0;
+                self.sum = 0.0;
+            }
+        }
+    }
+
+    pub fn finish(self) -> Measurement {
+        // gating blocks are 400 ms long, starting every 100 ms
+        let windows = |len: usize| self.steps.windows(len).map(mean).collect::<Vec<_>>();
+        Measurement {
+            blocks: windows(4),
+            // short-term windows are 3 s long
+            short_term: windows(30),
+            true_peak: self.true_peak.peak,
+        }
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len().max(1) as f64
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn lufs_to_energy(lufs: f64) -> f64 {
+    10f64.powf((lufs + 0.691) / 10.0)
+}
+
+/// The blocks above both the absolute gate and a gate `relative` LU below
+/// the loudness of the blocks above the absolute gate, or `None` if there
+/// are none.
+fn gated_loudness(blocks: &[f64], relative: f64) -> Option<Vec<f64>> {
+    let absolute = lufs_to_energy(SILENCE_LUFS);
+    let loud = blocks
+        .iter()
+        .copied()
+        .filter(|x| *x > absolute)
+        .collect::<Vec<_>>();
+    if loud.is_empty() {
+        return None;
+    }
+    let relative = lufs_to_energy(energy_to_lufs(mean(&loud)) + relative);
+    Some(loud.into_iter().filter(|x| *x > relative).collect())
+}
+
+/// The spread between the 10th and 95th percentile of the short-term
+/// loudness, per EBU Tech 3342.
+fn loudness_range(short_term: &mut [f64]) -> f64 {
+    let Some(mut gated) = gated_loudness(short_term, -20.0) else {
+        return 0.0;
+    };
+    if gated.is_empty() {
+        return 0.0;
+    }
+    gated.sort_by(f64::total_cmp);
+    let percentile =
+        |p: f64| energy_to_lufs(gated[((gated.len() - 1) as f64 * p).round() as usize]);
+    percentile(0.95) - percentile(0.10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(rate: u32, freq: f64, amplitude: f64, secs: f64) -> Vec<f32> {
+        (0..(rate as f64 * secs) as usize)
+            .map(|x| (amplitude * (2.0 * PI * freq * x as f64 / rate as f64).sin()) as f32)
+            .collect()
+    }
+
+    fn measure(rate: u32, samples: &[f32]) -> Measurement {
+        let mut meter = LoudnessMeter::new(StreamSpec {
+            sample_rate: rate,
+            channels: 1,
+        });
+        meter.process(samples);
+        meter.finish()
+    }
+
+    #[test]
+    fn full_scale_sine() {
+        // a 997 Hz sine at 0 dBFS on one channel is -3.01 LUFS
+        for rate in [44100, 48000, 96000] {
+            let tone = measure(rate, &sine(rate, 997.0, 1.0, 5.0));
+            let loudness = Loudness::of(&[&tone]).unwrap();
+            assert!((loudness.integrated + 3.01).abs() < 0.05, "{loudness:?}");
+            assert!((loudness.true_peak - 1.0).abs() < 0.02, "{loudness:?}");
+            assert!(loudness.range < 0.1, "{loudness:?}");
+        }
+    }
+
+    #[test]
+    fn gates_quiet_parts() {
+        let rate = 48000;
+        let loud = measure(rate, &sine(rate, 997.0, 0.5, 10.0));
+        let mut samples = sine(rate, 997.0, 0.5, 10.0);
+        samples.extend(vec![0.0; rate as usize * 10]);
+        let with_silence = measure(rate, &samples);
+        let a = Loudness::of(&[&loud]).unwrap();
+        let b = Loudness::of(&[&with_silence]).unwrap();
+        // only the few blocks that overlap the end of the tone count
+        assert!((a.integrated - b.integrated).abs() < 0.1, "{a:?} {b:?}");
+
+        // 20 dB quieter halves give a range of 20 LU
+        let quiet = measure(rate, &sine(rate, 997.0, 0.05, 10.0));
+        let album = Loudness::of(&[&loud, &quiet]).unwrap();
+        assert!((album.range - 20.0).abs() < 0.5, "{album:?}");
+        assert!(album.integrated > a.integrated - 3.1);
+    }
+
+    #[test]
+    fn nothing_above_the_gate() {
+        let rate = 48000;
+        let silence = measure(rate, &vec![0.0; rate as usize * 5]);
+        assert_eq!(Loudness::of(&[&silence]), None);
+        // too short for a single 400 ms block
+        let short = measure(rate, &sine(rate, 997.0, 1.0, 0.3));
+        assert_eq!(Loudness::of(&[&short]), None);
+        // but either counts towards an album with something audible
+        let tone = measure(rate, &sine(rate, 997.0, 1.0, 5.0));
+        let album = Loudness::of(&[&tone, &silence, &short]).unwrap();
+        assert_eq!(album, Loudness::of(&[&tone]).unwrap());
+    }
+}
@@ -0,0 +1,121 @@
+use std::{fmt, time::Duration};
+
+use lofty::{file::FileType, properties::FileProperties};
+
+/// The container/codec a file was read as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Codec {
+    Aac,
+    Aiff,
+    Ape,
+    Flac,
+    Mp3,
+    Mp4,
+    Musepack,
+    Opus,
+    Speex,
+    Vorbis,
+    Wav,
+    WavPack,
+    Other,
+}
+
+impl From<FileType> for Codec {
+    fn from(value: FileType) -> Self {
+        match value {
+            FileType::Aac => Self::Aac,
+            FileType::Aiff => Self::Aiff,
+            FileType::Ape => Self::Ape,
+            FileType::Flac => Self::Flac,
+            FileType::Mpeg => Self::Mp3,
+            FileType::Mp4 => Self::Mp4,
+            FileType::Mpc => Self::Musepack,
+            FileType::Opus => Self::Opus,
+            FileType::Speex => Self::Speex,
+            FileType::Vorbis => Self::Vorbis,
+            FileType::Wav => Self::Wav,
+            FileType::WavPack => Self::WavPack,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Aac => "AAC",
+            Self::Aiff => "AIFF",
+            Self::Ape => "APE",
+            Self::Flac => "FLAC",
+            Self::Mp3 => "MP3",
+            Self::Mp4 => "MP4",
+            Self::Musepack => "Musepack",
+            Self::Opus => "Opus",
+            Self::Speex => "Speex",
+            Self::Vorbis => "Vorbis",
+            Self::Wav => "WAV",
+            Self::WavPack => "WavPack",
+            Self::Other => "Unknown",
+        })
+    }
+}
+
+/// Audio properties of a file, as read during the scan.
+#[derive(Debug, Clone)]
+pub struct AudioField {
+    pub duration: Duration,
+    /// Audio bitrate in kbps, falling back on the overall bitrate
+    pub bitrate: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u8>,
+    pub channels: Option<u8>,
+    pub codec: Codec,
+}
+
+impl AudioField {
+    pub fn new(props: &FileProperties, file_type: FileType) -> Self {
+        Self {
+            duration: props.duration(),
+            bitrate: props.audio_bitrate().or(props.overall_bitrate()),
+            sample_rate: props.sample_rate(),
+            bit_depth: props.bit_depth(),
+            channels: props.channels(),
+            codec: file_type.into(),
+        }
+    }
+
+    /// Format the duration as "m:ss", or "h:mm:ss" for anything an hour or
+    /// longer.
+    pub fn display_duration(&self) -> String {
+        format_duration(self.duration)
+    }
+
+    /// A one-line summary for track info, eg. "FLAC · 44.1 kHz · 16 bit · 2 ch · 1411 kbps".
+    pub fn display_summary(&self) -> String {
+        let mut ret = vec![self.codec.to_string()];
+        if let Some(rate) = self.sample_rate {
+            ret.push(format!("{} kHz", rate as f32 / 1000.0));
+        }
+        if let Some(depth) = self.bit_depth {
+            ret.push(format!("{depth} bit"));
+        }
+        if let Some(channels) = self.channels {
+            ret.push(format!("{channels} ch"));
+        }
+        if let Some(bitrate) = self.bitrate {
+            ret.push(format!("{bitrate} kbps"));
+        }
+        ret.join(" · ")
+    }
+}
+
+/// Format a duration as "m:ss", or "h:mm:ss" for anything an hour or longer.
+pub fn format_duration(inp: Duration) -> String {
+    let secs = inp.as_secs();
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{h}:{m:02}:{s:02}")
+    } else {
+        format!("{m}:{s:02}")
+    }
+}
@@ -0,0 +1,5 @@
+mod audio_loudness;
+mod audio_props;
+
+pub use audio_loudness::*;
+pub use audio_props::*;
@@ -0,0 +1,320 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use uuid::Uuid;
+
+use crate::{
+    Item,
+    tag::{self, TagSet},
+};
+
+/// What tells an album apart from any other album with the same title.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AlbumId {
+    /// The MusicBrainz release id, which is unique per release.
+    MusicBrainz(Uuid),
+    /// Lowercased album title and album artists.
+    Tagged { title: String, artists: Vec<String> },
+    /// Lowercased album title and the folder it was found in, used when there
+    /// is no artist to go off of.
+    Folder { title: String, dir: PathBuf },
+}
+
+impl AlbumId {
+    pub fn new(path: &Path, tags: Option<&TagSet>) -> Self {
+        if let Some(id) = tags.and_then(|x| x.get_typed_tag::<tag::MusicBrainzReleaseId>()) {
+            return Self::MusicBrainz(id.0);
+        }
+        let title = tags
+            .and_then(|x| x.album_title())
+            .unwrap_or_default()
+            .to_lowercase();
+        let artists = tags
+            .map(|x| x.album_artists())
+            .unwrap_or_default()
+            .iter()
+            .map(|x| x.to_lowercase())
+            .collect::<Vec<_>>();
+        if artists.is_empty() {
+            Self::Folder {
+                title,
+                dir: path.parent().unwrap_or(path).to_owned(),
+            }
+        } else {
+            Self::Tagged { title, artists }
+        }
+    }
+}
+
+/// What tells an artist apart from any other artist with the same name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ArtistId {
+    /// The MusicBrainz artist id.
+    MusicBrainz(Uuid),
+    /// Lowercased artist name.
+    Name(String),
+}
+
+impl ArtistId {
+    /// Pair up artist names with their MusicBrainz ids. The ids are only used
+    /// if there is exactly one per name, as otherwise we cannot tell which id
+    /// belongs to which name.
+    pub fn from_names(names: &[String], ids: Option<&[Uuid]>) -> Vec<Self> {
+        match ids {
+            Some(ids) if ids.len() == names.len() => {
+                ids.iter().copied().map(Self::MusicBrainz).collect()
+            }
+            _ => names.iter().map(|x| Self::Name(x.to_lowercase())).collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Album {
+    pub id: AlbumId,
+    pub release_group: Option<Uuid>,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub artist_ids: Vec<ArtistId>,
+    /// Indexes into `Tracks`, ordered by disc and track
+    pub tracks: Vec<usize>,
+}
+
+/// The library's tracks grouped into albums.
+///
+/// Albums are ordered by date, except that editions of the same MusicBrainz
+/// release group are kept next to each other.
+#[derive(Debug, Default)]
+pub struct Albums {
+    albums: Vec<Album>,
+    /// Indexes of the albums in each release group
+    groups: HashMap<Uuid, Vec<usize>>,
+}
+
+impl Albums {
+    pub fn new(tracks: &[Item]) -> Self {
+        let mut index = HashMap::new();
+        let mut ret: Vec<Album> = vec![];
+        for (e, item) in tracks.iter().enumerate() {
+            if item.audio.is_none() && item.tags.is_none() || item.kind != super::MediaKind::Music {
+                continue;
+            }
+            let tags = item.tags.as_ref();
+            let id = AlbumId::new(&item.path, tags);
+            let at = *index.entry(id.clone()).or_insert_with(|| {
+                let artists = tags.map(|x| x.album_artists()).unwrap_or_default().to_vec();
+                let artist_ids = ArtistId::from_names(
+                    &artists,
+                    tags.and_then(|x| x.get_typed_tag::<tag::MusicBrainzReleaseArtistId>())
+                        .map(|x| x.0.as_slice()),
+                );
+                ret.push(Album {
+                    id,
+                    release_group: tags
+                        .and_then(|x| x.get_typed_tag::<tag::MusicBrainzReleaseGroupId>())
+                        .map(|x| x.0),
+                    title: tags
+                        .and_then(|x| x.album_title())
+                        .unwrap_or_default()
+                        .to_owned(),
+                    artists,
+                    artist_ids,
+                    tracks: vec![],
+                });
+                ret.len() - 1
+            });
+            ret[at].tracks.push(e);
+        }
+
+        for album in &mut ret {
+            album.tracks.sort_by_key(|x| track_order(&tracks[*x]));
+        }
+
+        // keep editions together, at the position of the earliest one
+        let date = |x: &Album| {
+            tracks[x.tracks[0]]
+                .tags
+                .as_ref()
+                .and_then(|x| x.sort_date())
+        };
+        let mut group_date: HashMap<Uuid, Option<tag::PartialDate>> = HashMap::new();
+        for album in &ret {
+            if let Some(group) = album.release_group {
+                let at = group_date.entry(group).or_insert(None);
+                *at = match (*at, date(album)) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+            }
+        }
+        ret.sort_by_cached_key(|x| {
+            let group = x.release_group.and_then(|x| group_date.get(&x).copied());
+            (
+                group.unwrap_or(date(x)),
+                x.release_group,
+                date(x),
+                x.title.to_lowercase(),
+            )
+        });
+
+        let mut groups: HashMap<Uuid, Vec<usize>> = HashMap::new();
+        for (e, album) in ret.iter().enumerate() {
+            if let Some(group) = album.release_group {
+                groups.entry(group).or_default().push(e);
+            }
+        }
+
+        Self {
+            albums: ret,
+            groups,
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Album> {
+        self.albums.get(idx)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Album> {
+        self.albums.iter()
+    }
+
+    /// The index of an album, which changes whenever the library does.
+    pub fn find(&self, id: &AlbumId) -> Option<usize> {
+        self.albums.iter().position(|x| x.id == *id)
+    }
+
+    /// Indexes of the other editions of an album, ie. the other releases in
+    /// the same release group.
+    pub fn editions(&self, idx: usize) -> impl Iterator<Item = usize> {
+        self.get(idx)
+            .and_then(|x| self.groups.get(&x.release_group?))
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(move |x| *x != idx)
+    }
+}
+
+/// The order of a track within its album: disc, vinyl side, track, then path.
+fn track_order(item: &Item) -> (u32, Option<String>, u32, PathBuf) {
+    let tags = item.tags.as_ref();
+    (
+        tags.and_then(|x| x.get_typed_tag::<tag::DiscPos>())
+            .map_or(0, |x| x.0),
+        tags.and_then(|x| x.get_typed_tag::<tag::TrackSide>())
+            .map(|x| x.side.clone()),
+        tags.and_then(|x| x.get_typed_tag::<tag::TrackPos>())
+            .map_or(0, |x| x.0),
+        item.path.clone(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::test_util::item;
+
+    fn track(path: &str, album: &str, artist: Option<&str>, release: Option<u128>) -> Item {
+        let mut tags = TagSet::new();
+        let _ = tags.push_typed_tag(tag::AlbumTitle {
+            inner: album.to_owned(),
+            sort_order: None,
+        });
+        if let Some(artist) = artist {
+            let _ = tags.push_typed_tag(tag::AlbumArtist {
+                inner: vec![artist.to_owned()],
+                sort_order: vec![],
+            });
+        }
+        if let Some(id) = release {
+            let _ = tags.push_typed_tag(tag::MusicBrainzReleaseId(Uuid::from_u128(id)));
+        }
+        item(path, tags, Some(60))
+    }
+
+    fn grouped(tracks: &[Item]) -> Vec<(AlbumId, Vec<usize>)> {
+        let mut ret = Albums::new(tracks)
+            .iter()
+            .map(|x| (x.id.clone(), x.tracks.clone()))
+            .collect::<Vec<_>>();
+        ret.sort_by_key(|x| x.1.clone());
+        ret
+    }
+
+    #[test]
+    fn musicbrainz_release() {
+        let tracks = [
+            track("/a/1.flac", "Greatest Hits", Some("Queen"), Some(1)),
+            track("/b/1.flac", "Greatest Hits", Some("Queen"), Some(2)),
+            // the id wins over differing tag text
+            track("/c/2.flac", "Greatest Hits (Remastered)", None, Some(1)),
+        ];
+        assert_eq!(
+            grouped(&tracks),
+            [
+                (AlbumId::MusicBrainz(Uuid::from_u128(1)), vec![0, 2]),
+                (AlbumId::MusicBrainz(Uuid::from_u128(2)), vec![1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn tagged_fallback() {
+        let tracks = [
+            track("/a/1.flac", "Greatest Hits", Some("Queen"), None),
+            track("/b/2.flac", "greatest hits", Some("QUEEN"), None),
+            track("/a/3.flac", "Greatest Hits", Some("ABBA"), None),
+        ];
+        let queen = AlbumId::Tagged {
+            title: "greatest hits".to_owned(),
+            artists: vec!["queen".to_owned()],
+        };
+        let abba = AlbumId::Tagged {
+            title: "greatest hits".to_owned(),
+            artists: vec!["abba".to_owned()],
+        };
+        assert_eq!(grouped(&tracks), [(queen, vec![0, 1]), (abba, vec![2])]);
+    }
+
+    #[test]
+    fn folder_fallback() {
+        let tracks = [
+            track("/a/1.flac", "Untitled", None, None),
+            track("/a/2.flac", "Untitled", None, None),
+            track("/b/1.flac", "Untitled", None, None),
+        ];
+        let folder = |dir: &str| AlbumId::Folder {
+            title: "untitled".to_owned(),
+            dir: dir.into(),
+        };
+        assert_eq!(
+            grouped(&tracks),
+            [(folder("/a"), vec![0, 1]), (folder("/b"), vec![2])]
+        );
+    }
+
+    #[test]
+    fn editions() {
+        let mut tracks = [
+            track("/a/1.flac", "Greatest Hits", Some("Queen"), Some(1)),
+            track("/b/1.flac", "Greatest Hits", Some("Queen"), Some(2)),
+            track("/c/1.flac", "Innuendo", Some("Queen"), Some(3)),
+        ];
+        for x in &mut tracks[..2] {
+            let tags = x.tags.as_mut().unwrap();
+            let _ = tags.push_typed_tag(tag::MusicBrainzReleaseGroupId(Uuid::from_u128(10)));
+        }
+        let albums = Albums::new(&tracks);
+        let find = |x| {
+            albums
+                .find(&AlbumId::MusicBrainz(Uuid::from_u128(x)))
+                .unwrap()
+        };
+        assert_eq!(albums.editions(find(1)).collect::<Vec<_>>(), [find(2)]);
+        assert_eq!(albums.editions(find(2)).collect::<Vec<_>>(), [find(1)]);
+        assert_eq!(albums.editions(find(3)).count(), 0);
+        assert_eq!(albums.editions(100).count(), 0);
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use crate::{Item, tag};
+
+use super::{Album, Albums, ArtistId};
+
+/// An album artist, with the albums they are credited on.
+#[derive(Debug)]
+pub struct Artist {
+    pub id: ArtistId,
+    pub name: String,
+    /// The name used for ordering, eg. "Beatles, The"
+    pub sort_name: String,
+    /// Indexes into `Albums` where they are an album artist, oldest first
+    pub albums: Vec<usize>,
+    /// Indexes into `Albums` of compilations and other artists' albums that
+    /// they have tracks on, oldest first
+    pub appearances: Vec<usize>,
+}
+
+/// All album artists in the library, ordered by their sort names.
+#[derive(Debug, Default)]
+pub struct Artists(Vec<Artist>);
+
+impl Artists {
+    pub fn new(tracks: &[Item], albums: &Albums) -> Self {
+        let mut index = HashMap::new();
+        let mut ret: Vec<Artist> = vec![];
+
+        // the album artists come first, so that only they are listed
+        for (e, album) in albums.iter().enumerate() {
+            if is_compilation(tracks, album) {
+                continue;
+            }
+            let sort_orders = tracks[album.tracks[0]]
+                .tags
+                .as_ref()
+                .and_then(|x| x.get_typed_tag::<tag::AlbumArtist>())
+                .map(|x| x.sort_order.as_slice())
+                .unwrap_or_default();
+            for (i, (name, id)) in album.artists.iter().zip(&album.artist_ids).enumerate() {
+                let sort_order = paired_sort_order(&album.artists, sort_orders, i);
+                let at = *index.entry(id.clone()).or_insert_with(|| {
+                    ret.push(Artist {
+                        id: id.clone(),
+                        name: name.clone(),
+                        sort_name: sort_name(name, sort_order),
+                        albums: vec![],
+                        appearances: vec![],
+                    });
+                    ret.len() - 1
+                });
+                ret[at].albums.push(e);
+            }
+        }
+
+        // then any albums they only have some tracks on
+        for (e, album) in albums.iter().enumerate() {
+            for item in &album.tracks {
+                let Some(tags) = tracks[*item].tags.as_ref() else {
+                    continue;
+                };
+                let ids = ArtistId::from_names(
+                    tags.track_artists(),
+                    tags.get_typed_tag::<tag::MusicBrainzArtistId>()
+                        .map(|x| x.0.as_slice()),
+                );
+                for id in ids {
+                    if album.artist_ids.contains(&id) {
+                        continue;
+                    }
+                    if let Some(at) = index.get(&id) {
+                        let appearances = &mut ret[*at].appearances;
+                        if appearances.last() != Some(&e) {
+                            appearances.push(e);
+                        }
+                    }
+                }
+            }
+        }
+
+        ret.sort_by_cached_key(|x| x.sort_name.to_lowercase());
+        Self(ret)
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Artist> {
+        self.0.get(idx)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Artist> {
+        self.0.iter()
+    }
+
+    /// The index of an artist, which changes whenever the library does.
+    pub fn find(&self, id: &ArtistId) -> Option<usize> {
+        self.0.iter().position(|x| x.id == *id)
+    }
+}
+
+/// Whether an album is a various artists release, going by the compilation
+/// flag or the album artist.
+fn is_compilation(tracks: &[Item], album: &Album) -> bool {
+    let flagged = tracks[album.tracks[0]]
+        .tags
+        .as_ref()
+        .and_then(|x| x.get_typed_tag::<tag::Compilation>())
+        .is_some_and(|x| x.0);
+    flagged
+        || album.artists.is_empty()
+        || album
+            .artists
+            .iter()
+            .any(|x| x.eq_ignore_ascii_case("various artists") || x.eq_ignore_ascii_case("various"))
+}
+
+/// The sort order for the `i`th name. The sort orders are only used if there
+/// is exactly one per name, as otherwise we cannot tell which goes with which.
+fn paired_sort_order<'a>(names: &[String], sort_orders: &'a [String], i: usize) -> Option<&'a str> {
+    (names.len() == sort_orders.len()).then(|| sort_orders[i].as_str())
+}
+
+/// The name to order an artist by. This is the tagged sort order if there is
+/// one, otherwise a leading article is moved to the end, so that "The Beatles"
+/// becomes "Beatles, The".
+pub fn sort_name(name: &str, sort_order: Option<&str>) -> String {
+    if let Some(sort_order) = sort_order.map(str::trim).filter(|x| !x.is_empty()) {
+        return sort_order.to_owned();
+    }
+    for article in ["The", "A", "An"] {
+        let rest = name
+            .get(..article.len())
+            .filter(|x| x.eq_ignore_ascii_case(article))
+            .and_then(|_| name[article.len()..].strip_prefix(' '))
+            .map(str::trim_start)
+            .filter(|x| !x.is_empty());
+        if let Some(rest) = rest {
+            return format!("{rest}, {}", &name[..article.len()]);
+        }
+    }
+    name.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::test_util::{item, tags};
+
+    fn track(path: &str, album: &str, album_artist: &str, artist: &str) -> Item {
+        let mut tags = tags(path, artist);
+        let _ = tags.push_typed_tag(tag::AlbumTitle {
+            inner: album.to_owned(),
+            sort_order: None,
+        });
+        let _ = tags.push_typed_tag(tag::AlbumArtist {
+            inner: vec![album_artist.to_owned()],
+            sort_order: vec![],
+        });
+        item(path, tags, Some(60))
+    }
+
+    #[test]
+    fn sort_names() {
+        assert_eq!(sort_name("The Beatles", None), "Beatles, The");
+        assert_eq!(sort_name("the the", None), "the, the");
+        assert_eq!(
+            sort_name("A Tribe Called Quest", None),
+            "Tribe Called Quest, A"
+        );
+        assert_eq!(sort_name("An Pierlé", None), "Pierlé, An");
+        assert_eq!(sort_name("Theatre of Tragedy", None), "Theatre of Tragedy");
+        assert_eq!(sort_name("Anathema", None), "Anathema");
+        assert_eq!(sort_name("The", None), "The");
+        assert_eq!(sort_name("Björk", None), "Björk");
+        assert_eq!(sort_name("The Beatles", Some("Beatles")), "Beatles");
+        assert_eq!(sort_name("The Beatles", Some(" ")), "Beatles, The");
+    }
+
+    #[test]
+    fn appearances() {
+        let tracks = [
+            track("/a/1.flac", "Abbey Road", "The Beatles", "The Beatles"),
+            track("/b/1.flac", "Imagine", "John Lennon", "John Lennon"),
+            track("/c/1.flac", "Now", "Various Artists", "The Beatles"),
+            track("/c/2.flac", "Now", "Various Artists", "John Lennon"),
+            track("/d/1.flac", "Duets", "John Lennon", "The Beatles"),
+            track("/e/1.flac", "Other", "Someone", "Nobody Else"),
+        ];
+        let albums = Albums::new(&tracks);
+        let artists = Artists::new(&tracks, &albums);
+        let album = |x| {
+            albums
+                .iter()
+                .position(|album| album.tracks.contains(&x))
+                .unwrap()
+        };
+
+        // the compilation is nobody's, and track artists are not listed
+        let names = artists.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["The Beatles", "John Lennon", "Someone"]);
+
+        let beatles = artists.get(0).unwrap();
+        assert_eq!(beatles.sort_name, "Beatles, The");
+        assert_eq!(beatles.albums, [album(0)]);
+        let mut appearances = beatles.appearances.clone();
+        appearances.sort();
+        let mut expected = vec![album(2), album(4)];
+        expected.sort();
+        assert_eq!(appearances, expected);
+
+        let lennon = artists.get(1).unwrap();
+        let mut own = lennon.albums.clone();
+        own.sort();
+        let mut expected = vec![album(1), album(4)];
+        expected.sort();
+        assert_eq!(own, expected);
+        assert_eq!(lennon.appearances, [album(2)]);
+        assert!(artists.get(2).unwrap().appearances.is_empty());
+    }
+}
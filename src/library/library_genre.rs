@@ -0,0 +1,270 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{Item, settings::GenreConfig};
+
+use super::MediaKind;
+
+/// A genre, with the tracks tagged with it.
+#[derive(Debug)]
+pub struct Genre {
+    pub name: String,
+    /// Index into `Genres` of the parent genre
+    pub parent: Option<usize>,
+    /// Indexes into `Genres`, ordered by name
+    pub children: Vec<usize>,
+    /// Indexes into `Tracks` of the tracks tagged with exactly this genre
+    pub tracks: Vec<usize>,
+}
+
+/// All genres in the library, arranged into the configured hierarchy.
+#[derive(Debug, Default)]
+pub struct Genres(Vec<Genre>);
+
+impl Genres {
+    pub fn new(tracks: &[Item], config: &GenreConfig) -> Self {
+        let aliases = config
+            .aliases
+            .iter()
+            .flat_map(|(name, others)| {
+                std::iter::once(name)
+                    .chain(others)
+                    .map(move |x| (genre_key(x), name.as_str()))
+            })
+            .collect::<HashMap<_, _>>();
+        let parents = config
+            .parents
+            .iter()
+            .map(|(child, parent)| (genre_key(child), parent.as_str()))
+            .collect::<HashMap<_, _>>();
+
+        let mut ret = Self::default();
+        let mut index = HashMap::new();
+        for (e, item) in tracks.iter().enumerate() {
+            if item.kind != MediaKind::Music {
+                continue;
+            }
+            let Some(tags) = item.tags.as_ref() else {
+                continue;
+            };
+            for name in tags.genres() {
+                let at = ret.intern(&mut index, &aliases, name);
+                // aliases can turn several genres of a track into one
+                if ret.0[at].tracks.last() != Some(&e) {
+                    ret.0[at].tracks.push(e);
+                }
+            }
+        }
+
+        // hook up the parents, adding any that are not tagged on anything
+        let mut at = 0;
+        while at < ret.0.len() {
+            let key = genre_key(&ret.0[at].name);
+            if let Some(parent) = parents.get(&key) {
+                let parent = ret.intern(&mut index, &aliases, parent);
+                if !ret.is_ancestor(at, parent) {
+                    ret.0[at].parent = Some(parent);
+                    ret.0[parent].children.push(at);
+                }
+            }
+            at += 1;
+        }
+        let names = ret
+            .0
+            .iter()
+            .map(|x| x.name.to_lowercase())
+            .collect::<Vec<_>>();
+        for genre in &mut ret.0 {
+            genre.children.sort_by_key(|x| &names[*x]);
+        }
+
+        ret
+    }
+
+    /// Find or add the genre going by a name.
+    fn intern(
+        &mut self,
+        index: &mut HashMap<String, usize>,
+        aliases: &HashMap<String, &str>,
+        name: &str,
+    ) -> usize {
+        let key = genre_key(name);
+        let (key, name) = match aliases.get(&key) {
+            Some(alias) => (genre_key(alias), *alias),
+            None => (key, name),
+        };
+        *index.entry(key).or_insert_with(|| {
+            self.0.push(Genre {
+                name: name.to_owned(),
+                parent: None,
+                children: vec![],
+                tracks: vec![],
+            });
+            self.0.len() - 1
+        })
+    }
+
+    /// Whether `genre` is `of` or one of its parents, which would make a loop.
+    fn is_ancestor(&self, genre: usize, mut of: usize) -> bool {
+        loop {
+            if genre == of {
+                return true;
+            }
+            match self.0[of].parent {
+                Some(parent) => of = parent,
+                None => return false,
+            }
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Genre> {
+        self.0.get(idx)
+    }
+
+    /// The genres in display order, with their depth in the hierarchy: each
+    /// genre is followed by its children, and top level genres are ordered
+    /// by name.
+    pub fn tree(&self) -> Vec<(usize, usize)> {
+        let mut roots = (0..self.0.len())
+            .filter(|x| self.0[*x].parent.is_none())
+            .collect::<Vec<_>>();
+        roots.sort_by_cached_key(|x| self.0[*x].name.to_lowercase());
+
+        let mut ret = vec![];
+        let mut stack = roots.into_iter().rev().map(|x| (x, 0)).collect::<Vec<_>>();
+        while let Some((at, depth)) = stack.pop() {
+            ret.push((at, depth));
+            stack.extend(self.0[at].children.iter().rev().map(|x| (*x, depth + 1)));
+        }
+        ret
+    }
+
+    /// The tracks of a genre and all of its sub-genres, in library order.
+    pub fn all_tracks(&self, idx: usize) -> Vec<usize> {
+        let mut ret = HashSet::new();
+        let mut stack = vec![idx];
+        while let Some(at) = stack.pop() {
+            ret.extend(&self.0[at].tracks);
+            stack.extend(&self.0[at].children);
+        }
+        let mut ret = ret.into_iter().collect::<Vec<_>>();
+        ret.sort();
+        ret
+    }
+}
+
+/// What genres are matched by: lowercase, ignoring anything other than
+/// letters, numbers and '&', so that "Hip-Hop" and "hip hop" are the same.
+fn genre_key(name: &str) -> String {
+    name.chars()
+        .filter(|x| x.is_alphanumeric() || *x == '&')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        library::test_util::{item, tags},
+        tag,
+    };
+
+    fn track(genres: &[&str]) -> Item {
+        let mut tags = tags("Title", "Artist");
+        let genres = genres.iter().map(|x| x.to_string()).collect();
+        let _ = tags.push_typed_tag(tag::Genre(genres));
+        item("/music/track.flac", tags, Some(60))
+    }
+
+    fn find<'a>(genres: &'a Genres, name: &str) -> (usize, &'a Genre) {
+        genres
+            .0
+            .iter()
+            .enumerate()
+            .find(|(_, x)| x.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn aliases() {
+        let tracks = [
+            track(&["Hip-Hop"]),
+            track(&["Hip Hop"]),
+            track(&["Rap/Hip Hop"]),
+            track(&["hip hop", "Hip-Hop/Rap"]),
+            track(&["RnB"]),
+        ];
+        let genres = Genres::new(&tracks, &GenreConfig::default());
+        let names = genres.0.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        // genres with aliases go by the configured name
+        assert_eq!(names, ["Hip Hop", "R&B"]);
+        assert_eq!(genres.0[0].tracks, [0, 1, 2, 3]);
+        assert_eq!(genres.0[1].tracks, [4]);
+    }
+
+    #[test]
+    fn several_per_track() {
+        let mut podcast = track(&["Rock"]);
+        podcast.kind = MediaKind::Podcast;
+        let tracks = [
+            track(&["Rock", "Jazz"]),
+            track(&["Jazz"]),
+            track(&[]),
+            podcast,
+        ];
+        let genres = Genres::new(&tracks, &GenreConfig::default());
+        assert_eq!(find(&genres, "Rock").1.tracks, [0]);
+        assert_eq!(find(&genres, "Jazz").1.tracks, [0, 1]);
+        assert_eq!(genres.0.len(), 2);
+    }
+
+    #[test]
+    fn hierarchy() {
+        let config = GenreConfig {
+            parents: HashMap::from([
+                ("Post-Rock".to_owned(), "Rock".to_owned()),
+                ("Math Rock".to_owned(), "Rock".to_owned()),
+                ("Rock".to_owned(), "Music".to_owned()),
+                // a loop is ignored
+                ("Music".to_owned(), "post rock".to_owned()),
+            ]),
+            ..Default::default()
+        };
+        let tracks = [
+            track(&["Post-Rock"]),
+            track(&["Math Rock", "Post-Rock"]),
+            track(&["Rock"]),
+            track(&["Jazz"]),
+        ];
+        let genres = Genres::new(&tracks, &config);
+        let (rock, _) = find(&genres, "Rock");
+        let (post_rock, _) = find(&genres, "Post-Rock");
+        let (math_rock, _) = find(&genres, "Math Rock");
+        let (music, untagged) = find(&genres, "Music");
+        assert!(untagged.tracks.is_empty());
+        assert_eq!(genres.0[post_rock].parent, Some(rock));
+        assert_eq!(genres.0[rock].children, [math_rock, post_rock]);
+        assert_eq!(genres.0[music].parent, None);
+
+        // counts roll up into the parents, each track once
+        assert_eq!(genres.all_tracks(post_rock), [0, 1]);
+        assert_eq!(genres.all_tracks(rock), [0, 1, 2]);
+        assert_eq!(genres.all_tracks(music), [0, 1, 2]);
+
+        let tree = genres
+            .tree()
+            .into_iter()
+            .map(|(x, depth)| (genres.0[x].name.as_str(), depth))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tree,
+            [
+                ("Jazz", 0),
+                ("Music", 0),
+                ("Rock", 1),
+                ("Math Rock", 2),
+                ("Post-Rock", 2),
+            ]
+        );
+    }
+}
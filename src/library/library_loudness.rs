@@ -0,0 +1,370 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use lofty::{
+    config::WriteOptions,
+    error::{ErrorKind, LoftyError},
+    file::{AudioFile, TaggedFileExt},
+    tag::{ItemKey, Tag},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Item,
+    audio::{Loudness, Measurement},
+    playback::ReplayGain,
+    settings, tag,
+};
+
+use super::Albums;
+
+/// The analysed loudness of a track, and of the album it was analysed with.
+/// Either is `None` where there was nothing loud enough to measure, which
+/// gets no gain.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrackLoudness {
+    pub track: Option<Loudness>,
+    pub album: Option<Loudness>,
+}
+
+impl TrackLoudness {
+    /// Work out the loudness of each track of an album, and of the album as
+    /// a whole. Tracks that are not part of an album are measured alone.
+    pub fn of_album(
+        measurements: &[(PathBuf, Measurement)],
+        is_album: bool,
+    ) -> Vec<(PathBuf, Self)> {
+        let album = is_album
+            .then(|| Loudness::of(&measurements.iter().map(|(_, x)| x).collect::<Vec<_>>()))
+            .flatten();
+        measurements
+            .iter()
+            .map(|(path, x)| {
+                let loudness = Self {
+                    track: Loudness::of(&[x]),
+                    album,
+                };
+                (path.clone(), loudness)
+            })
+            .collect()
+    }
+
+    pub fn replay_gain(&self) -> ReplayGain {
+        ReplayGain {
+            track_gain: self.track.map(|x| x.replay_gain() as f32),
+            track_peak: self.track.map(|x| x.true_peak as f32),
+            album_gain: self.album.map(|x| x.replay_gain() as f32),
+            album_peak: self.album.map(|x| x.true_peak as f32),
+        }
+    }
+
+    /// Write the results into the tags of a file, as ReplayGain 2.0. A file
+    /// with neither loudness is left alone.
+    pub fn write_tags(&self, path: &std::path::Path) -> lofty::error::Result<()> {
+        if self.track.is_none() && self.album.is_none() {
+            return Ok(());
+        }
+        let mut file = lofty::read_from_path(path)?;
+        if file.primary_tag().is_none() {
+            file.insert_tag(Tag::new(file.primary_tag_type()));
+        }
+        let Some(tag) = file.primary_tag_mut() else {
+            return Err(LoftyError::new(ErrorKind::UnsupportedTag));
+        };
+
+        let gain = |x: &Loudness| format!("{:.2} dB", x.replay_gain());
+        let peak = |x: &Loudness| format!("{:.6}", x.true_peak);
+        if let Some(track) = &self.track {
+            tag.insert_text(ItemKey::ReplayGainTrackGain, gain(track));
+            tag.insert_text(ItemKey::ReplayGainTrackPeak, peak(track));
+        }
+        if let Some(album) = &self.album {
+            tag.insert_text(ItemKey::ReplayGainAlbumGain, gain(album));
+            tag.insert_text(ItemKey::ReplayGainAlbumPeak, peak(album));
+        }
+        file.save_to_path(path, WriteOptions::default())
+    }
+}
+
+/// Loudness analysis results of every file, persisted in `loudness.toml`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoudnessResults {
+    #[serde(with = "settings::path_text::keys")]
+    tracks: HashMap<PathBuf, TrackLoudness>,
+}
+
+impl LoudnessResults {
+    pub fn load() -> Self {
+        settings::load("loudness.toml")
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        settings::save("loudness.toml", self)
+    }
+
+    pub fn get(&self, path: &std::path::Path) -> Option<TrackLoudness> {
+        self.tracks.get(path).copied()
+    }
+
+    pub fn insert(&mut self, path: PathBuf, loudness: TrackLoudness) {
+        self.tracks.insert(path, loudness);
+    }
+}
+
+/// A group of tracks to analyse together.
+#[derive(Debug, Clone)]
+pub struct LoudnessJob {
+    pub tracks: Vec<PathBuf>,
+    /// Whether the tracks make up an album, which gets an album loudness
+    pub is_album: bool,
+}
+
+/// Whether a track has ReplayGain tags, or R128 gains read as them.
+pub fn has_replay_gain(item: &Item) -> bool {
+    item.tags
+        .as_ref()
+        .is_some_and(|x| x.get_typed_tag::<tag::ReplayGainTrackGain>().is_some())
+}
+
+/// What needs analysing: every album with a track that has neither
+/// ReplayGain tags nor results, all of it so that the album loudness covers
+/// every track, then other such tracks alone.
+pub fn loudness_jobs(tracks: &[Item], albums: &Albums) -> Vec<LoudnessJob> {
+    let needs = |x: &Item| x.audio.is_some() && !has_replay_gain(x) && x.loudness.is_none();
+    let mut in_album = vec![false; tracks.len()];
+    let mut ret = vec![];
+    for album in albums.iter() {
+        for x in &album.tracks {
+            in_album[*x] = true;
+        }
+        if album.tracks.iter().any(|x| needs(&tracks[*x])) {
+            ret.push(LoudnessJob {
+                tracks: album
+                    .tracks
+                    .iter()
+                    .map(|x| tracks[*x].path.clone())
+                    .collect(),
+                is_album: true,
+            });
+        }
+    }
+    for (e, item) in tracks.iter().enumerate() {
+        if !in_album[e] && needs(item) {
+            ret.push(LoudnessJob {
+                tracks: vec![item.path.clone()],
+                is_album: false,
+            });
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audio::LoudnessMeter,
+        library::{
+            MediaKind,
+            test_util::{item, tags},
+        },
+        playback::StreamSpec,
+    };
+
+    /// A 997 Hz sine, mono at 48 kHz.
+    fn sine(amplitude: f32, secs: usize) -> Vec<f32> {
+        (0..48000 * secs)
+            .map(|x| amplitude * (x as f32 * 997.0 / 48000.0 * std::f32::consts::TAU).sin())
+            .collect()
+    }
+
+    fn measure(samples: &[f32]) -> Measurement {
+        let mut meter = LoudnessMeter::new(StreamSpec {
+            sample_rate: 48000,
+            channels: 1,
+        });
+        meter.process(samples);
+        meter.finish()
+    }
+
+    fn album_track(path: &str, album: &str) -> Item {
+        let mut tags = tags(path, "Artist");
+        let _ = tags.push_typed_tag(tag::AlbumTitle {
+            inner: album.to_owned(),
+            sort_order: None,
+        });
+        let _ = tags.push_typed_tag(tag::AlbumArtist {
+            inner: vec!["Artist".to_owned()],
+            sort_order: vec![],
+        });
+        item(path, tags, Some(60))
+    }
+
+    #[test]
+    fn album_loudness() {
+        let measurements = [
+            (PathBuf::from("/loud.flac"), measure(&sine(0.5, 5))),
+            (PathBuf::from("/quiet.flac"), measure(&sine(0.125, 5))),
+        ];
+        let album = TrackLoudness::of_album(&measurements, true);
+        let (loud, quiet) = (album[0].1, album[1].1);
+        assert_eq!(album[0].0, measurements[0].0);
+        let (loud_track, quiet_track) = (loud.track.unwrap(), quiet.track.unwrap());
+        // a quarter of the amplitude is 12 dB quieter
+        assert!((loud_track.integrated - quiet_track.integrated - 12.04).abs() < 0.1);
+        assert_eq!(loud.album, quiet.album);
+        let album_loudness = loud.album.unwrap();
+        assert!(album_loudness.integrated < loud_track.integrated);
+        assert!(album_loudness.integrated > quiet_track.integrated);
+        assert_eq!(album_loudness.true_peak, loud_track.true_peak);
+
+        let alone = TrackLoudness::of_album(&measurements, false);
+        assert_eq!(alone[0].1.track, loud.track);
+        assert!(alone.iter().all(|(_, x)| x.album.is_none()));
+
+        let gain = loud.replay_gain();
+        assert_eq!(gain.track_gain, Some(loud_track.replay_gain() as f32));
+        assert_eq!(gain.album_peak, Some(album_loudness.true_peak as f32));
+    }
+
+    #[test]
+    fn silence_gets_no_gain() {
+        let measurements = [
+            (PathBuf::from("/tone.flac"), measure(&sine(0.5, 5))),
+            (
+                PathBuf::from("/silence.flac"),
+                measure(&vec![0.0; 48000 * 5]),
+            ),
+            // shorter than a gating block
+            (
+                PathBuf::from("/short.flac"),
+                measure(&sine(0.5, 1)[..14400]),
+            ),
+        ];
+        let album = TrackLoudness::of_album(&measurements, true);
+        assert!(album[0].1.track.is_some());
+        for (_, x) in &album[1..] {
+            assert_eq!(x.track, None);
+            // the album gain still holds for them
+            assert_eq!(x.album, album[0].1.album);
+            let gain = x.replay_gain();
+            assert_eq!((gain.track_gain, gain.track_peak), (None, None));
+        }
+
+        // alone, there is nothing at all to apply or write
+        let alone = TrackLoudness::of_album(&measurements[1..], true);
+        assert!(
+            alone
+                .iter()
+                .all(|(_, x)| x.track.is_none() && x.album.is_none())
+        );
+        assert_eq!(alone[0].1.replay_gain(), ReplayGain::default());
+        // the file is not even opened
+        alone[0]
+            .1
+            .write_tags(std::path::Path::new("/nonexistent/silence.flac"))
+            .unwrap();
+    }
+
+    #[test]
+    fn jobs() {
+        let mut tracks = vec![
+            album_track("/a/1.flac", "A"),
+            album_track("/a/2.flac", "A"),
+            album_track("/b/1.flac", "B"),
+            album_track("/c/1.flac", "C"),
+            item("/single.flac", tags("Single", "Artist"), Some(60)),
+            item("/analysed.flac", tags("Analysed", "Artist"), Some(60)),
+            item("/missing.flac", tags("Missing", "Artist"), None),
+        ];
+        // only one track of A needs analysing, but the album is done whole
+        let _ = tracks[0]
+            .tags
+            .as_mut()
+            .unwrap()
+            .push_typed_tag(tag::ReplayGainTrackGain(-3.0));
+        let _ = tracks[2]
+            .tags
+            .as_mut()
+            .unwrap()
+            .push_typed_tag(tag::ReplayGainTrackGain(-3.0));
+        let loudness = Some(TrackLoudness {
+            track: Some(Loudness {
+                integrated: -20.0,
+                true_peak: 1.0,
+                range: 5.0,
+            }),
+            album: None,
+        });
+        tracks[3].loudness = loudness;
+        tracks[5].loudness = loudness;
+        // every music track is on some album, so take podcasts as loose tracks
+        tracks[4].kind = MediaKind::Podcast;
+        tracks[5].kind = MediaKind::Podcast;
+
+        let albums = Albums::new(&tracks);
+        let jobs = loudness_jobs(&tracks, &albums);
+        let jobs = jobs
+            .iter()
+            .map(|x| (x.tracks.clone(), x.is_album))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            jobs,
+            [
+                (vec![PathBuf::from("/a/1.flac"), "/a/2.flac".into()], true),
+                (vec![PathBuf::from("/single.flac")], false),
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_replay_gain() {
+        let path = std::env::temp_dir().join(format!("mioplays-{}-gain.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for x in sine(0.5, 1) {
+            writer
+                .write_sample((x * f32::from(i16::MAX)) as i16)
+                .unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let loudness = TrackLoudness {
+            track: Some(Loudness {
+                integrated: -12.5,
+                true_peak: 0.5,
+                range: 0.0,
+            }),
+            album: Some(Loudness {
+                integrated: -14.0,
+                true_peak: 0.75,
+                range: 3.0,
+            }),
+        };
+        loudness.write_tags(&path).unwrap();
+        let (tags, _) = smol::block_on(tag::decode_tags(path.clone(), false)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            tags.get_typed_tag::<tag::ReplayGainTrackGain>().unwrap().0,
+            -5.5
+        );
+        assert_eq!(
+            tags.get_typed_tag::<tag::ReplayGainTrackPeak>().unwrap().0,
+            0.5
+        );
+        assert_eq!(
+            tags.get_typed_tag::<tag::ReplayGainAlbumGain>().unwrap().0,
+            -4.0
+        );
+        assert_eq!(
+            tags.get_typed_tag::<tag::ReplayGainAlbumPeak>().unwrap().0,
+            0.75
+        );
+    }
+}
@@ -0,0 +1,590 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    path::{Component, Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Item, settings};
+
+use super::search_text;
+
+/// How far apart two durations can be, in seconds, for a track to still be
+/// taken as the same recording.
+const DURATION_SLACK: u64 = 3;
+
+/// A track in a playlist. Besides the path, enough is remembered about the
+/// track to find it again if it is moved or renamed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+    #[serde(with = "settings::path_text")]
+    pub path: PathBuf,
+    /// Track artists, joined with ", "
+    #[serde(default)]
+    pub artist: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// In whole seconds
+    #[serde(default)]
+    pub duration: Option<u64>,
+}
+
+impl PlaylistEntry {
+    pub fn of(item: &Item) -> Self {
+        let tags = item.tags.as_ref();
+        Self {
+            path: item.path.clone(),
+            artist: tags
+                .map(|x| x.track_artists().join(", "))
+                .filter(|x| !x.is_empty()),
+            title: tags.and_then(|x| x.display_title()),
+            duration: item.audio.as_ref().map(|x| x.duration.as_secs()),
+        }
+    }
+
+    /// The name to show when the track is not in the library.
+    pub fn display_title(&self) -> String {
+        self.title.clone().unwrap_or_else(|| {
+            self.path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        })
+    }
+}
+
+/// An ordered list of tracks put together by hand.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Playlist {
+    pub name: String,
+    #[serde(default)]
+    pub entries: Vec<PlaylistEntry>,
+}
+
+impl Playlist {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            entries: vec![],
+        }
+    }
+
+    pub fn remove(&mut self, pos: usize) -> Option<PlaylistEntry> {
+        (pos < self.entries.len()).then(|| self.entries.remove(pos))
+    }
+
+    /// Move the entry at `from` so that it ends up at `to`.
+    pub fn move_entry(&mut self, from: usize, to: usize) {
+        if from >= self.entries.len() {
+            return;
+        }
+        let entry = self.entries.remove(from);
+        let to = to.min(self.entries.len());
+        self.entries.insert(to, entry);
+    }
+
+    /// Indexes into `Tracks` for each entry, or `None` if the track is not in
+    /// the library.
+    pub fn resolve(&self, lookup: &TrackLookup) -> Vec<Option<usize>> {
+        self.entries.iter().map(|x| lookup.find(x)).collect()
+    }
+
+    /// Point entries whose file is gone at the track they most likely were,
+    /// going by their tags. Returns whether anything changed.
+    pub fn relink(&mut self, lookup: &TrackLookup) -> bool {
+        let mut changed = false;
+        for entry in &mut self.entries {
+            if lookup.by_path.contains_key(entry.path.as_path()) {
+                continue;
+            }
+            if let Some(x) = lookup.find(entry) {
+                entry.path = lookup.tracks[x].path.clone();
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Write the playlist as extended M3U. If `base` is set, paths under the
+    /// same root are written relative to it.
+    pub fn to_m3u(&self, base: Option<&Path>) -> String {
+        let mut ret = String::from("#EXTM3U\n");
+        let _ = writeln!(ret, "#PLAYLIST:{}", self.name);
+        for entry in &self.entries {
+            let duration = entry.duration.map_or(-1, |x| x as i64);
+            let title = entry.display_title();
+            match &entry.artist {
+                Some(artist) => {
+                    let _ = writeln!(ret, "#EXTINF:{duration},{artist} - {title}");
+                }
+                None => {
+                    let _ = writeln!(ret, "#EXTINF:{duration},{title}");
+                }
+            }
+            let path = base
+                .and_then(|x| relative_path(&entry.path, x))
+                .unwrap_or_else(|| entry.path.clone());
+            let _ = writeln!(ret, "{}", path.display());
+        }
+        ret
+    }
+
+    /// Read an M3U playlist, with or without the extended directives. Relative
+    /// paths are taken from `base`.
+    pub fn from_m3u(name: String, inp: &str, base: &Path) -> Self {
+        let mut ret = Self::new(name);
+        let mut info = None;
+        for line in inp.lines().map(str::trim) {
+            if let Some(x) = line.strip_prefix("#EXTINF:") {
+                info = Some(parse_extinf(x));
+            } else if let Some(x) = line.strip_prefix("#PLAYLIST:") {
+                ret.name = x.trim().to_owned();
+            } else if !line.is_empty() && !line.starts_with('#') {
+                let path = match line.strip_prefix("file://") {
+                    Some(x) => PathBuf::from(percent_decode(x)),
+                    None => PathBuf::from(line),
+                };
+                let (duration, artist, title) = info.take().unwrap_or_default();
+                ret.entries.push(PlaylistEntry {
+                    path: clean_path(&base.join(path)),
+                    artist,
+                    title,
+                    duration,
+                });
+            }
+        }
+        ret
+    }
+
+    /// Read an M3U file. The playlist is named after the file unless it says
+    /// otherwise.
+    pub fn import_m3u(path: &Path) -> std::io::Result<Self> {
+        // plain .m3u files are often in some legacy encoding
+        let inp = match String::from_utf8(std::fs::read(path)?) {
+            Ok(x) => x,
+            Err(err) => decode_cp1252(err.as_bytes()),
+        };
+        let inp = inp.strip_prefix('\u{feff}').unwrap_or(&inp);
+        let name = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let base = path.parent().unwrap_or(Path::new(""));
+        Ok(Self::from_m3u(name, inp, base))
+    }
+
+    /// Write an M3U8 file, with paths relative to it if `relative` is set.
+    pub fn export_m3u(&self, path: &Path, relative: bool) -> std::io::Result<()> {
+        let base = relative.then(|| path.parent().unwrap_or(Path::new("")));
+        std::fs::write(path, self.to_m3u(base))
+    }
+}
+
+/// The duration, artist and title of an `#EXTINF` line, eg.
+/// `123,Artist - Title`.
+fn parse_extinf(inp: &str) -> (Option<u64>, Option<String>, Option<String>) {
+    let (duration, text) = inp.split_once(',').unwrap_or((inp, ""));
+    // attributes may follow the duration
+    let duration = duration
+        .split_whitespace()
+        .next()
+        .and_then(|x| x.parse::<f64>().ok())
+        .filter(|x| *x >= 0.0)
+        .map(|x| x.round() as u64);
+    let text = text.trim();
+    let (artist, title) = match text.split_once(" - ") {
+        Some((artist, title)) => (Some(artist.trim()), title.trim()),
+        None => (None, text),
+    };
+    let some = |x: &str| (!x.is_empty()).then(|| x.to_owned());
+    (duration, artist.and_then(some), some(title))
+}
+
+/// Undo the %-escapes of a `file://` URL.
+fn percent_decode(inp: &str) -> String {
+    let mut ret = Vec::with_capacity(inp.len());
+    let mut bytes = inp.bytes();
+    while let Some(x) = bytes.next() {
+        if x == b'%' {
+            let hex = bytes.clone().take(2).collect::<Vec<_>>();
+            if let [a, b] = hex[..]
+                && a.is_ascii_hexdigit()
+                && b.is_ascii_hexdigit()
+            {
+                let x = u8::from_str_radix(std::str::from_utf8(&hex).unwrap(), 16).unwrap();
+                ret.push(x);
+                bytes.nth(1);
+                continue;
+            }
+        }
+        ret.push(x);
+    }
+    String::from_utf8_lossy(&ret).into_owned()
+}
+
+/// Decode Windows-1252, which Latin-1 is a subset of apart from the control
+/// characters at 0x80 to 0x9f, as browsers do.
+fn decode_cp1252(inp: &[u8]) -> String {
+    const HIGH: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž',
+        '\u{8f}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}',
+        'ž', 'Ÿ',
+    ];
+    inp.iter()
+        .map(|x| match x {
+            0x80..=0x9f => HIGH[usize::from(x - 0x80)],
+            _ => char::from(*x),
+        })
+        .collect()
+}
+
+/// `path` relative to the directory `base`, or `None` if they have nothing in
+/// common.
+fn relative_path(path: &Path, base: &Path) -> Option<PathBuf> {
+    let mut path = path.components().peekable();
+    let mut base = base.components().peekable();
+    let mut common = false;
+    while let (Some(a), Some(b)) = (path.peek(), base.peek()) {
+        if a != b {
+            break;
+        }
+        common = true;
+        path.next();
+        base.next();
+    }
+    if !common {
+        return None;
+    }
+    let mut ret = PathBuf::new();
+    for x in base {
+        match x {
+            Component::Normal(_) => ret.push(".."),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    ret.extend(path);
+    Some(ret)
+}
+
+/// Resolve the `.` and `..` in a path without looking at the file system, so
+/// that relative entries match the paths in the library.
+fn clean_path(path: &Path) -> PathBuf {
+    let mut ret = PathBuf::new();
+    for x in path.components() {
+        match x {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(ret.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                ret.pop();
+            }
+            _ => ret.push(x),
+        }
+    }
+    ret
+}
+
+/// Finds the library tracks for playlist entries, by path or failing that by
+/// tags.
+pub struct TrackLookup<'a> {
+    tracks: &'a [Item],
+    by_path: HashMap<&'a Path, usize>,
+    /// Normalised title to tracks
+    by_title: HashMap<String, Vec<usize>>,
+}
+
+impl<'a> TrackLookup<'a> {
+    pub fn new(tracks: &'a [Item]) -> Self {
+        let mut by_title: HashMap<_, Vec<_>> = HashMap::new();
+        for (e, item) in tracks.iter().enumerate() {
+            if let Some(title) = item.tags.as_ref().and_then(|x| x.display_title()) {
+                by_title.entry(search_text(&title)).or_default().push(e);
+            }
+        }
+        Self {
+            tracks,
+            by_path: tracks
+                .iter()
+                .enumerate()
+                .map(|(e, x)| (x.path.as_path(), e))
+                .collect(),
+            by_title,
+        }
+    }
+
+    /// The track an entry refers to. If the path is not in the library, this
+    /// is the track with the same title, and the same artist and a close
+    /// duration as far as the entry has them. Entries with only a title, and
+    /// ties between tracks, are not guessed at.
+    pub fn find(&self, entry: &PlaylistEntry) -> Option<usize> {
+        if let Some(x) = self.by_path.get(entry.path.as_path()) {
+            return Some(*x);
+        }
+        if entry.artist.is_none() && entry.duration.is_none() {
+            return None;
+        }
+        let title = search_text(entry.title.as_deref()?);
+        let artist = entry.artist.as_deref().map(search_text);
+        let mut candidates = self
+            .by_title
+            .get(&title)?
+            .iter()
+            .copied()
+            .filter(|x| {
+                let Some(artist) = &artist else {
+                    return true;
+                };
+                let artists = self.tracks[*x]
+                    .tags
+                    .as_ref()
+                    .map(|x| x.track_artists())
+                    .unwrap_or_default();
+                *artist == search_text(&artists.join(", "))
+                    || artists.iter().any(|x| *artist == search_text(x))
+            })
+            .filter_map(|x| {
+                let distance = match (entry.duration, self.tracks[x].audio.as_ref()) {
+                    (Some(a), Some(b)) => a.abs_diff(b.duration.as_secs()),
+                    (Some(_), None) => return None,
+                    (None, _) => 0,
+                };
+                (distance <= DURATION_SLACK).then_some((distance, x))
+            })
+            .collect::<Vec<_>>();
+        candidates.sort();
+        match candidates[..] {
+            [(a, _), (b, _), ..] if a == b => None,
+            [(_, x), ..] => Some(x),
+            [] => None,
+        }
+    }
+}
+
+/// All playlists, persisted in `playlists.toml`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Playlists {
+    pub playlists: Vec<Playlist>,
+}
+
+impl Playlists {
+    pub fn load() -> Self {
+        settings::load("playlists.toml")
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        settings::save("playlists.toml", self)
+    }
+
+    /// Relink every playlist, see `Playlist::relink`.
+    pub fn relink(&mut self, lookup: &TrackLookup) -> bool {
+        let mut changed = false;
+        for playlist in &mut self.playlists {
+            changed |= playlist.relink(lookup);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::test_util::{item, tags};
+
+    fn entry(
+        path: &str,
+        title: &str,
+        artist: Option<&str>,
+        duration: Option<u64>,
+    ) -> PlaylistEntry {
+        PlaylistEntry {
+            path: PathBuf::from(path),
+            artist: artist.map(str::to_owned),
+            title: Some(title.to_owned()),
+            duration,
+        }
+    }
+
+    #[test]
+    fn extinf() {
+        let some = |x: &str| Some(x.to_owned());
+        assert_eq!(
+            parse_extinf("123,Artist - Title"),
+            (Some(123), some("Artist"), some("Title"))
+        );
+        assert_eq!(parse_extinf("-1,Title"), (None, None, some("Title")));
+        assert_eq!(
+            parse_extinf("12.6 tvg-id=\"x\",A - B - C"),
+            (Some(13), some("A"), some("B - C"))
+        );
+        assert_eq!(parse_extinf(" 5 , Title "), (Some(5), None, some("Title")));
+        assert_eq!(parse_extinf("abc"), (None, None, None));
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(
+            percent_decode("/music/A%20B/%C3%A9t%C3%A9.mp3"),
+            "/music/A B/été.mp3"
+        );
+        assert_eq!(percent_decode("100%25"), "100%");
+        // broken escapes are kept as they are
+        assert_eq!(percent_decode("50% off%2"), "50% off%2");
+        assert_eq!(percent_decode("%zz%"), "%zz%");
+    }
+
+    #[test]
+    fn relative_paths() {
+        let relative = |a: &str, b: &str| relative_path(Path::new(a), Path::new(b));
+        assert_eq!(
+            relative("/music/a/b.flac", "/music/a"),
+            Some(PathBuf::from("b.flac"))
+        );
+        assert_eq!(
+            relative("/music/a/b.flac", "/music/lists"),
+            Some(PathBuf::from("../a/b.flac"))
+        );
+        assert_eq!(
+            relative("/music/a/b.flac", "/home/x/y"),
+            Some(PathBuf::from("../../../music/a/b.flac"))
+        );
+        assert_eq!(relative("music/a.flac", "lists"), None);
+        assert_eq!(
+            clean_path(Path::new("/music/lists/../a/./b.flac")),
+            PathBuf::from("/music/a/b.flac")
+        );
+        assert_eq!(
+            clean_path(Path::new("../a.flac")),
+            PathBuf::from("../a.flac")
+        );
+    }
+
+    #[test]
+    fn m3u_round_trip() {
+        let playlist = Playlist {
+            name: "Mix".to_owned(),
+            entries: vec![
+                entry("/music/a/one.flac", "One", Some("Artist"), Some(200)),
+                entry("/music/b/two.flac", "Two", None, None),
+                entry("/other/three.flac", "Three - Live", Some("A, B"), Some(61)),
+            ],
+        };
+        for base in ["/music/lists", "/music/a"] {
+            let text = playlist.to_m3u(Some(Path::new(base)));
+            let read = Playlist::from_m3u("file".to_owned(), &text, Path::new(base));
+            assert_eq!(read.name, "Mix");
+            assert_eq!(read.entries, playlist.entries, "{text}");
+        }
+        let text = playlist.to_m3u(None);
+        assert!(
+            text.contains("#EXTINF:-1,Two\n/music/b/two.flac\n"),
+            "{text}"
+        );
+        let read = Playlist::from_m3u("file".to_owned(), &text, Path::new("/elsewhere"));
+        assert_eq!(read.entries, playlist.entries);
+    }
+
+    #[test]
+    fn plain_m3u() {
+        let text = "one.mp3\r\n# a comment\r\nfile:///music/t%C3%BC.mp3\r\n";
+        let read = Playlist::from_m3u("name".to_owned(), text, Path::new("/lists"));
+        assert_eq!(read.name, "name");
+        let paths = read
+            .entries
+            .iter()
+            .map(|x| x.path.as_path())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [Path::new("/lists/one.mp3"), Path::new("/music/tü.mp3")]
+        );
+        assert_eq!(read.entries[0].display_title(), "one");
+    }
+
+    #[test]
+    fn legacy_encoding() {
+        assert_eq!(decode_cp1252(b"Caf\xe9 \x96 \x80\x9f"), "Café – €Ÿ");
+        assert_eq!(decode_cp1252(b"\x81\x8d"), "\u{81}\u{8d}");
+
+        let path = std::env::temp_dir().join(format!("mioplays-test-{}.m3u", std::process::id()));
+        std::fs::write(
+            &path,
+            b"#EXTINF:10,Beyonc\xe9 - D\xe9j\xe0 Vu\nd\xe9j\xe0.mp3\n",
+        )
+        .unwrap();
+        let read = Playlist::import_m3u(&path);
+        let _ = std::fs::remove_file(&path);
+        let entry = &read.unwrap().entries[0];
+        assert_eq!(entry.artist.as_deref(), Some("Beyoncé"));
+        assert_eq!(entry.title.as_deref(), Some("Déjà Vu"));
+        assert_eq!(entry.path, std::env::temp_dir().join("déjà.mp3"));
+    }
+
+    #[test]
+    fn relinks_only_clear_matches() {
+        let tracks = [
+            item("/music/new/one.flac", tags("One", "Artist"), Some(200)),
+            item("/music/new/two.flac", tags("Two", "Artist"), Some(100)),
+            item("/music/live/two.flac", tags("Two", "Artist"), Some(300)),
+            item("/music/new/three.flac", tags("Three", "Artist"), Some(100)),
+            item(
+                "/music/best-of/three.flac",
+                tags("Three", "Artist"),
+                Some(100),
+            ),
+            item("/music/new/four.flac", tags("Four", "Someone"), Some(100)),
+        ];
+        let lookup = TrackLookup::new(&tracks);
+        let find = |x| lookup.find(&x);
+        assert_eq!(find(entry("/music/new/one.flac", "x", None, None)), Some(0));
+        assert_eq!(
+            find(entry("/old/one.flac", "one", Some("ARTIST"), Some(202))),
+            Some(0)
+        );
+        assert_eq!(
+            find(entry("/old/one.flac", "One", Some("Artist"), Some(210))),
+            None
+        );
+        assert_eq!(
+            find(entry("/old/one.flac", "One", Some("Other"), Some(200))),
+            None
+        );
+        // a title alone is not enough
+        assert_eq!(find(entry("/old/one.flac", "One", None, None)), None);
+        assert_eq!(
+            find(entry("/old/four.flac", "Four", None, Some(100))),
+            Some(5)
+        );
+        // the closest duration wins
+        assert_eq!(
+            find(entry("/old/two.flac", "Two", Some("Artist"), Some(299))),
+            Some(2)
+        );
+        assert_eq!(
+            find(entry("/old/two.flac", "Two", Some("Artist"), None)),
+            None
+        );
+        // ties are not guessed at
+        assert_eq!(
+            find(entry("/old/three.flac", "Three", Some("Artist"), Some(100))),
+            None
+        );
+
+        let mut playlist = Playlist {
+            name: "x".to_owned(),
+            entries: vec![
+                entry("/old/one.flac", "One", Some("Artist"), Some(200)),
+                entry("/old/three.flac", "Three", Some("Artist"), Some(100)),
+            ],
+        };
+        assert!(playlist.relink(&lookup));
+        assert_eq!(playlist.entries[0].path, Path::new("/music/new/one.flac"));
+        assert_eq!(playlist.entries[1].path, Path::new("/old/three.flac"));
+        assert!(!playlist.relink(&lookup));
+        assert_eq!(playlist.resolve(&lookup), [Some(0), None]);
+    }
+}
@@ -0,0 +1,902 @@
+use std::{borrow::Cow, fmt, time::Duration};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{Item, tag};
+
+use super::{MediaKind, search_text};
+
+/// A filter over the library, parsed from a query such as
+/// `artist:"Boards of Canada" year:1995..2005 genre:ambient -live duration:>5m`.
+///
+/// Terms next to each other must all match. Terms can be combined with `OR`,
+/// negated with `NOT` or a leading '-', and grouped with parentheses. A term is
+/// either `field:value` or a bare value, which is looked for in the titles,
+/// artists, album, composer, work and genres.
+///
+/// Text values match if the field contains them, ignoring case and
+/// diacritics. `field:=value` only matches the whole field, and
+/// `field:/regex/` matches a (case insensitive) regular expression. Number,
+/// duration and date fields take `value`, `>value`, `>=value`, `<value`,
+/// `<=value`, or an inclusive range `low..high`. `added` and `lastplayed` take
+/// how long ago, so `added:<30d` is anything added in the last 30 days. Any
+/// other field is looked for in the custom tags, so `mood:chill` matches a
+/// MOOD tag.
+#[derive(Debug, Clone)]
+pub enum Query {
+    All(Vec<Query>),
+    Any(Vec<Query>),
+    Not(Box<Query>),
+    Term(Term),
+}
+
+/// A single `field:value` term.
+#[derive(Debug, Clone)]
+pub enum Term {
+    /// `None` for bare values, which match any of the common text fields
+    Text(Option<TextField>, TextMatch),
+    Number(NumberField, Compare<f64>),
+    Duration(Compare<Duration>),
+    /// How long ago something happened, compared as a duration
+    Age(AgeField, Compare<Duration>),
+    Date(Compare<Day>),
+    /// A custom tag, by its key
+    Custom(String, TextMatch),
+}
+
+/// A date as (year, month, day), for comparing.
+pub type Day = (i16, i8, i8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextField {
+    Title,
+    /// Track artists and album artists
+    Artist,
+    AlbumArtist,
+    Album,
+    Composer,
+    Work,
+    Genre,
+    Comment,
+    Path,
+    /// "music", "podcast" or "audiobook"
+    Kind,
+    Codec,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgeField {
+    /// When the file was first found by a scan
+    Added,
+    LastPlayed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberField {
+    Year,
+    Track,
+    Disc,
+    Rating,
+    Bitrate,
+    SampleRate,
+    BitDepth,
+    Channels,
+    Plays,
+}
+
+#[derive(Debug, Clone)]
+pub enum TextMatch {
+    /// Normalised by `search_text`
+    Contains(String),
+    /// Normalised by `search_text`
+    Exact(String),
+    Regex(Regex),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compare<T> {
+    Lt(T),
+    Le(T),
+    Gt(T),
+    Ge(T),
+    /// Inclusive on both ends. Equality is a range from the value to itself.
+    Range(T, T),
+}
+
+/// Why a query could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    /// Byte offset into the query
+    pub at: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at {})", self.message, self.at + 1)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl Query {
+    /// Parse a query. An empty query matches everything.
+    pub fn parse(inp: &str) -> Result<Self, QueryError> {
+        let tokens = lex(inp)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            at: 0,
+            end: inp.len(),
+        };
+        let ret = parser.parse_any()?;
+        match parser.tokens.get(parser.at) {
+            None => Ok(ret),
+            Some((at, _)) => Err(QueryError {
+                at: *at,
+                message: "unmatched ')'".to_owned(),
+            }),
+        }
+    }
+
+    pub fn matches(&self, item: &Item) -> bool {
+        match self {
+            Self::All(x) => x.iter().all(|x| x.matches(item)),
+            Self::Any(x) => x.iter().any(|x| x.matches(item)),
+            Self::Not(x) => !x.matches(item),
+            Self::Term(x) => x.matches(item),
+        }
+    }
+
+    /// Indexes of the tracks matching the query, in library order.
+    pub fn filter(&self, tracks: &[Item]) -> Vec<usize> {
+        tracks
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| self.matches(x))
+            .map(|(e, _)| e)
+            .collect()
+    }
+
+    /// Whether the query is only bare words, which the search index can
+    /// answer much faster.
+    pub fn is_plain(&self) -> bool {
+        match self {
+            Self::All(x) => x.iter().all(Self::is_plain),
+            Self::Term(Term::Text(None, TextMatch::Contains(_))) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Term {
+    pub fn matches(&self, item: &Item) -> bool {
+        let tags = item.tags.as_ref();
+        match self {
+            Self::Text(Some(field), matcher) => {
+                field.values(item).iter().any(|x| matcher.matches(x))
+            }
+            Self::Text(None, matcher) => [
+                TextField::Title,
+                TextField::Artist,
+                TextField::Album,
+                TextField::Composer,
+                TextField::Work,
+                TextField::Genre,
+            ]
+            .iter()
+            .any(|field| field.values(item).iter().any(|x| matcher.matches(x))),
+            Self::Number(field, cmp) => field.value(item).is_some_and(|x| cmp.matches(x)),
+            // to the second, as shown
+            Self::Duration(cmp) => item
+                .audio
+                .as_ref()
+                .is_some_and(|x| cmp.matches(Duration::from_secs(x.duration.as_secs()))),
+            Self::Age(field, cmp) => {
+                let at = match field {
+                    AgeField::Added => item.stats.added,
+                    AgeField::LastPlayed => item.stats.last_played,
+                };
+                let now = jiff::Timestamp::now().as_second();
+                at.is_some_and(|x| {
+                    cmp.matches(Duration::from_secs(now.saturating_sub(x).max(0) as u64))
+                })
+            }
+            Self::Date(cmp) => tags.and_then(|x| x.sort_date()).is_some_and(|x| {
+                cmp.matches((x.year(), x.month().unwrap_or(1), x.day().unwrap_or(1)))
+            }),
+            Self::Custom(key, matcher) => tags.is_some_and(|x| {
+                x.find_custom_tags(key).any(|x| match x.value() {
+                    tag::CustomValue::Text(x) => x.iter().any(|x| matcher.matches(x)),
+                    tag::CustomValue::Locator(x) => matcher.matches(x),
+                    tag::CustomValue::Binary(_) => false,
+                })
+            }),
+        }
+    }
+}
+
+impl TextField {
+    fn parse(inp: &str) -> Option<Self> {
+        Some(match inp {
+            "title" => Self::Title,
+            "artist" => Self::Artist,
+            "albumartist" => Self::AlbumArtist,
+            "album" => Self::Album,
+            "composer" => Self::Composer,
+            "work" => Self::Work,
+            "genre" => Self::Genre,
+            "comment" => Self::Comment,
+            "path" => Self::Path,
+            "kind" => Self::Kind,
+            "codec" | "format" => Self::Codec,
+            _ => return None,
+        })
+    }
+
+    fn values<'a>(&self, item: &'a Item) -> Vec<Cow<'a, str>> {
+        let tags = item.tags.as_ref();
+        let one = |x: Option<&'a str>| x.map(Cow::from).into_iter().collect();
+        let many = |x: Option<&'a [String]>| {
+            x.unwrap_or_default()
+                .iter()
+                .map(|x| Cow::from(x.as_str()))
+                .collect::<Vec<_>>()
+        };
+        match self {
+            Self::Title => tags
+                .and_then(|x| x.display_title())
+                .map(Cow::from)
+                .into_iter()
+                .collect(),
+            Self::Artist => {
+                let mut ret = many(tags.map(|x| x.track_artists()));
+                ret.extend(many(tags.map(|x| x.album_artists())));
+                ret
+            }
+            Self::AlbumArtist => many(tags.map(|x| x.album_artists())),
+            Self::Album => one(tags.and_then(|x| x.album_title())),
+            Self::Composer => one(tags.and_then(|x| x.composer())),
+            Self::Work => one(tags.and_then(|x| x.work())),
+            Self::Genre => many(tags.map(|x| x.genres())),
+            Self::Comment => one(tags.and_then(|x| x.localized_comment())),
+            Self::Path => vec![item.path.to_string_lossy()],
+            Self::Kind => vec![Cow::from(match item.kind {
+                MediaKind::Music => "music",
+                MediaKind::Podcast => "podcast",
+                MediaKind::Audiobook => "audiobook",
+            })],
+            Self::Codec => item
+                .audio
+                .as_ref()
+                .map(|x| Cow::from(x.codec.to_string()))
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+impl NumberField {
+    fn parse(inp: &str) -> Option<Self> {
+        Some(match inp {
+            "year" => Self::Year,
+            "track" => Self::Track,
+            "disc" | "disk" => Self::Disc,
+            "rating" | "stars" => Self::Rating,
+            "bitrate" => Self::Bitrate,
+            "samplerate" => Self::SampleRate,
+            "bitdepth" | "bits" => Self::BitDepth,
+            "channels" => Self::Channels,
+            "plays" | "playcount" => Self::Plays,
+            _ => return None,
+        })
+    }
+
+    fn value(&self, item: &Item) -> Option<f64> {
+        let tags = item.tags.as_ref();
+        let audio = item.audio.as_ref();
+        match self {
+            Self::Year => tags?.sort_date().map(|x| x.year().into()),
+            Self::Track => tags?.get_typed_tag::<tag::TrackPos>().map(|x| x.0.into()),
+            Self::Disc => tags?.get_typed_tag::<tag::DiscPos>().map(|x| x.0.into()),
+            // unrated tracks count as no stars
+            Self::Rating => Some(
+                tags.and_then(|x| x.get_typed_tag::<tag::Rating>())
+                    .map_or(0.0, |x| x.0.into()),
+            ),
+            Self::Bitrate => audio?.bitrate.map(f64::from),
+            Self::SampleRate => audio?.sample_rate.map(f64::from),
+            Self::BitDepth => audio?.bit_depth.map(f64::from),
+            Self::Channels => audio?.channels.map(f64::from),
+            Self::Plays => Some(item.stats.plays.into()),
+        }
+    }
+}
+
+impl TextMatch {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Self::Contains(x) => search_text(value).contains(x.as_str()),
+            Self::Exact(x) => search_text(value) == *x,
+            Self::Regex(x) => x.is_match(value),
+        }
+    }
+}
+
+impl<T: PartialOrd> Compare<T> {
+    fn matches(&self, value: T) -> bool {
+        match self {
+            Self::Lt(x) => value < *x,
+            Self::Le(x) => value <= *x,
+            Self::Gt(x) => value > *x,
+            Self::Ge(x) => value >= *x,
+            Self::Range(low, high) => *low <= value && value <= *high,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Or,
+    And,
+    Not,
+    /// The field, if any, and the value
+    Term(Option<String>, Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    /// Unquoted, and as such may have an operator in front
+    Plain(String),
+    Quoted(String),
+    Regex(String),
+}
+
+/// Split a query into tokens, along with their byte offsets.
+fn lex(inp: &str) -> Result<Vec<(usize, Token)>, QueryError> {
+    let mut ret = vec![];
+    let mut chars = inp.char_indices().peekable();
+    while let Some(&(at, c)) = chars.peek() {
+        match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                ret.push((at, Token::Open));
+            }
+            ')' => {
+                chars.next();
+                ret.push((at, Token::Close));
+            }
+            '-' => {
+                chars.next();
+                ret.push((at, Token::Not));
+            }
+            _ => {
+                // the field name, if there is one
+                let mut word = String::new();
+                let mut field = None;
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || c == ')' || c == '"' || (c == '/' && word.is_empty()) {
+                        break;
+                    }
+                    chars.next();
+                    if c == ':'
+                        && field.is_none()
+                        && word.starts_with(char::is_alphabetic)
+                        && word.chars().all(|x| x.is_alphanumeric() || x == '_')
+                    {
+                        field = Some(std::mem::take(&mut word).to_lowercase());
+                        break;
+                    }
+                    word.push(c);
+                }
+
+                let value = match chars.peek() {
+                    Some((_, '"')) if word.is_empty() => {
+                        chars.next();
+                        Value::Quoted(read_until(&mut chars, '"', at)?)
+                    }
+                    Some((_, '/')) if word.is_empty() => {
+                        chars.next();
+                        Value::Regex(read_until(&mut chars, '/', at)?)
+                    }
+                    _ => {
+                        while let Some(&(_, c)) = chars.peek() {
+                            if c.is_whitespace() || c == ')' {
+                                break;
+                            }
+                            chars.next();
+                            word.push(c);
+                        }
+                        Value::Plain(word)
+                    }
+                };
+                let token = match (&field, &value) {
+                    (None, Value::Plain(x)) if x == "OR" || x == "|" => Token::Or,
+                    (None, Value::Plain(x)) if x == "AND" || x == "&" => Token::And,
+                    (None, Value::Plain(x)) if x == "NOT" => Token::Not,
+                    _ => Token::Term(field, value),
+                };
+                ret.push((at, token));
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// Read up to a closing delimiter, which can be escaped with '\'. The escape
+/// is kept for regexes, so that `\/` still means '/' and `\d` still works.
+fn read_until(
+    chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>,
+    end: char,
+    start: usize,
+) -> Result<String, QueryError> {
+    let mut ret = String::new();
+    while let Some((_, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, x)) if x == end => ret.push(x),
+                Some((_, x)) => {
+                    if end == '/' {
+                        ret.push('\\');
+                    }
+                    ret.push(x);
+                }
+                None => break,
+            },
+            _ if c == end => return Ok(ret),
+            _ => ret.push(c),
+        }
+    }
+    Err(QueryError {
+        at: start,
+        message: format!("missing closing {end}"),
+    })
+}
+
+/// Recursive descent over the tokens. `OR` binds looser than `AND`, which
+/// binds looser than `NOT`.
+struct Parser<'a> {
+    tokens: &'a [(usize, Token)],
+    at: usize,
+    /// Offset of the end of the query, for errors at the end
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at).map(|(_, x)| x)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens.get(self.at).map_or(self.end, |(x, _)| *x)
+    }
+
+    fn parse_any(&mut self) -> Result<Query, QueryError> {
+        let mut ret = vec![self.parse_all()?];
+        while self.peek() == Some(&Token::Or) {
+            // `OR` needs a term on both sides
+            if is_empty(&ret[ret.len() - 1]) {
+                return Err(self.expected_term());
+            }
+            self.at += 1;
+            let next = self.parse_all()?;
+            if is_empty(&next) {
+                return Err(self.expected_term());
+            }
+            ret.push(next);
+        }
+        Ok(if ret.len() == 1 {
+            ret.pop().unwrap()
+        } else {
+            Query::Any(ret)
+        })
+    }
+
+    fn parse_all(&mut self) -> Result<Query, QueryError> {
+        let mut ret = vec![];
+        loop {
+            match self.peek() {
+                None | Some(Token::Close | Token::Or) => break,
+                // `AND` needs a term on both sides
+                Some(Token::And) if ret.is_empty() => return Err(self.expected_term()),
+                Some(Token::And) => {
+                    self.at += 1;
+                    ret.push(self.parse_not()?);
+                }
+                _ => ret.push(self.parse_not()?),
+            }
+        }
+        Ok(if ret.len() == 1 {
+            ret.pop().unwrap()
+        } else {
+            Query::All(ret)
+        })
+    }
+
+    fn parse_not(&mut self) -> Result<Query, QueryError> {
+        let at = self.offset();
+        match self.tokens.get(self.at).map(|(_, x)| x.clone()) {
+            Some(Token::Not) => {
+                self.at += 1;
+                Ok(Query::Not(Box::new(self.parse_not()?)))
+            }
+            Some(Token::Open) => {
+                self.at += 1;
+                let ret = self.parse_any()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(QueryError {
+                        at,
+                        message: "unmatched '('".to_owned(),
+                    });
+                }
+                if is_empty(&ret) {
+                    return Err(self.expected_term());
+                }
+                self.at += 1;
+                Ok(ret)
+            }
+            Some(Token::Term(field, value)) => {
+                self.at += 1;
+                parse_term(field.as_deref(), value)
+                    .map(Query::Term)
+                    .map_err(|message| QueryError { at, message })
+            }
+            _ => Err(self.expected_term()),
+        }
+    }
+
+    fn expected_term(&self) -> QueryError {
+        QueryError {
+            at: self.offset(),
+            message: "expected a term".to_owned(),
+        }
+    }
+}
+
+fn is_empty(query: &Query) -> bool {
+    matches!(query, Query::All(x) if x.is_empty())
+}
+
+fn parse_term(field: Option<&str>, value: Value) -> Result<Term, String> {
+    let text_match = |value: Value| -> Result<TextMatch, String> {
+        Ok(match value {
+            Value::Plain(x) => match x.strip_prefix('=') {
+                Some(x) => TextMatch::Exact(search_text(x)),
+                None => TextMatch::Contains(search_text(&x)),
+            },
+            Value::Quoted(x) => TextMatch::Contains(search_text(&x)),
+            Value::Regex(x) => TextMatch::Regex(
+                RegexBuilder::new(&x)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|x| format!("invalid regex: {x}"))?,
+            ),
+        })
+    };
+
+    let Some(field) = field else {
+        return Ok(Term::Text(None, text_match(value)?));
+    };
+    if let Some(field) = TextField::parse(field) {
+        return Ok(Term::Text(Some(field), text_match(value)?));
+    }
+    let text = match &value {
+        Value::Plain(x) | Value::Quoted(x) => Some(x.as_str()),
+        Value::Regex(_) => None,
+    };
+    let text = || text.ok_or_else(|| format!("{field} cannot be matched with a regex"));
+    if let Some(field) = NumberField::parse(field) {
+        let parse = |x: &str| x.parse::<f64>().ok().map(|x| (x, x));
+        return Ok(Term::Number(field, parse_compare(text()?, parse)?));
+    }
+    match field {
+        "duration" | "length" => {
+            let parse = |x: &str| parse_duration(x).map(|x| (x, x));
+            Ok(Term::Duration(parse_compare(text()?, parse)?))
+        }
+        "date" => Ok(Term::Date(parse_compare(text()?, parse_date_range)?)),
+        "added" | "lastplayed" => {
+            let field = match field {
+                "added" => AgeField::Added,
+                _ => AgeField::LastPlayed,
+            };
+            let parse = |x: &str| parse_duration(x).map(|x| (x, x));
+            Ok(Term::Age(field, parse_compare(text()?, parse)?))
+        }
+        _ => Ok(Term::Custom(field.to_owned(), text_match(value)?)),
+    }
+}
+
+/// Parse a comparison. `parse` returns the earliest and latest values a
+/// literal could mean, so that eg. `date:1995` covers the whole year.
+fn parse_compare<T: Copy>(
+    inp: &str,
+    parse: impl Fn(&str) -> Option<(T, T)>,
+) -> Result<Compare<T>, String> {
+    let parse = |x: &str| parse(x.trim()).ok_or_else(|| format!("invalid value '{x}'"));
+    Ok(if let Some(x) = inp.strip_prefix(">=") {
+        Compare::Ge(parse(x)?.0)
+    } else if let Some(x) = inp.strip_prefix("<=") {
+        Compare::Le(parse(x)?.1)
+    } else if let Some(x) = inp.strip_prefix('>') {
+        Compare::Gt(parse(x)?.1)
+    } else if let Some(x) = inp.strip_prefix('<') {
+        Compare::Lt(parse(x)?.0)
+    } else if let Some((low, high)) = inp.split_once("..") {
+        Compare::Range(parse(low)?.0, parse(high)?.1)
+    } else {
+        let (low, high) = parse(inp.strip_prefix('=').unwrap_or(inp))?;
+        Compare::Range(low, high)
+    })
+}
+
+/// Parse a duration: "90" or "90s" for seconds, "5m", "1h30m", "2w3d", or
+/// "3:30" and "1:02:03" as on a clock.
+fn parse_duration(inp: &str) -> Option<Duration> {
+    if inp.is_empty() {
+        return None;
+    }
+    if inp.contains(':') {
+        let secs = inp
+            .split(':')
+            .try_fold(0.0, |acc, x| Some(acc * 60.0 + x.parse::<f64>().ok()?))?;
+        return Duration::try_from_secs_f64(secs).ok();
+    }
+    let mut secs = 0.0;
+    let mut number = String::new();
+    for c in inp.chars() {
+        let unit = match c {
+            'w' => 7.0 * 86400.0,
+            'd' => 86400.0,
+            'h' => 3600.0,
+            'm' => 60.0,
+            's' => 1.0,
+            _ => {
+                number.push(c);
+                continue;
+            }
+        };
+        secs += std::mem::take(&mut number).parse::<f64>().ok()? * unit;
+    }
+    if !number.is_empty() {
+        secs += number.parse::<f64>().ok()?;
+    }
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// Parse a date into the first and last days it could mean.
+fn parse_date_range(inp: &str) -> Option<(Day, Day)> {
+    let date = tag::PartialDate::parse(inp)?;
+    let (month, day) = (date.month(), date.day());
+    Some((
+        (date.year(), month.unwrap_or(1), day.unwrap_or(1)),
+        (date.year(), month.unwrap_or(12), day.unwrap_or(31)),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::test_util::{self, tags};
+
+    fn item(title: &str, artist: &str, date: &str, secs: u64, rating: u8) -> Item {
+        let mut tags = tags(title, artist);
+        let _ = tags.push_typed_tag(tag::Genre(vec!["Ambient".to_owned()]));
+        if let Some(date) = tag::PartialDate::parse(date) {
+            let _ = tags.push_typed_tag(tag::ReleaseDate(date));
+        }
+        if rating > 0 {
+            let _ = tags.push_typed_tag(tag::Rating(rating));
+        }
+        test_util::item(&format!("/music/{artist}/{title}.flac"), tags, Some(secs))
+    }
+
+    fn titles(query: &str) -> Vec<usize> {
+        let tracks =
+            ["alpha", "beta gamma", "beta", "gamma"].map(|x| item(x, "Artist", "2000", 60, 0));
+        Query::parse(query).unwrap().filter(&tracks)
+    }
+
+    fn error(query: &str) -> QueryError {
+        Query::parse(query).unwrap_err()
+    }
+
+    #[test]
+    fn example() {
+        let tracks = [
+            item("Roygbiv", "Boards of Canada", "1998", 400, 5),
+            item("Roygbiv (Live)", "Boards of Canada", "1998", 400, 5),
+            item("Reach for the Dead", "Boards of Canada", "2013", 400, 5),
+            item("Aquarius", "Boards of Canada", "1998", 299, 5),
+            item("Olson", "Boards of Canada", "1998", 400, 3),
+            item("Xtal", "Aphex Twin", "1998", 400, 5),
+            item("Kid for Today", "Boards of Canada", "1995", 301, 4),
+        ];
+        let query = Query::parse(
+            r#"artist:"Boards of Canada" year:1995..2005 genre:ambient -live duration:>5m rating:>=4"#,
+        )
+        .unwrap();
+        assert_eq!(query.filter(&tracks), [0, 6]);
+        assert!(!query.is_plain());
+        assert!(Query::parse("boards canada").unwrap().is_plain());
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(titles(""), [0, 1, 2, 3]);
+        assert_eq!(titles("beta gamma"), [1]);
+        assert_eq!(titles("beta AND gamma"), [1]);
+        assert_eq!(titles("beta & gamma"), [1]);
+        // OR binds looser than AND
+        assert_eq!(titles("alpha OR beta gamma"), [0, 1]);
+        assert_eq!(titles("beta gamma | alpha"), [0, 1]);
+        assert_eq!(titles("(alpha OR beta) gamma"), [1]);
+        // NOT binds tighter than AND
+        assert_eq!(titles("NOT beta gamma"), [3]);
+        assert_eq!(titles("-beta"), [0, 3]);
+        assert_eq!(titles("-(beta OR alpha)"), [3]);
+        assert_eq!(titles("NOT NOT alpha"), [0]);
+    }
+
+    #[test]
+    fn text() {
+        let tracks = [
+            item("Björk's Song", "Björk", "2000", 60, 0),
+            item("Bjork", "Someone", "2000", 60, 0),
+        ];
+        let filter = |x| Query::parse(x).unwrap().filter(&tracks);
+        assert_eq!(filter("bjork"), [0, 1]);
+        assert_eq!(filter("artist:bjork"), [0]);
+        assert_eq!(filter("title:=bjork"), [1]);
+        assert_eq!(filter(r#"title:"bjork's song""#), [0]);
+        assert_eq!(filter("title:/^BJÖ/"), [0]);
+        assert_eq!(filter(r"path:/\/someone\//"), [1]);
+        assert_eq!(filter("kind:music codec:flac"), [0, 1]);
+        assert_eq!(filter("kind:podcast"), [] as [usize; 0]);
+    }
+
+    #[test]
+    fn custom_tags() {
+        let mut tracks = [
+            item("One", "Artist", "2000", 60, 0),
+            item("Two", "Artist", "2000", 60, 0),
+        ];
+        let tags = tracks[0].tags.as_mut().unwrap();
+        let _ = tags.push_custom_tag(
+            lofty::tag::TagType::VorbisComments,
+            "MOOD",
+            tag::CustomValue::Text(vec!["Chill".to_owned()]),
+        );
+        let filter = |x| Query::parse(x).unwrap().filter(&tracks);
+        assert_eq!(filter("mood:chill"), [0]);
+        assert_eq!(filter("MOOD:/ch.ll/"), [0]);
+        assert_eq!(filter("-mood:chill"), [1]);
+        assert_eq!(filter("mood:angry"), [] as [usize; 0]);
+    }
+
+    #[test]
+    fn ranges() {
+        let tracks = [1994, 1995, 2000, 2005, 2006].map(|x| item("x", "y", &x.to_string(), 60, 0));
+        let filter = |x| Query::parse(x).unwrap().filter(&tracks);
+        assert_eq!(filter("year:1995..2005"), [1, 2, 3]);
+        assert_eq!(filter("year:2000"), [2]);
+        assert_eq!(filter("year:=2000"), [2]);
+        assert_eq!(filter("year:>2000"), [3, 4]);
+        assert_eq!(filter("year:>=2000"), [2, 3, 4]);
+        assert_eq!(filter("year:<1995"), [0]);
+        assert_eq!(filter("year:<=1995"), [0, 1]);
+        assert_eq!(
+            filter("rating:0 plays:<1 channels:2 bitrate:>=900"),
+            [0, 1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn durations() {
+        let secs = |x| parse_duration(x).map(|x| x.as_secs_f64());
+        assert_eq!(secs("90"), Some(90.0));
+        assert_eq!(secs("90s"), Some(90.0));
+        assert_eq!(secs("5m"), Some(300.0));
+        assert_eq!(secs("1.5m"), Some(90.0));
+        assert_eq!(secs("1h30m"), Some(5400.0));
+        assert_eq!(secs("2w3d"), Some(17.0 * 86400.0));
+        assert_eq!(secs("3:30"), Some(210.0));
+        assert_eq!(secs("1:02:03"), Some(3723.0));
+        for garbage in ["", "m", "5x", "3:", ":", "-5m"] {
+            assert_eq!(secs(garbage), None, "{garbage}");
+        }
+
+        let tracks = [179, 180, 210, 300, 301].map(|x| item("x", "y", "2000", x, 0));
+        let filter = |x| Query::parse(x).unwrap().filter(&tracks);
+        assert_eq!(filter("duration:3m..3:30"), [1, 2]);
+        assert_eq!(filter("length:>5m"), [4]);
+        assert_eq!(filter("duration:<=300"), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(
+            parse_date_range("1997"),
+            Some(((1997, 1, 1), (1997, 12, 31)))
+        );
+        assert_eq!(
+            parse_date_range("1997-05"),
+            Some(((1997, 5, 1), (1997, 5, 31)))
+        );
+        assert_eq!(
+            parse_date_range("1997-05-21"),
+            Some(((1997, 5, 21), (1997, 5, 21)))
+        );
+
+        let tracks =
+            ["1996-12-31", "1997", "1997-05-21", "1998-01"].map(|x| item("x", "y", x, 60, 0));
+        let filter = |x| Query::parse(x).unwrap().filter(&tracks);
+        // a year is taken as its first day
+        assert_eq!(filter("date:1997"), [1, 2]);
+        assert_eq!(filter("date:>=1997-05"), [2, 3]);
+        assert_eq!(filter("date:>1997-05"), [3]);
+        assert_eq!(filter("date:<1997-05-21"), [0, 1]);
+        assert_eq!(filter("date:1997-01..1997-05"), [1, 2]);
+    }
+
+    #[test]
+    fn ages() {
+        let now = jiff::Timestamp::now().as_second();
+        let mut tracks = [1, 10, 60].map(|x| {
+            let mut ret = item("x", "y", "2000", 60, 0);
+            ret.stats.added = Some(now - x * 86400);
+            ret
+        });
+        tracks[0].stats.last_played = Some(now - 3600);
+        let filter = |x| Query::parse(x).unwrap().filter(&tracks);
+        assert_eq!(filter("added:<30d"), [0, 1]);
+        assert_eq!(filter("added:>4w"), [2]);
+        assert_eq!(filter("lastplayed:<1d"), [0]);
+        // never played does not count as long ago
+        assert_eq!(filter("lastplayed:>1d"), [] as [usize; 0]);
+    }
+
+    #[test]
+    fn errors() {
+        let at = |x| error(x).at;
+        let message = |x| error(x).message;
+        assert_eq!(message("duration:>"), "invalid value ''");
+        assert_eq!(message("year:abc"), "invalid value 'abc'");
+        assert_eq!(message("year:1995..x"), "invalid value 'x'");
+        assert_eq!(message("date:soon"), "invalid value 'soon'");
+        assert_eq!(message("year:/19/"), "year cannot be matched with a regex");
+        assert!(message("title:/(/").starts_with("invalid regex"));
+        assert_eq!(
+            error(r#"a artist:"Boards"#),
+            QueryError {
+                at: 2,
+                message: "missing closing \"".to_owned(),
+            }
+        );
+        assert_eq!(message("title:/abc"), "missing closing /");
+        assert_eq!(
+            (at("a (b"), message("a (b")),
+            (2, "unmatched '('".to_owned())
+        );
+        assert_eq!(
+            (at("a b)"), message("a b)")),
+            (3, "unmatched ')'".to_owned())
+        );
+        for (query, offset) in [
+            ("-", 1),
+            ("a OR", 4),
+            ("OR a", 0),
+            ("a OR OR b", 5),
+            ("a AND", 5),
+            ("AND a", 0),
+            ("a ()", 3),
+        ] {
+            assert_eq!(message(query), "expected a term", "{query}");
+            assert_eq!(at(query), offset, "{query}");
+        }
+    }
+}
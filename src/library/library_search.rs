@@ -0,0 +1,395 @@
+use std::{collections::HashMap, ops::Range};
+
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+use crate::Item;
+
+use super::{Albums, Artists, MediaKind};
+
+/// What a search result points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SearchHit {
+    /// Index into `Artists`
+    Artist(usize),
+    /// Index into `Albums`
+    Album(usize),
+    /// Index into `Tracks`
+    Track(usize),
+}
+
+/// An in-memory index for searching the library by word prefixes.
+///
+/// Every document (artist, album or track) is split into normalised words,
+/// which are stored once in a sorted vocabulary. A query word then matches a
+/// contiguous range of the vocabulary, so checking whether a document matches
+/// is a matter of comparing integers.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    /// Every word in the library, sorted and without duplicates
+    vocab: Vec<Box<str>>,
+    /// Indexes into `docs` for each word of `vocab`, sorted
+    postings: Vec<Vec<u32>>,
+    docs: Vec<SearchHit>,
+    /// Indexes into `vocab` for each document
+    doc_words: Vec<Box<[u32]>>,
+}
+
+/// The documents matching a query, kept around so that the next query can
+/// narrow them down rather than starting over.
+#[derive(Debug, Default)]
+pub struct SearchResults {
+    words: Vec<String>,
+    /// Indexes into `docs`, in document order
+    docs: Vec<u32>,
+}
+
+impl SearchIndex {
+    pub fn new(tracks: &[Item], albums: &Albums, artists: &Artists) -> Self {
+        let mut docs = vec![];
+        let mut texts: Vec<Vec<&str>> = vec![];
+        for (e, artist) in artists.iter().enumerate() {
+            docs.push(SearchHit::Artist(e));
+            texts.push(vec![&artist.name, &artist.sort_name]);
+        }
+        for (e, album) in albums.iter().enumerate() {
+            docs.push(SearchHit::Album(e));
+            let mut text = vec![album.title.as_str()];
+            text.extend(album.artists.iter().map(String::as_str));
+            texts.push(text);
+        }
+        for (e, item) in tracks.iter().enumerate() {
+            if item.kind != MediaKind::Music {
+                continue;
+            }
+            let Some(tags) = item.tags.as_ref() else {
+                continue;
+            };
+            let mut text = vec![];
+            text.extend(tags.track_title());
+            text.extend(tags.track_artists().iter().map(String::as_str));
+            text.extend(tags.album_title());
+            text.extend(tags.album_artists().iter().map(String::as_str));
+            text.extend(tags.composer());
+            text.extend(tags.work());
+            text.extend(tags.genres().iter().map(String::as_str));
+            docs.push(SearchHit::Track(e));
+            texts.push(text);
+        }
+
+        // build the vocabulary, then point everything into it
+        let doc_words = texts
+            .iter()
+            .map(|x| x.iter().flat_map(|x| search_words(x)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let mut vocab = doc_words.iter().flatten().cloned().collect::<Vec<_>>();
+        vocab.sort_unstable();
+        vocab.dedup();
+        let lookup = vocab
+            .iter()
+            .enumerate()
+            .map(|(e, x)| (x.as_str(), e as u32))
+            .collect::<HashMap<_, _>>();
+
+        let mut postings = vec![vec![]; vocab.len()];
+        let doc_words = doc_words
+            .iter()
+            .enumerate()
+            .map(|(doc, words)| {
+                let mut ids = words.iter().map(|x| lookup[x.as_str()]).collect::<Vec<_>>();
+                ids.sort_unstable();
+                ids.dedup();
+                for id in &ids {
+                    postings[*id as usize].push(doc as u32);
+                }
+                ids.into_boxed_slice()
+            })
+            .collect();
+
+        Self {
+            vocab: vocab.into_iter().map(String::into_boxed_str).collect(),
+            postings,
+            docs,
+            doc_words,
+        }
+    }
+
+    /// Find the documents with a word starting with each word of the query.
+    ///
+    /// If the query only adds to the `previous` one, as when typing, the
+    /// previous results are narrowed down instead of searching everything.
+    pub fn search(&self, query: &str, previous: Option<&SearchResults>) -> SearchResults {
+        let words = search_words(query).collect::<Vec<_>>();
+        let ranges = words
+            .iter()
+            .map(|x| self.prefix_range(x))
+            .collect::<Vec<_>>();
+        if words.is_empty() || ranges.iter().any(|x| x.is_empty()) {
+            return SearchResults {
+                words,
+                docs: vec![],
+            };
+        }
+
+        let candidates = match previous {
+            Some(previous) if refines(&previous.words, &words) => previous.docs.clone(),
+            // start from the narrowest word
+            _ => {
+                let range = ranges
+                    .iter()
+                    .min_by_key(|x| {
+                        self.postings[x.start as usize..x.end as usize]
+                            .iter()
+                            .map(Vec::len)
+                            .sum::<usize>()
+                    })
+                    .unwrap();
+                let mut ret = self.postings[range.start as usize..range.end as usize]
+                    .iter()
+                    .flatten()
+                    .copied()
+                    .collect::<Vec<_>>();
+                ret.sort_unstable();
+                ret.dedup();
+                ret
+            }
+        };
+        let docs = candidates
+            .into_iter()
+            .filter(|x| {
+                let doc = &self.doc_words[*x as usize];
+                ranges
+                    .iter()
+                    .all(|range| doc.iter().any(|x| range.contains(x)))
+            })
+            .collect();
+
+        SearchResults { words, docs }
+    }
+
+    /// The range of `vocab` starting with `prefix`.
+    fn prefix_range(&self, prefix: &str) -> Range<u32> {
+        let start = self.vocab.partition_point(|x| &**x < prefix);
+        let len = self.vocab[start..].partition_point(|x| x.starts_with(prefix));
+        start as u32..(start + len) as u32
+    }
+
+    /// The hits of some results, in document order: artists, then albums,
+    /// then tracks.
+    pub fn hits<'a>(&'a self, results: &'a SearchResults) -> impl Iterator<Item = SearchHit> + 'a {
+        results.docs.iter().map(|x| self.docs[*x as usize])
+    }
+}
+
+/// Whether every document matching `new` would also match `old`, ie. `new` has
+/// at least as many words, and each of them starts with the word of `old`. An
+/// empty query matches nothing, so nothing refines it.
+fn refines(old: &[String], new: &[String]) -> bool {
+    !old.is_empty()
+        && new.len() >= old.len()
+        && old.iter().zip(new).all(|(old, new)| new.starts_with(old))
+}
+
+/// Split text into words for searching. Words are lowercased and stripped of
+/// diacritics, so that "Björk" is found by "bjork".
+pub fn search_words(inp: &str) -> impl Iterator<Item = String> + '_ {
+    inp.split(|x: char| !x.is_alphanumeric() && !is_combining_mark(x))
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            let mut ret = String::with_capacity(x.len());
+            for c in x.nfd().filter(|x| !is_combining_mark(*x)) {
+                // letters that do not decompose
+                match c {
+                    'ø' | 'Ø' => ret.push('o'),
+                    'æ' | 'Æ' => ret.push_str("ae"),
+                    'œ' | 'Œ' => ret.push_str("oe"),
+                    'ł' | 'Ł' => ret.push('l'),
+                    'đ' | 'Đ' => ret.push('d'),
+                    'ß' => ret.push_str("ss"),
+                    c => ret.extend(c.to_lowercase()),
+                }
+            }
+            ret
+        })
+        .filter(|x| !x.is_empty())
+}
+
+/// Text normalised as by `search_words`, joined with spaces, for comparing
+/// ignoring case, accents and punctuation.
+pub fn search_text(inp: &str) -> String {
+    search_words(inp).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{
+        library::test_util::{self, tags},
+        tag,
+    };
+
+    fn item(title: &str, artist: &str, album: &str) -> Item {
+        let mut tags = tags(title, artist);
+        let _ = tags.push_typed_tag(tag::AlbumTitle {
+            inner: album.to_owned(),
+            sort_order: None,
+        });
+        let _ = tags.push_typed_tag(tag::AlbumArtist {
+            inner: vec![artist.to_owned()],
+            sort_order: vec![],
+        });
+        test_util::item(&format!("/music/{artist}/{album}/{title}.flac"), tags, None)
+    }
+
+    fn index(tracks: &[Item]) -> SearchIndex {
+        let albums = Albums::new(tracks);
+        let artists = Artists::new(tracks, &albums);
+        SearchIndex::new(tracks, &albums, &artists)
+    }
+
+    fn tracks_of(index: &SearchIndex, results: &SearchResults) -> Vec<usize> {
+        index
+            .hits(results)
+            .filter_map(|x| match x {
+                SearchHit::Track(x) => Some(x),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn folds_diacritics() {
+        let words = |x| search_words(x).collect::<Vec<_>>();
+        assert_eq!(words("Björk"), ["bjork"]);
+        assert_eq!(
+            words("Sigur Rós – Ágætis byrjun"),
+            ["sigur", "ros", "agaetis", "byrjun"]
+        );
+        assert_eq!(
+            words("Øresund Straße, Łódź"),
+            ["oresund", "strasse", "lodz"]
+        );
+        assert_eq!(words("CAFÉ  del-Mar!"), ["cafe", "del", "mar"]);
+        // decomposed input folds the same as composed input
+        assert_eq!(words("Cafe\u{301}"), ["cafe"]);
+
+        let tracks = [
+            item("Jóga", "Björk", "Homogenic"),
+            item("Hoppípolla", "Sigur Rós", "Takk..."),
+        ];
+        let index = index(&tracks);
+        let results = index.search("bjork jog", None);
+        assert_eq!(tracks_of(&index, &results), [0]);
+        let results = index.search("HOPPÍ", None);
+        assert_eq!(tracks_of(&index, &results), [1]);
+    }
+
+    #[test]
+    fn refines_previous_results() {
+        let words = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        assert!(refines(&words(&["bo"]), &words(&["boa"])));
+        assert!(refines(&words(&["boa"]), &words(&["boa", "c"])));
+        assert!(!refines(&words(&["boa"]), &words(&["bo"])));
+        assert!(!refines(&words(&["boa"]), &words(&["bca"])));
+        assert!(!refines(&[], &words(&["boa"])));
+
+        let tracks = [
+            item(
+                "Roygbiv",
+                "Boards of Canada",
+                "Music Has the Right to Children",
+            ),
+            item(
+                "Dayvan Cowboy",
+                "Boards of Canada",
+                "The Campfire Headphase",
+            ),
+            item("Bohemian Rhapsody", "Queen", "A Night at the Opera"),
+            item(
+                "Born Slippy",
+                "Underworld",
+                "Second Toughest in the Infants",
+            ),
+        ];
+        let index = index(&tracks);
+        let mut previous: Option<SearchResults> = None;
+        for query in ["b", "bo", "boa", "boards c", "boards cow", "boards", "bor"] {
+            let typed = index.search(query, previous.as_ref());
+            let fresh = index.search(query, None);
+            assert_eq!(typed.docs, fresh.docs, "{query}");
+            previous = Some(typed);
+        }
+        let results = index.search("boards cow", None);
+        assert_eq!(tracks_of(&index, &results), [1]);
+        let results = index.search("bor", None);
+        assert_eq!(tracks_of(&index, &results), [3]);
+    }
+
+    /// A library made up of a few thousand words.
+    fn large_library(len: usize) -> Vec<Item> {
+        let syllables = ["ka", "lo", "mi", "ne", "ru", "sa", "to", "vi", "ze", "qu"];
+        let word = |mut x: usize| {
+            let mut ret = String::new();
+            for _ in 0..4 {
+                ret.push_str(syllables[x % syllables.len()]);
+                x /= syllables.len();
+            }
+            ret
+        };
+        (0..len)
+            .map(|x| {
+                let title = format!("{} {}", word(x * 7919 % 10_000), word(x * 104_729 % 10_000));
+                let artist = word(x / 12 % 2000 + 3);
+                let album = format!("{} {}", word(x / 12 % 5000), word(x % 97));
+                item(&title, &artist, &album)
+            })
+            .collect()
+    }
+
+    const TYPED_QUERIES: [&str; 8] = [
+        "k",
+        "ka",
+        "kalo",
+        "kalomi",
+        "kalomi ne",
+        "lo mi",
+        "zequ",
+        "x",
+    ];
+
+    #[test]
+    fn refines_on_a_large_library() {
+        let tracks = large_library(10_000);
+        let index = index(&tracks);
+        let mut previous: Option<SearchResults> = None;
+        for query in TYPED_QUERIES {
+            let typed = index.search(query, previous.as_ref());
+            let fresh = index.search(query, None);
+            assert_eq!(typed.docs, fresh.docs, "{query}");
+            previous = Some(typed);
+        }
+        assert!(!index.search("kalo", None).docs.is_empty());
+        assert!(index.search("x", None).docs.is_empty());
+    }
+
+    /// Timing depends on the machine, so this is run by hand, in release:
+    /// `cargo test --release -- --ignored fast_on_a_large_library`.
+    #[test]
+    #[ignore]
+    fn fast_on_a_large_library() {
+        let tracks = large_library(100_000);
+        let index = index(&tracks);
+        let start = Instant::now();
+        let mut previous: Option<SearchResults> = None;
+        for query in TYPED_QUERIES {
+            previous = Some(index.search(query, previous.as_ref()));
+        }
+        for query in TYPED_QUERIES {
+            index.search(query, None);
+        }
+        let each = start.elapsed() / (2 * TYPED_QUERIES.len() as u32);
+        // the target is a few ms
+        assert!(each.as_millis() < 5, "{each:?} per search");
+    }
+}
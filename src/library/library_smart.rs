@@ -0,0 +1,302 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Item, settings, tag};
+
+use super::{Query, QueryError, sort_name};
+
+/// What a smart playlist can be sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortField {
+    Added,
+    LastPlayed,
+    Plays,
+    /// Release date
+    Date,
+    Title,
+    Artist,
+    Album,
+    Rating,
+    Duration,
+}
+
+/// A playlist of whatever tracks match a query, re-evaluated whenever the
+/// library changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmartPlaylist {
+    pub name: String,
+    /// See `Query` for the syntax
+    pub query: String,
+    /// Library order if not set
+    #[serde(default)]
+    pub sort: Option<SortField>,
+    #[serde(default)]
+    pub descending: bool,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl SmartPlaylist {
+    /// Indexes into `Tracks` of the tracks in the playlist, in order.
+    pub fn evaluate(&self, tracks: &[Item]) -> Result<Vec<usize>, QueryError> {
+        let mut ret = Query::parse(&self.query)?.filter(tracks);
+        if let Some(sort) = self.sort {
+            let mut keyed = ret
+                .into_iter()
+                .map(|x| (sort.key(&tracks[x]), x))
+                .collect::<Vec<_>>();
+            // tracks without a value go last either way, and the sort is
+            // stable so that ties stay in library order
+            keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+                (Some(a), Some(b)) if self.descending => b.cmp(a),
+                (Some(a), Some(b)) => a.cmp(b),
+                (a, b) => b.is_some().cmp(&a.is_some()),
+            });
+            ret = keyed.into_iter().map(|(_, x)| x).collect();
+        }
+        if let Some(limit) = self.limit {
+            ret.truncate(limit);
+        }
+        Ok(ret)
+    }
+
+    /// How the playlist is sorted and cut off, eg. "by plays, descending,
+    /// first 50". Empty for library order without a limit.
+    pub fn display_order(&self) -> String {
+        let mut ret = vec![];
+        if let Some(sort) = self.sort {
+            ret.push(format!("by {sort}"));
+            if self.descending {
+                ret.push("descending".to_owned());
+            }
+        }
+        if let Some(limit) = self.limit {
+            ret.push(format!("first {limit}"));
+        }
+        ret.join(", ")
+    }
+}
+
+/// The value a track is sorted by.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    Number(i64),
+    /// Lowercased
+    Text(String),
+}
+
+impl SortField {
+    /// Every field, in the order they are offered in the UI
+    pub const ALL: [Self; 9] = [
+        Self::Added,
+        Self::LastPlayed,
+        Self::Plays,
+        Self::Date,
+        Self::Title,
+        Self::Artist,
+        Self::Album,
+        Self::Rating,
+        Self::Duration,
+    ];
+
+    fn key(self, item: &Item) -> Option<SortKey> {
+        let tags = item.tags.as_ref();
+        let text = |x: Option<&str>| x.map(|x| SortKey::Text(x.to_lowercase()));
+        let number = match self {
+            Self::Title => {
+                let title = tags?.track_title_sort_order().map(ToOwned::to_owned);
+                return text(title.or_else(|| tags?.display_title()).as_deref());
+            }
+            Self::Artist => {
+                let (artists, sort_orders) =
+                    (tags?.track_artists(), tags?.track_artist_sort_orders());
+                let artist = artists.first()?;
+                let sort_order =
+                    (artists.len() == sort_orders.len()).then(|| sort_orders[0].as_str());
+                return text(Some(&sort_name(artist, sort_order)));
+            }
+            Self::Album => return text(tags?.album_sort_title()),
+            Self::Added => item.stats.added,
+            Self::LastPlayed => item.stats.last_played,
+            Self::Plays => Some(item.stats.plays.into()),
+            Self::Date => tags?.sort_date().map(|x| {
+                let (month, day) = (x.month().unwrap_or(0), x.day().unwrap_or(0));
+                i64::from(x.year()) * 10000 + i64::from(month) * 100 + i64::from(day)
+            }),
+            Self::Rating => tags?.get_typed_tag::<tag::Rating>().map(|x| x.0.into()),
+            Self::Duration => item.audio.as_ref().map(|x| x.duration.as_millis() as i64),
+        };
+        number.map(SortKey::Number)
+    }
+}
+
+impl fmt::Display for SortField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Added => "date added",
+            Self::LastPlayed => "last played",
+            Self::Plays => "plays",
+            Self::Date => "release date",
+            Self::Title => "title",
+            Self::Artist => "artist",
+            Self::Album => "album",
+            Self::Rating => "rating",
+            Self::Duration => "duration",
+        })
+    }
+}
+
+/// All smart playlists, persisted in `smart_playlists.toml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SmartPlaylists {
+    pub playlists: Vec<SmartPlaylist>,
+}
+
+impl Default for SmartPlaylists {
+    fn default() -> Self {
+        Self {
+            playlists: vec![
+                SmartPlaylist {
+                    name: "Recently added favourites".to_owned(),
+                    query: "added:<30d plays:>0".to_owned(),
+                    sort: Some(SortField::Plays),
+                    descending: true,
+                    limit: Some(50),
+                },
+                SmartPlaylist {
+                    name: "Never played".to_owned(),
+                    query: "plays:0 kind:music".to_owned(),
+                    sort: Some(SortField::Added),
+                    descending: true,
+                    limit: Some(200),
+                },
+            ],
+        }
+    }
+}
+
+impl SmartPlaylists {
+    pub fn load() -> Self {
+        settings::load("smart_playlists.toml")
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        settings::save("smart_playlists.toml", self)
+    }
+
+    /// Evaluate every playlist, in order.
+    pub fn evaluate(&self, tracks: &[Item]) -> Vec<Result<Vec<usize>, QueryError>> {
+        self.playlists.iter().map(|x| x.evaluate(tracks)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::test_util::{item, tags};
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    /// A track added `days` ago and played `plays` times.
+    fn track(title: &str, days: i64, plays: u32) -> Item {
+        let mut ret = item(
+            &format!("/music/{title}.flac"),
+            tags(title, "Artist"),
+            Some(60),
+        );
+        ret.stats.added = Some(jiff::Timestamp::now().as_second() - days * DAY);
+        ret.stats.plays = plays;
+        ret
+    }
+
+    fn playlist(query: &str, sort: Option<SortField>, descending: bool) -> SmartPlaylist {
+        SmartPlaylist {
+            name: "x".to_owned(),
+            query: query.to_owned(),
+            sort,
+            descending,
+            limit: None,
+        }
+    }
+
+    #[test]
+    fn most_played_recently_added() {
+        let mut tracks = (0..80)
+            .map(|x| track(&format!("new {x}"), x % 30, x as u32 + 1))
+            .collect::<Vec<_>>();
+        tracks.push(track("old favourite", 100, 1000));
+        tracks.push(track("never played", 1, 0));
+        let favourites = &SmartPlaylists::default().playlists[0];
+        let ret = favourites.evaluate(&tracks).unwrap();
+        // the 50 most played of the 80 new tracks, most played first
+        assert_eq!(ret, (30..80).rev().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn sorts() {
+        let mut tracks = [
+            track("b", 3, 2),
+            track("C", 1, 5),
+            track("a", 2, 2),
+            track("d", 4, 0),
+        ];
+        tracks[3].stats.added = None;
+
+        let sorted = |sort, descending| {
+            playlist("", Some(sort), descending)
+                .evaluate(&tracks)
+                .unwrap()
+        };
+        // text sorts ignore case
+        assert_eq!(sorted(SortField::Title, false), [2, 0, 1, 3]);
+        assert_eq!(sorted(SortField::Title, true), [3, 1, 0, 2]);
+        // ties stay in library order, either way
+        assert_eq!(sorted(SortField::Plays, false), [3, 0, 2, 1]);
+        assert_eq!(sorted(SortField::Plays, true), [1, 0, 2, 3]);
+        // tracks without a value go last, either way
+        assert_eq!(sorted(SortField::Added, false), [0, 2, 1, 3]);
+        assert_eq!(sorted(SortField::Added, true), [1, 2, 0, 3]);
+        assert_eq!(sorted(SortField::Rating, true), [0, 1, 2, 3]);
+
+        assert_eq!(
+            playlist("", None, true).evaluate(&tracks).unwrap(),
+            [0, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let tracks = [track("a", 1, 1), track("b", 1, 2), track("c", 1, 3)];
+        let mut playlist = playlist("plays:>1", Some(SortField::Plays), true);
+        playlist.limit = Some(1);
+        assert_eq!(playlist.evaluate(&tracks).unwrap(), [2]);
+        playlist.limit = Some(10);
+        assert_eq!(playlist.evaluate(&tracks).unwrap(), [2, 1]);
+        playlist.limit = Some(0);
+        assert_eq!(playlist.evaluate(&tracks).unwrap(), [] as [usize; 0]);
+        playlist.query = "plays:>".to_owned();
+        assert!(playlist.evaluate(&tracks).is_err());
+    }
+
+    #[test]
+    fn display_order() {
+        let mut playlist = SmartPlaylist {
+            name: "x".to_owned(),
+            query: "x".to_owned(),
+            sort: None,
+            descending: true,
+            limit: None,
+        };
+        assert_eq!(playlist.display_order(), "");
+        playlist.limit = Some(50);
+        assert_eq!(playlist.display_order(), "first 50");
+        playlist.sort = Some(SortField::Plays);
+        assert_eq!(playlist.display_order(), "by plays, descending, first 50");
+        playlist.descending = false;
+        playlist.limit = None;
+        assert_eq!(playlist.display_order(), "by plays");
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use crate::{
+    Item,
+    settings::{self, Settings},
+    tag,
+};
+
+/// What kind of listening a track is for. Spoken word is kept out of the
+/// music views and out of shuffle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MediaKind {
+    #[default]
+    Music,
+    Podcast,
+    Audiobook,
+}
+
+impl MediaKind {
+    /// Classify a track by its tags, extension, and the configured podcast and
+    /// audiobook folders.
+    pub fn of(item: &Item, settings: &Settings) -> Self {
+        let under = |dirs: &[std::path::PathBuf]| {
+            dirs.iter().any(|x| {
+                item.path.starts_with(settings::expand_home(x)) || item.path.starts_with(x)
+            })
+        };
+        let tags = item.tags.as_ref();
+        let flagged_podcast = tags.is_some_and(|x| {
+            x.get_typed_tag::<tag::PodcastFlag>().is_some_and(|x| x.0)
+                || x.get_typed_tag::<tag::PodcastUrl>().is_some()
+        });
+        let audiobook_ext = item
+            .path
+            .extension()
+            .and_then(|x| x.to_str())
+            .is_some_and(|x| x.eq_ignore_ascii_case("m4b") || x.eq_ignore_ascii_case("aax"));
+
+        if flagged_podcast || under(&settings.podcast_dirs) {
+            Self::Podcast
+        } else if audiobook_ext || under(&settings.audiobook_dirs) {
+            Self::Audiobook
+        } else {
+            Self::Music
+        }
+    }
+}
+
+/// A podcast or audiobook, with its episodes/parts in order.
+#[derive(Debug)]
+pub struct Show {
+    pub title: String,
+    /// What the show is ordered by, its sort order if it has one
+    pub sort_title: String,
+    pub kind: MediaKind,
+    /// Indexes into `Tracks`. Podcasts are newest first, audiobooks are in
+    /// disc/track order.
+    pub episodes: Vec<usize>,
+}
+
+/// All spoken word tracks, grouped by show (or book).
+#[derive(Debug, Default)]
+pub struct Shows(Vec<Show>);
+
+impl Shows {
+    pub fn new(tracks: &[Item]) -> Self {
+        let mut index = HashMap::new();
+        let mut ret: Vec<Show> = vec![];
+        for (e, item) in tracks.iter().enumerate() {
+            if item.kind == MediaKind::Music {
+                continue;
+            }
+            let tags = item.tags.as_ref();
+            let show = tags.and_then(|x| x.get_typed_tag::<tag::ShowName>());
+            let title = show
+                .map(|x| x.inner.as_str())
+                .or_else(|| tags.and_then(|x| x.album_title()))
+                .unwrap_or_default();
+            let sort_title = match show {
+                Some(x) => x.sort_order.as_deref().unwrap_or(title),
+                None => tags.and_then(|x| x.album_sort_title()).unwrap_or(title),
+            };
+            let at = *index
+                .entry((item.kind, title.to_lowercase()))
+                .or_insert_with(|| {
+                    ret.push(Show {
+                        title: title.to_owned(),
+                        sort_title: sort_title.to_owned(),
+                        kind: item.kind,
+                        episodes: vec![],
+                    });
+                    ret.len() - 1
+                });
+            ret[at].episodes.push(e);
+        }
+
+        for show in &mut ret {
+            let tags = |x: &usize| tracks[*x].tags.as_ref();
+            match show.kind {
+                MediaKind::Podcast => {
+                    show.episodes
+                        .sort_by_key(|x| std::cmp::Reverse(tags(x).and_then(|x| x.sort_date())));
+                }
+                _ => show.episodes.sort_by_key(|x| {
+                    (
+                        tags(x)
+                            .and_then(|x| x.get_typed_tag::<tag::DiscPos>())
+                            .map(|x| x.0),
+                        tags(x)
+                            .and_then(|x| x.get_typed_tag::<tag::TrackPos>())
+                            .map(|x| x.0),
+                        tracks[*x].path.clone(),
+                    )
+                }),
+            }
+        }
+        ret.sort_by_cached_key(|x| x.sort_title.to_lowercase());
+
+        Self(ret)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Show> {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::test_util::{item, tags};
+
+    #[test]
+    fn media_kind() {
+        let settings = Settings {
+            podcast_dirs: vec!["/podcasts".into()],
+            audiobook_dirs: vec!["/books".into()],
+            ..Default::default()
+        };
+        let kind = |path: &str, tag: Option<fn(&mut tag::TagSet)>| {
+            let mut tags = tags("Title", "Artist");
+            if let Some(tag) = tag {
+                tag(&mut tags);
+            }
+            MediaKind::of(&item(path, tags, Some(60)), &settings)
+        };
+        let flagged = |x: &mut tag::TagSet| {
+            let _ = x.push_typed_tag(tag::PodcastFlag(true));
+        };
+        let unflagged = |x: &mut tag::TagSet| {
+            let _ = x.push_typed_tag(tag::PodcastFlag(false));
+        };
+        let feed = |x: &mut tag::TagSet| {
+            let _ = x.push_typed_tag(tag::PodcastUrl("https://example.com/feed".to_owned()));
+        };
+
+        assert_eq!(kind("/music/a.mp3", None), MediaKind::Music);
+        assert_eq!(kind("/music/a.mp3", Some(flagged)), MediaKind::Podcast);
+        assert_eq!(kind("/music/a.mp3", Some(unflagged)), MediaKind::Music);
+        assert_eq!(kind("/music/a.mp3", Some(feed)), MediaKind::Podcast);
+        assert_eq!(kind("/podcasts/show/a.mp3", None), MediaKind::Podcast);
+        assert_eq!(kind("/music/a.m4b", None), MediaKind::Audiobook);
+        assert_eq!(kind("/music/a.AAX", None), MediaKind::Audiobook);
+        assert_eq!(kind("/books/a/01.mp3", None), MediaKind::Audiobook);
+        // a podcast wins over the audiobook extension
+        assert_eq!(kind("/books/a.m4b", Some(flagged)), MediaKind::Podcast);
+        // folders are matched by component, not by prefix
+        assert_eq!(kind("/podcasts-old/a.mp3", None), MediaKind::Music);
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use crate::{Item, tag};
+
+use super::AlbumId;
+
+/// A recording of a classical work, with its movements in order.
+#[derive(Debug)]
+pub struct Work {
+    pub title: String,
+    pub composer: Option<String>,
+    /// The album the recording is on
+    pub album: Option<String>,
+    /// Indexes into `Tracks`, ordered by movement number
+    pub movements: Vec<usize>,
+}
+
+/// What tells a work apart from any other work with the same title.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum WorkId {
+    MusicBrainz(Uuid),
+    /// Lowercased title and composer
+    Tagged {
+        title: String,
+        composer: Option<String>,
+    },
+}
+
+/// All tracks with a `Work` tag, grouped by work and album, so that different
+/// recordings of a work stay apart. A work is told apart by its MusicBrainz
+/// work id, or its title and composer if it has none.
+///
+/// Works are ordered by composer (using the composer's sort order if there is
+/// one), then title, then album.
+#[derive(Debug, Default)]
+pub struct Works(Vec<Work>);
+
+impl Works {
+    pub fn new(tracks: &[Item]) -> Self {
+        let mut index = HashMap::new();
+        let mut ret: Vec<(Option<String>, Work)> = vec![];
+        for (e, item) in tracks.iter().enumerate() {
+            let Some(tags) = item.tags.as_ref() else {
+                continue;
+            };
+            let Some(title) = tags.work() else {
+                continue;
+            };
+            let composer = tags.get_typed_tag::<tag::Composer>();
+            let work = match tags.get_typed_tag::<tag::MusicBrainzWorkId>() {
+                Some(id) => WorkId::MusicBrainz(id.0),
+                None => WorkId::Tagged {
+                    title: title.to_lowercase(),
+                    composer: composer.map(|x| x.inner.to_lowercase()),
+                },
+            };
+            let key = (work, AlbumId::new(&item.path, Some(tags)));
+            let at = *index.entry(key).or_insert_with(|| {
+                ret.push((
+                    composer.map(|x| x.sort_order.clone().unwrap_or_else(|| x.inner.clone())),
+                    Work {
+                        title: title.to_owned(),
+                        composer: composer.map(|x| x.inner.clone()),
+                        album: tags.album_title().map(ToOwned::to_owned),
+                        movements: vec![],
+                    },
+                ));
+                ret.len() - 1
+            });
+            ret[at].1.movements.push(e);
+        }
+
+        for (_, work) in &mut ret {
+            work.movements.sort_by_key(|x| {
+                let tags = tracks[*x].tags.as_ref();
+                (
+                    tags.and_then(|x| x.get_typed_tag::<tag::MovementPos>())
+                        .map(|x| x.0),
+                    tags.and_then(|x| x.get_typed_tag::<tag::DiscPos>())
+                        .map(|x| x.0),
+                    tags.and_then(|x| x.get_typed_tag::<tag::TrackPos>())
+                        .map(|x| x.0),
+                )
+            });
+        }
+        ret.sort_by_cached_key(|(sort, work)| {
+            (
+                sort.as_ref().map(|x| x.to_lowercase()),
+                work.title.to_lowercase(),
+                work.album.as_ref().map(|x| x.to_lowercase()),
+            )
+        });
+
+        Self(ret.into_iter().map(|(_, x)| x).collect())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Work> {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::test_util;
+
+    fn item(path: &str, work: &str, album: &str, work_id: Option<Uuid>) -> Item {
+        let mut tags = tag::TagSet::new();
+        let _ = tags.push_typed_tag(tag::Work(work.to_owned()));
+        let _ = tags.push_typed_tag(tag::Composer {
+            inner: "Ludwig van Beethoven".to_owned(),
+            sort_order: None,
+        });
+        let _ = tags.push_typed_tag(tag::AlbumTitle {
+            inner: album.to_owned(),
+            sort_order: None,
+        });
+        let _ = tags.push_typed_tag(tag::AlbumArtist {
+            inner: vec!["Berliner Philharmoniker".to_owned()],
+            sort_order: vec![],
+        });
+        if let Some(id) = work_id {
+            let _ = tags.push_typed_tag(tag::MusicBrainzWorkId(id));
+        }
+        test_util::item(path, tags, None)
+    }
+
+    #[test]
+    fn recordings_stay_apart() {
+        let tracks = [
+            item("/a/1.flac", "Symphony No. 5", "Karajan 1962", None),
+            item("/b/1.flac", "Symphony No. 5", "Karajan 1977", None),
+            item("/a/2.flac", "symphony no. 5", "Karajan 1962", None),
+        ];
+        let works = Works::new(&tracks);
+        let works = works.iter().collect::<Vec<_>>();
+        assert_eq!(works.len(), 2);
+        assert_eq!(works[0].album.as_deref(), Some("Karajan 1962"));
+        assert_eq!(works[0].movements, [0, 2]);
+        assert_eq!(works[1].movements, [1]);
+    }
+
+    #[test]
+    fn work_ids() {
+        let id = Uuid::from_u128(5);
+        let tracks = [
+            item("/a/1.flac", "Symphony No. 5", "Karajan 1962", Some(id)),
+            item("/a/2.flac", "Sinfonie Nr. 5", "Karajan 1962", Some(id)),
+            item(
+                "/a/3.flac",
+                "Symphony No. 5",
+                "Karajan 1962",
+                Some(Uuid::from_u128(6)),
+            ),
+        ];
+        let works = Works::new(&tracks);
+        let mut movements = works
+            .iter()
+            .map(|x| x.movements.clone())
+            .collect::<Vec<_>>();
+        movements.sort();
+        assert_eq!(movements, [vec![0, 1], vec![2]]);
+    }
+}
@@ -0,0 +1,65 @@
+mod library_album;
+mod library_artist;
+mod library_genre;
+mod library_loudness;
+mod library_playlist;
+mod library_query;
+mod library_search;
+mod library_smart;
+mod library_spoken;
+mod library_work;
+
+pub use library_album::*;
+pub use library_artist::*;
+pub use library_genre::*;
+pub use library_loudness::*;
+pub use library_playlist::*;
+pub use library_query::*;
+pub use library_search::*;
+pub use library_smart::*;
+pub use library_spoken::*;
+pub use library_work::*;
+
+#[cfg(test)]
+mod test_util {
+    use std::{path::PathBuf, time::Duration};
+
+    use crate::{
+        Item,
+        audio::{AudioField, Codec},
+        tag::{self, TagSet},
+    };
+
+    /// Tags with a title and a single artist.
+    pub fn tags(title: &str, artist: &str) -> TagSet {
+        let mut tags = TagSet::new();
+        let _ = tags.push_typed_tag(tag::TrackTitle {
+            inner: title.to_owned(),
+            sort_order: None,
+        });
+        let _ = tags.push_typed_tag(tag::TrackArtist {
+            inner: vec![artist.to_owned()],
+            sort_order: vec![],
+        });
+        tags
+    }
+
+    /// A music track, with CD quality FLAC audio if it has a length.
+    pub fn item(path: &str, tags: TagSet, secs: Option<u64>) -> Item {
+        Item {
+            path: PathBuf::from(path),
+            audio: secs.map(|x| AudioField {
+                duration: Duration::from_secs(x),
+                bitrate: Some(900),
+                sample_rate: Some(44100),
+                bit_depth: Some(16),
+                channels: Some(2),
+                codec: Codec::Flac,
+            }),
+            tags: Some(tags),
+            kind: Default::default(),
+            stats: Default::default(),
+            loudness: None,
+        }
+    }
+}
@@ -1,25 +1,32 @@
 use slint::{Model, Weak as SlintWeak};
 use smol::prelude::*;
 use std::{
+    collections::HashMap,
     path::PathBuf,
     sync::{Arc, LazyLock, Weak as ArcWeak},
 };
 
 slint::include_modules!();
 
+mod audio;
+mod library;
+mod playback;
+mod settings;
 mod tag;
 
-static DEFAULT_MUSIC: LazyLock<PathBuf> = LazyLock::new(|| PathBuf::from("~/Music"));
+static DEFAULT_MUSIC: LazyLock<PathBuf> =
+    LazyLock::new(|| settings::expand_home(std::path::Path::new("~/Music")));
 static ASYNC_RT: smol::Executor<'static> = smol::Executor::new();
 
-#[derive(Debug)]
-struct AudioField;
-
 #[derive(Debug)]
 struct Item {
     pub path: PathBuf,
-    pub audio: Option<AudioField>,
+    pub audio: Option<audio::AudioField>,
     pub tags: Option<tag::TagSet>,
+    pub kind: library::MediaKind,
+    pub stats: settings::PlayStat,
+    /// Results of loudness analysis, for files without ReplayGain tags
+    pub loudness: Option<library::TrackLoudness>,
 }
 
 #[derive(Default)]
@@ -28,33 +35,999 @@ struct Tracks(Vec<Item>);
 #[derive(Default)]
 struct MioPlaysState {
     pub tracks: Tracks,
+    /// Index into `Tracks` of every path
+    pub by_path: HashMap<PathBuf, usize>,
+    pub albums: library::Albums,
+    pub artists: library::Artists,
+    pub genres: library::Genres,
+    pub search: library::SearchIndex,
+    pub last_search: library::SearchResults,
+    pub works: library::Works,
+    pub shows: library::Shows,
+    pub settings: settings::Settings,
+    pub genre_config: settings::GenreConfig,
+    pub resume: settings::ResumePositions,
+    pub stats: settings::PlayStats,
+    pub loudness: library::LoudnessResults,
+    /// Whether loudness analysis is running
+    pub analysing: bool,
+    pub playlists: library::Playlists,
+    pub smart_playlists: library::SmartPlaylists,
+    /// Indexes into `Tracks` for each smart playlist, or why its query is
+    /// invalid
+    pub smart_results: Vec<Result<Vec<usize>, library::QueryError>>,
+    pub queue: playback::PlayQueue,
+    pub player: Option<playback::Engine>,
+    /// Name of the output device, for settings saved per device
+    pub output: String,
+    /// Whether the player has the current track, paused or not
+    pub loaded: bool,
+    pub paused: bool,
+    /// How far into the current track playback is, and how long the player
+    /// says it is
+    pub position: std::time::Duration,
+    pub duration: Option<std::time::Duration>,
+    /// Tracks in a row that could not be played
+    pub failures: usize,
+    /// What last went wrong in the player, shown until a track plays
+    pub player_error: String,
 }
 
 impl MioPlaysState {
     fn new() -> Self {
-        Self::default()
+        let output = playback::output_name();
+        let mut settings = settings::Settings::load();
+        settings.playback.dsp = settings.dsp.get(&output).cloned().unwrap_or_default();
+        let queue = playback::PlayQueue::load();
+        Self {
+            position: queue.position(),
+            settings,
+            output,
+            genre_config: settings::GenreConfig::load(),
+            resume: settings::ResumePositions::load(),
+            stats: settings::PlayStats::load(),
+            loudness: library::LoudnessResults::load(),
+            playlists: library::Playlists::load(),
+            smart_playlists: library::SmartPlaylists::load(),
+            queue,
+            ..Default::default()
+        }
     }
 }
 
-impl Tracks {
+impl MioPlaysState {
     fn make_slint_vec(&self) -> Vec<AlbumItem> {
-        let mut ret = vec![];
-        for (e, _album) in self.0.iter().enumerate() {
-            let x = AlbumItem {
-                album: "ALBUM".into(),
-                artist: "ARTIST".into(),
+        (0..self.albums.iter().count())
+            .map(|x| self.make_album_item(x))
+            .collect()
+    }
+
+    fn make_album_item(&self, idx: usize) -> AlbumItem {
+        let album = self.albums.get(idx).unwrap();
+        let first = &self.tracks.0[album.tracks[0]];
+        let editions = self.albums.editions(idx).count();
+        AlbumItem {
+            album: album.title.as_str().into(),
+            editions: if editions > 0 {
+                format!("{} editions", editions + 1).into()
+            } else {
+                Default::default()
+            },
+            artist: album.artists.join(", ").into(),
+            id: idx.try_into().unwrap(),
+            title: album.title.as_str().into(),
+            year: first
+                .tags
+                .as_ref()
+                .and_then(|x| x.display_year())
+                .unwrap_or_default()
+                .into(),
+            duration: audio::format_duration(
+                album
+                    .tracks
+                    .iter()
+                    .filter_map(|x| self.tracks.0[*x].audio.as_ref())
+                    .map(|x| x.duration)
+                    .sum(),
+            )
+            .into(),
+            audio_info: first
+                .audio
+                .as_ref()
+                .map(|x| x.display_summary())
+                .unwrap_or_default()
+                .into(),
+            comment: first
+                .tags
+                .as_ref()
+                .and_then(|x| x.localized_comment())
+                .unwrap_or_default()
+                .into(),
+            album_art: Default::default(),
+        }
+    }
+}
+
+impl MioPlaysState {
+    fn make_album_page(&self, idx: usize) -> AlbumPage {
+        let Some(album) = self.albums.get(idx) else {
+            return Default::default();
+        };
+        let item = self.make_album_item(idx);
+
+        // split the tracks up by disc, keeping the album order
+        let mut discs: Vec<(u32, Vec<usize>)> = vec![];
+        for track in &album.tracks {
+            let disc = self.tracks.0[*track]
+                .tags
+                .as_ref()
+                .and_then(|x| x.get_typed_tag::<tag::DiscPos>())
+                .map_or(0, |x| x.0);
+            match discs.last_mut() {
+                Some((at, tracks)) if *at == disc => tracks.push(*track),
+                _ => discs.push((disc, vec![*track])),
+            }
+        }
+        let disc_count = discs.len();
+        let discs = discs
+            .into_iter()
+            .map(|(disc, tracks)| {
+                let subtitle = self.tracks.0[tracks[0]]
+                    .tags
+                    .as_ref()
+                    .and_then(|x| x.get_typed_tag::<tag::DiscTitle>())
+                    .map(|x| x.0.as_str());
+                let title = match (subtitle, disc_count > 1 && disc > 0) {
+                    (Some(subtitle), true) => format!("Disc {disc}: {subtitle}"),
+                    (Some(subtitle), false) => subtitle.to_owned(),
+                    (None, true) => format!("Disc {disc}"),
+                    (None, false) => String::new(),
+                };
+                let tracks = tracks
+                    .into_iter()
+                    .map(|x| self.make_track_item(x, &album.artists))
+                    .collect::<Vec<_>>();
+                DiscItem {
+                    title: title.into(),
+                    tracks: slint::ModelRc::new(slint::VecModel::from(tracks)),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        AlbumPage {
+            title: item.title,
+            artist: item.artist,
+            year: item.year,
+            duration: item.duration,
+            album_art: Default::default(),
+            id: item.id,
+            discs: slint::ModelRc::new(slint::VecModel::from(discs)),
+        }
+    }
+
+    /// Make the tracklist entry for a track. The track artists are only shown
+    /// if they differ from the album artists.
+    fn make_track_item(&self, idx: usize, album_artists: &[String]) -> TrackItem {
+        let item = &self.tracks.0[idx];
+        let tags = item.tags.as_ref();
+        let artists = tags.map(|x| x.track_artists()).unwrap_or_default();
+        TrackItem {
+            number: tags
+                .and_then(|x| {
+                    x.get_typed_tag::<tag::TrackSide>()
+                        .map(|x| x.label.clone())
+                        .or_else(|| Some(x.get_typed_tag::<tag::TrackPos>()?.0.to_string()))
+                })
+                .unwrap_or_default()
+                .into(),
+            title: tags
+                .and_then(|x| x.display_title())
+                .unwrap_or_else(|| {
+                    item.path
+                        .file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .into(),
+            artist: if artists.is_empty() || artists == album_artists {
+                Default::default()
+            } else {
+                artists.join(", ").into()
+            },
+            duration: item
+                .audio
+                .as_ref()
+                .map(|x| x.display_duration())
+                .unwrap_or_default()
+                .into(),
+            id: idx.try_into().unwrap(),
+        }
+    }
+}
+
+impl MioPlaysState {
+    fn make_artists_slint_vec(&self) -> Vec<ArtistItem> {
+        self.artists
+            .iter()
+            .enumerate()
+            .map(|(e, artist)| ArtistItem {
+                name: artist.name.as_str().into(),
+                sort_name: artist.sort_name.as_str().into(),
                 id: e.try_into().unwrap(),
-                title: "TITLE".into(),
-                album_art: Default::default(),
+                albums: match artist.albums.len() {
+                    1 => "1 album".into(),
+                    n => format!("{n} albums").into(),
+                },
+            })
+            .collect()
+    }
+
+    fn make_artist_page(&self, idx: usize) -> ArtistPage {
+        let Some(artist) = self.artists.get(idx) else {
+            return Default::default();
+        };
+        let albums = |x: &[usize]| {
+            let ret = x
+                .iter()
+                .map(|x| self.make_album_item(*x))
+                .collect::<Vec<_>>();
+            slint::ModelRc::new(slint::VecModel::from(ret))
+        };
+        ArtistPage {
+            id: idx.try_into().unwrap(),
+            name: artist.name.as_str().into(),
+            albums: albums(&artist.albums),
+            appearances: albums(&artist.appearances),
+        }
+    }
+}
+
+impl MioPlaysState {
+    fn make_genres_slint_vec(&self) -> Vec<GenreItem> {
+        self.genres
+            .tree()
+            .into_iter()
+            .map(|(idx, depth)| GenreItem {
+                name: self.genres.get(idx).unwrap().name.as_str().into(),
+                count: match self.genres.all_tracks(idx).len() {
+                    1 => "1 track".into(),
+                    n => format!("{n} tracks").into(),
+                },
+                depth: depth.try_into().unwrap(),
+                id: idx.try_into().unwrap(),
+            })
+            .collect()
+    }
+
+    fn make_genre_page(&self, idx: usize) -> GenrePage {
+        let Some(genre) = self.genres.get(idx) else {
+            return Default::default();
+        };
+        let tracks = self
+            .genres
+            .all_tracks(idx)
+            .into_iter()
+            .collect::<std::collections::HashSet<_>>();
+        let albums = self
+            .albums
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.tracks.iter().any(|x| tracks.contains(x)))
+            .map(|(e, _)| self.make_album_item(e))
+            .collect::<Vec<_>>();
+        GenrePage {
+            name: genre.name.as_str().into(),
+            albums: slint::ModelRc::new(slint::VecModel::from(albums)),
+        }
+    }
+}
+
+impl MioPlaysState {
+    /// Search the library, returning the artists, albums and tracks found,
+    /// and why the query could not be parsed if it could not be.
+    ///
+    /// Plain words go through the search index. Anything using the query
+    /// language filters the tracks instead, and only finds tracks.
+    fn search(&mut self, query: &str) -> ([Vec<SearchResultItem>; 3], Option<String>) {
+        const LIMITS: [usize; 3] = [20, 50, 200];
+
+        let (hits, error) = match library::Query::parse(query) {
+            Ok(filter) if !filter.is_plain() => {
+                let hits = filter
+                    .filter(&self.tracks.0)
+                    .into_iter()
+                    .map(library::SearchHit::Track)
+                    .collect::<Vec<_>>();
+                self.last_search = Default::default();
+                (hits, None)
+            }
+            // search for the words while a query is being typed out
+            parsed => {
+                let results = self.search.search(query, Some(&self.last_search));
+                let hits = self.search.hits(&results).collect::<Vec<_>>();
+                self.last_search = results;
+                (hits, parsed.err().map(|x| x.to_string()))
+            }
+        };
+
+        let mut ret: [Vec<SearchResultItem>; 3] = Default::default();
+        for hit in hits {
+            let (group, item) = match hit {
+                library::SearchHit::Artist(x) => {
+                    let artist = self.artists.get(x).unwrap();
+                    let item = SearchResultItem {
+                        title: artist.name.as_str().into(),
+                        subtitle: Default::default(),
+                        id: x.try_into().unwrap(),
+                    };
+                    (0, item)
+                }
+                library::SearchHit::Album(x) => {
+                    let album = self.albums.get(x).unwrap();
+                    let item = SearchResultItem {
+                        title: album.title.as_str().into(),
+                        subtitle: album.artists.join(", ").into(),
+                        id: x.try_into().unwrap(),
+                    };
+                    (1, item)
+                }
+                library::SearchHit::Track(x) => {
+                    let tags = self.tracks.0[x].tags.as_ref();
+                    let item = SearchResultItem {
+                        title: tags
+                            .and_then(|x| x.display_title())
+                            .unwrap_or_default()
+                            .into(),
+                        subtitle: tags
+                            .map(|x| {
+                                let mut ret = x.track_artists().join(", ");
+                                if let Some(album) = x.album_title() {
+                                    ret.push_str(" · ");
+                                    ret.push_str(album);
+                                }
+                                ret
+                            })
+                            .unwrap_or_default()
+                            .into(),
+                        id: x.try_into().unwrap(),
+                    };
+                    (2, item)
+                }
+            };
+            if ret[group].len() < LIMITS[group] {
+                ret[group].push(item);
+            }
+        }
+        (ret, error)
+    }
+}
+
+impl MioPlaysState {
+    /// Set the playlists, their drawer entries and the menu to add to them.
+    fn set_playlists(&self, mainui: &MainWindow) {
+        let lookup = library::TrackLookup::new(&self.tracks.0);
+        let mut playlists = vec![];
+        let mut nav = vec![];
+        let mut menu = vec![];
+        for playlist in &self.playlists.playlists {
+            let tracks = playlist
+                .entries
+                .iter()
+                .zip(playlist.resolve(&lookup))
+                .map(|(entry, x)| match x {
+                    Some(x) => self.make_track_item(x, &[]),
+                    None => TrackItem {
+                        title: entry.display_title().into(),
+                        artist: "Not in the library".into(),
+                        id: -1,
+                        ..Default::default()
+                    },
+                })
+                .collect::<Vec<_>>();
+            playlists.push(PlaylistItem {
+                name: playlist.name.as_str().into(),
+                tracks: slint::ModelRc::new(slint::VecModel::from(tracks)),
+            });
+            nav.push(NavigationItem {
+                text: playlist.name.as_str().into(),
+                ..Default::default()
+            });
+            menu.push(MenuItem {
+                text: playlist.name.as_str().into(),
+                enabled: true,
+                ..Default::default()
+            });
+        }
+        let browse_state = mainui.global::<MainBrowsingState>();
+        browse_state.set_playlists(slint::ModelRc::new(slint::VecModel::from(playlists)));
+        browse_state.set_playlist_nav(slint::ModelRc::new(slint::VecModel::from(nav)));
+        browse_state.set_playlist_menu(slint::ModelRc::new(slint::VecModel::from(menu)));
+    }
+
+    /// Set the smart playlists and their drawer entries.
+    fn set_smart_playlists(&self, mainui: &MainWindow) {
+        let mut playlists = vec![];
+        let mut nav = vec![];
+        for (playlist, result) in self
+            .smart_playlists
+            .playlists
+            .iter()
+            .zip(&self.smart_results)
+        {
+            let (tracks, error) = match result {
+                Ok(x) => (
+                    x.iter().map(|x| self.make_track_item(*x, &[])).collect(),
+                    None,
+                ),
+                Err(err) => (vec![], Some(err.to_string())),
+            };
+            playlists.push(SmartPlaylistItem {
+                name: playlist.name.as_str().into(),
+                query: playlist.query.as_str().into(),
+                order: playlist.display_order().into(),
+                error: error.unwrap_or_default().into(),
+                tracks: slint::ModelRc::new(slint::VecModel::from(tracks)),
+            });
+            nav.push(NavigationItem {
+                text: playlist.name.as_str().into(),
+                ..Default::default()
+            });
+        }
+        let browse_state = mainui.global::<MainBrowsingState>();
+        browse_state.set_smart_playlists(slint::ModelRc::new(slint::VecModel::from(playlists)));
+        browse_state.set_smart_playlist_nav(slint::ModelRc::new(slint::VecModel::from(nav)));
+    }
+
+    fn make_works_slint_vec(&self) -> Vec<WorkItem> {
+        let mut ret = vec![];
+        for work in self.works.iter() {
+            let movements = work
+                .movements
+                .iter()
+                .filter_map(|x| self.tracks.0[*x].tags.as_ref())
+                .map(|x| {
+                    let movement = x.movement().unwrap_or_default();
+                    match x.get_typed_tag::<tag::MovementPos>() {
+                        Some(pos) => format!("{}. {movement}", pos.0).into(),
+                        None => movement.into(),
+                    }
+                })
+                .collect::<Vec<slint::SharedString>>();
+            ret.push(WorkItem {
+                title: work.title.as_str().into(),
+                composer: work.composer.as_deref().unwrap_or_default().into(),
+                album: work.album.as_deref().unwrap_or_default().into(),
+                movements: slint::ModelRc::new(slint::VecModel::from(movements)),
+            });
+        }
+        ret
+    }
+}
+
+impl MioPlaysState {
+    /// The paths of some tracks, for the queue.
+    fn track_paths(&self, tracks: &[usize]) -> Vec<PathBuf> {
+        tracks
+            .iter()
+            .filter_map(|x| self.tracks.0.get(*x))
+            .map(|x| x.path.clone())
+            .collect()
+    }
+
+    /// What shuffling needs to know about a track.
+    fn shuffle_key(&self, path: &std::path::Path) -> playback::ShuffleKey {
+        let Some(item) = self.by_path.get(path).map(|x| &self.tracks.0[*x]) else {
+            return Default::default();
+        };
+        let tags = item.tags.as_ref();
+        playback::ShuffleKey {
+            album: tags.and_then(|x| {
+                let title = x.album_title()?;
+                Some(format!("{}\0{}", title, x.album_artists().join(", ")).to_lowercase())
+            }),
+            artist: tags.and_then(|x| x.track_artists().first().map(|x| x.to_lowercase())),
+            spoken: item.kind != library::MediaKind::Music,
+        }
+    }
+
+    /// Play some tracks now, in the shuffle order. With `keep_first`, the
+    /// first track plays first anyway.
+    fn play_tracks(&mut self, tracks: &[usize], keep_first: bool) {
+        let tracks = self.track_paths(tracks);
+        let mut queue = std::mem::take(&mut self.queue);
+        queue.play_shuffled(
+            tracks,
+            keep_first,
+            |x| self.shuffle_key(x),
+            &mut rand::rng(),
+        );
+        self.queue = queue;
+    }
+
+    /// Play the current track of the queue from where the queue is in it,
+    /// or stop if there is none.
+    fn play_current(&mut self) {
+        let Some(player) = &self.player else {
+            return;
+        };
+        let at = self.queue.position();
+        match self.queue.current() {
+            Some(x) => {
+                player.send(playback::Command::Play(self.source(x)));
+                if !at.is_zero() {
+                    player.send(playback::Command::Seek(at));
+                }
+            }
+            None => player.send(playback::Command::Stop),
+        }
+        self.loaded = self.queue.current().is_some();
+        self.paused = false;
+        self.position = at;
+        self.duration = None;
+    }
+
+    /// Go to a position in the current track. Before it is loaded, eg.
+    /// after a restart, it starts there once played.
+    fn seek(&mut self, at: std::time::Duration) {
+        let at = match self.current_duration() {
+            Some(x) => at.min(x),
+            None => at,
+        };
+        self.position = at;
+        self.queue.set_position(at);
+        if self.loaded
+            && let Some(player) = &self.player
+        {
+            player.send(playback::Command::Seek(at));
+        }
+    }
+
+    /// How long the current track is, as the player says or else as the
+    /// library has it.
+    fn current_duration(&self) -> Option<std::time::Duration> {
+        self.duration.or_else(|| {
+            let item = &self.tracks.0[*self.by_path.get(self.queue.current()?)?];
+            Some(item.audio.as_ref()?.duration)
+        })
+    }
+
+    /// Keep where the current track is at, so that it carries on from there
+    /// after a restart, and podcasts and audiobooks also when played again
+    /// later.
+    fn save_position(&mut self) {
+        let Some(path) = self.queue.current().map(|x| x.to_owned()) else {
+            return;
+        };
+        self.queue.set_position(self.position);
+        self.report_save("the queue", self.queue.save());
+        if self.shuffle_key(&path).spoken
+            && let Some(length) = self.current_duration()
+        {
+            self.resume.remember(path, self.position, length);
+            self.report_save("resume positions", self.resume.save());
+        }
+    }
+
+    /// Show a file that could not be saved where player errors are shown.
+    fn report_save(&mut self, what: &str, result: std::io::Result<()>) {
+        if let Err(err) = result {
+            self.player_error = format!("Could not save {what}: {err}");
+        }
+    }
+
+    /// Get the player ready for what plays after the current track.
+    fn preload_next(&self) {
+        let Some(player) = &self.player else {
+            return;
+        };
+        let next = self.queue.peek_next();
+        let fade = match (self.queue.current(), next) {
+            (Some(from), Some(to)) => self.crossfades(from, to),
+            _ => false,
+        };
+        player.send(playback::Command::SetNext(
+            next.map(|x| self.source(x)),
+            fade,
+        ));
+    }
+
+    /// What the player needs to know about a track. Analysed loudness is
+    /// used for tracks without ReplayGain tags, and podcasts and audiobooks
+    /// play at their own speed.
+    fn source(&self, path: &std::path::Path) -> playback::Source {
+        let item = self.by_path.get(path).map(|x| &self.tracks.0[*x]);
+        let speed = match item {
+            Some(x) if x.kind != library::MediaKind::Music => self.settings.spoken_speed,
+            _ => 1.0,
+        };
+        if let Some(item) = item
+            && !library::has_replay_gain(item)
+            && let Some(loudness) = &item.loudness
+        {
+            return playback::Source {
+                path: path.to_owned(),
+                replay_gain: loudness.replay_gain(),
+                speed,
+            };
+        }
+        let tags = item.and_then(|x| x.tags.as_ref());
+        playback::Source {
+            path: path.to_owned(),
+            replay_gain: playback::ReplayGain {
+                track_gain: tags
+                    .and_then(|x| x.get_typed_tag::<tag::ReplayGainTrackGain>())
+                    .map(|x| x.0),
+                track_peak: tags
+                    .and_then(|x| x.get_typed_tag::<tag::ReplayGainTrackPeak>())
+                    .map(|x| x.0),
+                album_gain: tags
+                    .and_then(|x| x.get_typed_tag::<tag::ReplayGainAlbumGain>())
+                    .map(|x| x.0),
+                album_peak: tags
+                    .and_then(|x| x.get_typed_tag::<tag::ReplayGainAlbumPeak>())
+                    .map(|x| x.0),
+            },
+            speed,
+        }
+    }
+
+    /// Whether to crossfade from one track into another. Albums stay
+    /// gapless, and podcasts and audiobooks never fade.
+    fn crossfades(&self, from: &std::path::Path, to: &std::path::Path) -> bool {
+        let (from, to) = (self.shuffle_key(from), self.shuffle_key(to));
+        !from.spoken && !to.spoken && (from.album.is_none() || from.album != to.album)
+    }
+
+    /// Set the playback settings.
+    fn set_playback_options(&self, mainui: &MainWindow) {
+        let options = &self.settings.playback;
+        let playing_state = mainui.global::<PlayingState>();
+        playing_state.set_volume(options.volume);
+        playing_state.set_crossfade(options.crossfade.secs);
+        playing_state.set_fade_curve(match options.crossfade.curve {
+            playback::FadeCurve::Linear => 0,
+            playback::FadeCurve::EqualPower => 1,
+            playback::FadeCurve::SCurve => 2,
+        });
+        playing_state.set_trim_silence(options.trim_silence);
+        let normalization = &options.normalization;
+        playing_state.set_gain_mode(match normalization.mode {
+            playback::GainMode::Off => 0,
+            playback::GainMode::Track => 1,
+            playback::GainMode::Album => 2,
+        });
+        playing_state.set_preamp(normalization.preamp);
+        playing_state.set_fallback_gain(normalization.fallback);
+        playing_state.set_prevent_clipping(normalization.prevent_clipping);
+        playing_state.set_bit_perfect(options.output.bit_perfect);
+        playing_state.set_resample_quality(match options.output.quality {
+            playback::ResampleQuality::Balanced => 0,
+            playback::ResampleQuality::Best => 1,
+        });
+        playing_state.set_dither(options.output.dither);
+
+        let dsp = &options.dsp;
+        playing_state.set_output_name(self.output.as_str().into());
+        playing_state.set_equalizer(dsp.equalizer);
+        playing_state.set_eq_preamp(dsp.preamp);
+        let bands = dsp
+            .bands
+            .iter()
+            .map(|x| EqBandItem {
+                label: x.display_freq().into(),
+                kind: match x.kind {
+                    playback::FilterKind::Peak => 0,
+                    playback::FilterKind::LowShelf => 1,
+                    playback::FilterKind::HighShelf => 2,
+                    playback::FilterKind::LowPass => 3,
+                    playback::FilterKind::HighPass => 4,
+                },
+                freq: x.freq,
+                gain: x.gain,
+                q: x.q,
+            })
+            .collect::<Vec<_>>();
+        playing_state.set_eq_bands(slint::ModelRc::new(slint::VecModel::from(bands)));
+        let presets = playback::EQ_PRESETS
+            .iter()
+            .map(|x| MenuItem {
+                text: x.name.into(),
+                enabled: true,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        playing_state.set_eq_presets(slint::ModelRc::new(slint::VecModel::from(presets)));
+        playing_state.set_balance(dsp.balance);
+        playing_state.set_mono(dsp.mono);
+    }
+
+    /// Count a play of the current track, and move on from it. A finished
+    /// episode starts over next time.
+    fn finish_current(&mut self) {
+        if let Some(path) = self.queue.current() {
+            let stat = self.stats.played(path);
+            if let Some(x) = self.by_path.get(path) {
+                self.tracks.0[*x].stats = stat;
+            }
+            let saved = self.stats.save();
+            let forgot = self.resume.forget(path);
+            self.report_save("play counts", saved);
+            if forgot {
+                self.report_save("resume positions", self.resume.save());
+            }
+        }
+        self.queue.finished();
+        self.position = Default::default();
+        self.duration = None;
+    }
+
+    /// Make the queue entry for a path, which may have left the library.
+    fn make_queue_item(&self, path: &std::path::Path) -> TrackItem {
+        match self.by_path.get(path) {
+            Some(x) => self.make_track_item(*x, &[]),
+            None => TrackItem {
+                title: path
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+                    .into(),
+                id: -1,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// How far into the current track playback is, and how long it is.
+    fn position_view(&self) -> PositionView {
+        PositionView {
+            at: self.position.as_secs_f32(),
+            duration: self.current_duration().unwrap_or_default().as_secs_f32(),
+        }
+    }
+
+    /// Set how far into the current track playback is.
+    fn set_position(&self, mainui: &MainWindow) {
+        self.position_view().show(mainui);
+    }
+    /// The tags of the current track, if it is in the library.
+    fn current_tags(&self) -> Option<&tag::TagSet> {
+        let x = self.by_path.get(self.queue.current()?)?;
+        self.tracks.0[*x].tags.as_ref()
+    }
+
+    /// Every tag of the current track that fits on a line, as "Name: value".
+    fn current_details(&self) -> String {
+        let Some(tags) = self.current_tags() else {
+            return String::new();
+        };
+        let mut ret = tags
+            .iter()
+            .filter_map(|x| Some(format!("{}: {}", x.display_name()?, x.display_value()?)))
+            .collect::<Vec<_>>();
+        ret.sort();
+        ret.join("\n")
+    }
+
+    /// The current track, the queue and the history.
+    fn queue_view(&self) -> QueueView {
+        QueueView {
+            position: self.position_view(),
+            is_playing: self.loaded && !self.paused,
+            player_error: self.player_error.clone(),
+            shuffle: match self.queue.shuffle {
+                playback::ShuffleMode::Off => 0,
+                playback::ShuffleMode::Tracks => 1,
+                playback::ShuffleMode::Albums => 2,
+                playback::ShuffleMode::Smart => 3,
+            },
+            repeat: match self.queue.repeat {
+                playback::RepeatMode::Off => 0,
+                playback::RepeatMode::All => 1,
+                playback::RepeatMode::One => 2,
+            },
+            current_path: self.queue.current().map(|x| x.to_owned()),
+            current: self
+                .queue
+                .current()
+                .map(|x| self.make_queue_item(x))
+                .unwrap_or_default(),
+            details: self.current_details(),
+            lyrics: self
+                .current_tags()
+                .and_then(|x| x.localized_lyrics())
+                .unwrap_or_default()
+                .to_owned(),
+            upcoming: self
+                .queue
+                .upcoming()
+                .iter()
+                .map(|x| self.make_queue_item(x))
+                .collect(),
+            history: self
+                .queue
+                .history()
+                .rev()
+                .map(|x| self.make_queue_item(x))
+                .collect(),
+        }
+    }
+
+    /// Set the current track, the queue and the history.
+    fn set_queue(&self, mainui: &MainWindow) {
+        self.queue_view().show(mainui);
+    }
+}
+
+/// Where playback is, taken from the state so that the event loop can show
+/// it without locking the state.
+struct PositionView {
+    at: f32,
+    duration: f32,
+}
+
+impl PositionView {
+    fn show(self, mainui: &MainWindow) {
+        let playing_state = mainui.global::<PlayingState>();
+        playing_state.set_position(self.at);
+        playing_state.set_duration(self.duration);
+    }
+}
+
+/// What the queue page shows, taken from the state so that the event loop
+/// can show it without locking the state.
+struct QueueView {
+    position: PositionView,
+    is_playing: bool,
+    player_error: String,
+    shuffle: i32,
+    repeat: i32,
+    current_path: Option<PathBuf>,
+    current: TrackItem,
+    details: String,
+    lyrics: String,
+    upcoming: Vec<TrackItem>,
+    history: Vec<TrackItem>,
+}
+
+impl QueueView {
+    fn show(self, mainui: &MainWindow) {
+        self.position.show(mainui);
+        let playing_state = mainui.global::<PlayingState>();
+        playing_state.set_is_playing(self.is_playing);
+        playing_state.set_has_current(self.current_path.is_some());
+        playing_state.set_player_error(self.player_error.into());
+        playing_state.set_shuffle(self.shuffle);
+        playing_state.set_repeat(self.repeat);
+        // the cover is read from the file, so only when the track changes,
+        // which tracks outside the library cannot be told apart by
+        if self.current.id != playing_state.get_current().id
+            || self.current.id < 0
+            || self.current_path.is_none()
+        {
+            set_current_art(mainui, self.current_path, self.current.id);
+            playing_state.set_details(self.details.into());
+            playing_state.set_lyrics(self.lyrics.into());
+        }
+        playing_state.set_current(self.current);
+        // update the model in place, so that the view keeps its scroll position
+        let queue = playing_state.get_queue();
+        match queue.as_any().downcast_ref::<slint::VecModel<TrackItem>>() {
+            Some(queue) => queue.set_vec(self.upcoming),
+            None => {
+                playing_state.set_queue(slint::ModelRc::new(slint::VecModel::from(self.upcoming)))
+            }
+        }
+        playing_state.set_history(slint::ModelRc::new(slint::VecModel::from(self.history)));
+    }
+}
+
+/// Read the cover of a file in the background, and hand it to `show` on the
+/// UI thread. Nothing is shown if there is no cover.
+fn load_art(
+    mainui: &MainWindow,
+    path: PathBuf,
+    show: impl FnOnce(&MainWindow, slint::Image) + Send + 'static,
+) {
+    let w_mainui = mainui.as_weak();
+    ASYNC_RT
+        .spawn(async move {
+            let Some(art) = smol::unblock(move || tag::read_cover_art(&path)).await else {
+                return;
             };
-            ret.push(x);
+            let art = slint::SharedPixelBuffer::<slint::Rgba8Pixel>::clone_from_slice(
+                art.as_raw(),
+                art.width(),
+                art.height(),
+            );
+            let _ = w_mainui
+                .upgrade_in_event_loop(move |mainui| show(&mainui, slint::Image::from_rgba8(art)));
+        })
+        .detach();
+}
+
+/// Load the cover of the current track, with the id it has in the UI, in the
+/// background.
+fn set_current_art(mainui: &MainWindow, path: Option<PathBuf>, id: i32) {
+    let playing_state = mainui.global::<PlayingState>();
+    playing_state.set_album_art(Default::default());
+    let Some(path) = path else {
+        return;
+    };
+    load_art(mainui, path, move |mainui, art| {
+        let playing_state = mainui.global::<PlayingState>();
+        // the track may have changed in the meantime
+        if playing_state.get_current().id == id {
+            playing_state.set_album_art(art);
+        }
+    });
+}
+
+impl MioPlaysState {
+    fn make_shows_slint_vec(&self) -> Vec<ShowItem> {
+        let mut ret = vec![];
+        for show in self.shows.iter() {
+            let episodes = show
+                .episodes
+                .iter()
+                .map(|e| {
+                    let item = &self.tracks.0[*e];
+                    let tags = item.tags.as_ref();
+                    let resume = self.resume.get(&item.path);
+                    let chapters = tags
+                        .and_then(|x| x.get_typed_tag::<tag::Chapters>())
+                        .map(|x| x.0.as_slice())
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|x| ChapterItem {
+                            title: x.title.as_str().into(),
+                            start: audio::format_duration(x.start).into(),
+                            start_secs: x.start.as_secs_f32(),
+                        })
+                        .collect::<Vec<_>>();
+                    EpisodeItem {
+                        title: tags
+                            .and_then(|x| x.track_title())
+                            .map(ToOwned::to_owned)
+                            .unwrap_or_else(|| {
+                                item.path
+                                    .file_stem()
+                                    .unwrap_or_default()
+                                    .to_string_lossy()
+                                    .into_owned()
+                            })
+                            .into(),
+                        id: (*e).try_into().unwrap(),
+                        resume: resume
+                            .map(|x| format!("resume at {}", audio::format_duration(x)))
+                            .unwrap_or_default()
+                            .into(),
+                        resume_secs: resume.map(|x| x.as_secs_f32()).unwrap_or_default(),
+                        chapters: slint::ModelRc::new(slint::VecModel::from(chapters)),
+                    }
+                })
+                .collect::<Vec<_>>();
+            ret.push(ShowItem {
+                title: show.title.as_str().into(),
+                kind: match show.kind {
+                    library::MediaKind::Podcast => "Podcast",
+                    library::MediaKind::Audiobook => "Audiobook",
+                    library::MediaKind::Music => "Music",
+                }
+                .into(),
+                episodes: slint::ModelRc::new(slint::VecModel::from(episodes)),
+            });
         }
         ret
     }
+}
 
-    async fn scan(&mut self) {
+impl Tracks {
+    /// Returns how many files were left out as their audio cannot be played.
+    async fn scan(&mut self) -> usize {
         // TODO: error handling
-        async fn scan_recurse(at: PathBuf, limit: u8) -> Vec<Item> {
+        async fn scan_recurse(at: PathBuf, limit: u8, unsupported: &mut usize) -> Vec<Item> {
             if limit > 0 {
                 // normal scan logic
                 let mut ret = vec![];
@@ -66,26 +1039,48 @@ impl Tracks {
                         // file logic
                         let path = item.path().clone();
                         let ext = path.extension().and_then(|x| x.to_str());
+                        if ext.is_some_and(playback::is_unsupported_extension) {
+                            *unsupported += 1;
+                            continue;
+                        }
+                        let (want_tags, want_audio) = match &ext {
+                            Some(ext) => (
+                                check_extension_for_tag_decoder(ext).await,
+                                check_extension_for_sound_decoder(ext).await,
+                            ),
+                            None => (false, false),
+                        };
+                        // cover art, cue sheets and rip logs are not tracks,
+                        // and are left for whatever reads them by name
+                        if !want_tags && !want_audio {
+                            continue;
+                        }
+                        let (tags, audio) = match tag::decode_tags(path.clone(), want_audio).await {
+                            Some((tags, audio)) => (want_tags.then_some(tags), audio),
+                            // lofty does not know every container symphonia
+                            // plays, but anything neither can open is skipped
+                            None if want_audio && can_play(&path).await => (None, None),
+                            None => continue,
+                        };
+                        // Opus also comes in plain Ogg files
+                        if audio
+                            .as_ref()
+                            .is_some_and(|x| x.codec == audio::Codec::Opus)
+                        {
+                            *unsupported += 1;
+                            continue;
+                        }
                         ret.push(Item {
-                            path: path.clone(),
-                            audio: if let Some(ext) = &ext
-                                && check_extension_for_sound_decoder(ext).await
-                            {
-                                Some(AudioField)
-                            } else {
-                                None
-                            },
-                            tags: if let Some(ext) = &ext
-                                && check_extension_for_tag_decoder(ext).await
-                            {
-                                Some(tag::decode_tags(path).await)
-                            } else {
-                                None
-                            },
+                            path,
+                            audio,
+                            tags,
+                            kind: Default::default(),
+                            stats: Default::default(),
+                            loudness: None,
                         });
                     } else if ftype.is_dir() {
                         // traverse dir
-                        ret.extend(Box::pin(scan_recurse(item.path(), 10)).await);
+                        ret.extend(Box::pin(scan_recurse(item.path(), 10, unsupported)).await);
                     }
                 }
                 ret
@@ -96,19 +1091,28 @@ impl Tracks {
         }
 
         let input_dir = &*DEFAULT_MUSIC;
+        let mut unsupported = 0;
         self.0.clear();
-        self.0.extend(scan_recurse(input_dir.clone(), 10).await);
+        self.0
+            .extend(scan_recurse(input_dir.clone(), 10, &mut unsupported).await);
+        self.0
+            .sort_by_key(|x| x.tags.as_ref().and_then(|x| x.sort_date()));
+        unsupported
     }
 }
 
 async fn check_extension_for_tag_decoder(inp: &str) -> bool {
-    // TODO: impl
-    true
+    lofty::file::FileType::from_ext(inp).is_some()
 }
 
 async fn check_extension_for_sound_decoder(inp: &str) -> bool {
-    // TODO: impl
-    true
+    playback::can_decode_extension(inp)
+}
+
+/// Whether the audio decoder can open a file.
+async fn can_play(path: &std::path::Path) -> bool {
+    let path = path.to_owned();
+    smol::unblock(move || playback::Decoder::open(&path).is_ok()).await
 }
 
 fn reload_music_files(
@@ -121,36 +1125,1277 @@ fn reload_music_files(
     };
     ASYNC_RT
         .spawn(async move {
-            // reset track list
+            // scan without holding the state, so that playback and the rest
+            // of the UI carry on meanwhile
+            let mut tracks = Tracks::default();
+            let unsupported = tracks.scan().await;
+
             let mut state = state_lock.write().await;
-            state.tracks.scan().await;
-            drop(state);
+            // the runtime is ticked on the UI thread, so the window is at hand
+            let Some(mainui) = w_mainui.upgrade() else {
+                return;
+            };
+            // album and artist indexes change with the library, so the open
+            // album and artist are found again by their ids afterwards
+            let browse_state = mainui.global::<MainBrowsingState>();
+            let open_album = browse_state
+                .get_album_open()
+                .then(|| usize::try_from(browse_state.get_album_page().id).ok())
+                .flatten()
+                .and_then(|x| Some(state.albums.get(x)?.id.clone()));
+            let open_artist = browse_state
+                .get_artist_open()
+                .then(|| usize::try_from(browse_state.get_artist_page().id).ok())
+                .flatten()
+                .and_then(|x| Some(state.artists.get(x)?.id.clone()));
+
+            // reset track list
+            state.tracks = tracks;
+            let MioPlaysState {
+                tracks,
+                settings,
+                stats,
+                loudness,
+                ..
+            } = &mut *state;
+            for item in &mut tracks.0 {
+                item.kind = library::MediaKind::of(item, settings);
+                item.stats = stats.scanned(&item.path);
+                item.loudness = loudness.get(&item.path);
+            }
+            let saved = stats.save();
+            state.report_save("play counts", saved);
+            state.by_path = state
+                .tracks
+                .0
+                .iter()
+                .enumerate()
+                .map(|(e, x)| (x.path.clone(), e))
+                .collect();
+            state.albums = library::Albums::new(&state.tracks.0);
+            state.artists = library::Artists::new(&state.tracks.0, &state.albums);
+            state.genres = library::Genres::new(&state.tracks.0, &state.genre_config);
+            state.works = library::Works::new(&state.tracks.0);
+            state.search =
+                library::SearchIndex::new(&state.tracks.0, &state.albums, &state.artists);
+            state.last_search = Default::default();
+            state.shows = library::Shows::new(&state.tracks.0);
+            state.smart_results = state.smart_playlists.evaluate(&state.tracks.0);
+            let MioPlaysState {
+                tracks, playlists, ..
+            } = &mut *state;
+            if playlists.relink(&library::TrackLookup::new(&tracks.0)) {
+                let saved = playlists.save();
+                state.report_save("playlists", saved);
+            }
 
             // then load the grid
-            w_mainui
-                .upgrade_in_event_loop(move |mainui| {
-                    let state = state_lock.read_blocking();
-                    let ret = state.tracks.make_slint_vec();
-                    let ret = slint::ModelRc::new(slint::VecModel::from(ret));
-                    mainui.global::<MainBrowsingState>().set_tracks(ret);
-                })
-                .unwrap();
+            browse_state.set_library_status(match unsupported {
+                0 => Default::default(),
+                1 => "1 Opus file was left out, as Opus cannot be played".into(),
+                x => format!("{x} Opus files were left out, as Opus cannot be played").into(),
+            });
+            let ret = state.make_slint_vec();
+            let ret = slint::ModelRc::new(slint::VecModel::from(ret));
+            browse_state.set_tracks(ret);
+            let ret = state.make_artists_slint_vec();
+            let ret = slint::ModelRc::new(slint::VecModel::from(ret));
+            browse_state.set_artists(ret);
+            let ret = state.make_genres_slint_vec();
+            let ret = slint::ModelRc::new(slint::VecModel::from(ret));
+            browse_state.set_genres(ret);
+            let ret = state.make_works_slint_vec();
+            let ret = slint::ModelRc::new(slint::VecModel::from(ret));
+            browse_state.set_works(ret);
+            let ret = state.make_shows_slint_vec();
+            let ret = slint::ModelRc::new(slint::VecModel::from(ret));
+            browse_state.set_shows(ret);
+            state.set_playlists(&mainui);
+            state.set_smart_playlists(&mainui);
+            state.set_queue(&mainui);
+            if let Some(id) = &open_album {
+                match state.albums.find(id) {
+                    Some(idx) => {
+                        let page = AlbumPage {
+                            album_art: browse_state.get_album_page().album_art,
+                            ..state.make_album_page(idx)
+                        };
+                        browse_state.set_album_page(page);
+                    }
+                    None => browse_state.set_album_open(false),
+                }
+            }
+            if let Some(id) = &open_artist {
+                match state.artists.find(id) {
+                    Some(idx) => browse_state.set_artist_page(state.make_artist_page(idx)),
+                    None => browse_state.set_artist_open(false),
+                }
+            }
         })
         .detach();
 }
 
-fn main() {
-    let state = Arc::new(smol::lock::RwLock::new(MioPlaysState::new()));
-    let mainui = MainWindow::new().unwrap();
-    let browse_state = mainui.global::<MainBrowsingState>();
+/// Run a UI callback on the state once nothing else holds it. The callback
+/// waits for the lock rather than being dropped, and as the runtime is ticked
+/// on the UI thread, it can update the window from there.
+fn with_state(
+    w_state: &ArcWeak<smol::lock::RwLock<MioPlaysState>>,
+    w_mainui: &SlintWeak<MainWindow>,
+    callback: impl FnOnce(&mut MioPlaysState, &MainWindow) + Send + 'static,
+) {
+    let Some(state_lock) = w_state.upgrade() else {
+        return;
+    };
+    let w_mainui = w_mainui.clone();
+    ASYNC_RT
+        .spawn(async move {
+            let mut state = state_lock.write().await;
+            if let Some(mainui) = w_mainui.upgrade() {
+                callback(&mut state, &mainui);
+            }
+        })
+        .detach();
+}
 
-    browse_state.on_begin_reload_all_tracks({
-        let w_state = Arc::downgrade(&state);
-        let w_mainui = mainui.as_weak();
-        move || reload_music_files(w_state.clone(), w_mainui.clone())
+/// Change the playlists from a UI callback, then save them and update the UI.
+/// An error is shown on the playlists page.
+fn edit_playlists(
+    w_state: &ArcWeak<smol::lock::RwLock<MioPlaysState>>,
+    w_mainui: &SlintWeak<MainWindow>,
+    edit: impl FnOnce(&mut MioPlaysState) -> Result<(), String> + Send + 'static,
+) {
+    with_state(w_state, w_mainui, move |state, mainui| {
+        let ret = edit(state).and_then(|()| {
+            state
+                .playlists
+                .save()
+                .map_err(|err| format!("Could not save playlists: {err}"))
+        });
+        let browse_state = mainui.global::<MainBrowsingState>();
+        browse_state.set_playlist_error(ret.err().unwrap_or_default().into());
+        state.set_playlists(mainui);
+    });
+}
+
+/// The playlist at an index from the UI.
+fn playlist_at(state: &mut MioPlaysState, idx: i32) -> Result<&mut library::Playlist, String> {
+    usize::try_from(idx)
+        .ok()
+        .and_then(|x| state.playlists.playlists.get_mut(x))
+        .ok_or_else(|| "No such playlist".to_owned())
+}
+
+/// Change the smart playlists from a UI callback, then evaluate and save them
+/// and update the UI.
+fn edit_smart_playlists(
+    w_state: &ArcWeak<smol::lock::RwLock<MioPlaysState>>,
+    w_mainui: &SlintWeak<MainWindow>,
+    edit: impl FnOnce(&mut MioPlaysState) -> Result<(), String> + Send + 'static,
+) {
+    with_state(w_state, w_mainui, move |state, mainui| {
+        let ret = edit(state).and_then(|()| {
+            state
+                .smart_playlists
+                .save()
+                .map_err(|err| format!("Could not save smart playlists: {err}"))
+        });
+        state.smart_results = state.smart_playlists.evaluate(&state.tracks.0);
+        let browse_state = mainui.global::<MainBrowsingState>();
+        browse_state.set_smart_playlist_error(ret.err().unwrap_or_default().into());
+        state.set_smart_playlists(mainui);
+    });
+}
+
+/// The smart playlist at an index from the UI.
+fn smart_playlist_at(
+    state: &mut MioPlaysState,
+    idx: i32,
+) -> Result<&mut library::SmartPlaylist, String> {
+    usize::try_from(idx)
+        .ok()
+        .and_then(|x| state.smart_playlists.playlists.get_mut(x))
+        .ok_or_else(|| "No such playlist".to_owned())
+}
+
+/// Change the queue from a UI callback, then save it and update the UI. With
+/// `play`, the current track then plays, from where the queue says.
+fn edit_queue(
+    w_state: &ArcWeak<smol::lock::RwLock<MioPlaysState>>,
+    w_mainui: &SlintWeak<MainWindow>,
+    play: bool,
+    edit: impl FnOnce(&mut MioPlaysState) + Send + 'static,
+) {
+    with_state(w_state, w_mainui, move |state, mainui| {
+        if play {
+            state.save_position();
+        }
+        edit(state);
+        if play {
+            state.failures = 0;
+            state.play_current();
+        }
+        state.preload_next();
+        let saved = state.queue.save();
+        state.report_save("the queue", saved);
+        state.set_queue(mainui);
+    });
+}
+
+/// Change the playback settings from a UI callback, then save them and pass
+/// them on to the player. DSP settings are saved for the current output.
+fn edit_playback_options(
+    w_state: &ArcWeak<smol::lock::RwLock<MioPlaysState>>,
+    w_mainui: &SlintWeak<MainWindow>,
+    edit: impl FnOnce(&mut playback::PlaybackOptions) + Send + 'static,
+) {
+    with_state(w_state, w_mainui, move |state, mainui| {
+        let dsp = state.settings.playback.dsp.clone();
+        edit(&mut state.settings.playback);
+        if state.settings.playback.dsp != dsp {
+            let (output, dsp) = (state.output.clone(), state.settings.playback.dsp.clone());
+            state.settings.dsp.insert(output, dsp);
+        }
+        let saved = state.settings.save();
+        state.report_save("settings", saved);
+        if let Some(player) = &state.player {
+            player.send(playback::Command::SetOptions(
+                state.settings.playback.clone(),
+            ));
+        }
+        state.set_playback_options(mainui);
+        let playing_state = mainui.global::<PlayingState>();
+        playing_state.set_player_error(state.player_error.as_str().into());
+    });
+}
+
+/// Measure the loudness of everything without ReplayGain tags in the
+/// background, album by album, keeping the results and optionally writing
+/// them into the files. Progress is shown on the playback page.
+fn analyse_loudness(
+    w_state: &ArcWeak<smol::lock::RwLock<MioPlaysState>>,
+    w_mainui: &SlintWeak<MainWindow>,
+    write_tags: bool,
+) {
+    // decoding is heavy, so keep to one file per core
+    static ANALYSING_THREADS: LazyLock<smol::lock::Semaphore> = LazyLock::new(|| {
+        smol::lock::Semaphore::new(
+            std::thread::available_parallelism()
+                .map(|x| x.get())
+                .unwrap_or(1),
+        )
     });
 
-    drop(browse_state);
+    let Some(state_lock) = w_state.upgrade() else {
+        return;
+    };
+    let w_mainui = w_mainui.clone();
+    ASYNC_RT
+        .spawn(async move {
+            let jobs = {
+                let mut state = state_lock.write().await;
+                if state.analysing {
+                    return;
+                }
+                state.analysing = true;
+                library::loudness_jobs(&state.tracks.0, &state.albums)
+            };
+            let report = |progress: f32, status: String, analysing: bool| {
+                let _ = w_mainui.upgrade_in_event_loop(move |mainui| {
+                    let playing_state = mainui.global::<PlayingState>();
+                    playing_state.set_analysing(analysing);
+                    playing_state.set_analysis_progress(progress);
+                    playing_state.set_analysis_status(status.into());
+                });
+            };
+            let total = jobs.iter().map(|x| x.tracks.len()).sum::<usize>();
+            report(0.0, format!("Analysing {total} tracks"), true);
+
+            // start on every track at once, so that the cores stay busy
+            // across albums, and finish them album by album
+            let jobs = jobs
+                .into_iter()
+                .map(|job| {
+                    let tasks = job
+                        .tracks
+                        .into_iter()
+                        .map(|path| {
+                            ASYNC_RT.spawn(async move {
+                                let _lock = ANALYSING_THREADS.acquire().await;
+                                let ret = smol::unblock({
+                                    let path = path.clone();
+                                    move || audio::measure_file(&path)
+                                })
+                                .await;
+                                (path, ret)
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    (tasks, job.is_album)
+                })
+                .collect::<Vec<_>>();
+
+            let (mut done, mut failed, mut unwritten) = (0, 0, 0);
+            let mut unsaved = None;
+            for (tasks, is_album) in jobs {
+                let mut measurements = vec![];
+                for task in tasks {
+                    match task.await {
+                        (path, Ok(x)) => measurements.push((path, x)),
+                        (_, Err(_)) => failed += 1,
+                    }
+                    done += 1;
+                }
+                let results = library::TrackLoudness::of_album(&measurements, is_album);
+                if write_tags {
+                    let written = smol::unblock({
+                        let results = results.clone();
+                        move || {
+                            results
+                                .iter()
+                                .filter(|(path, x)| x.write_tags(path).is_ok())
+                                .count()
+                        }
+                    })
+                    .await;
+                    unwritten += results.len() - written;
+                }
+
+                let mut state = state_lock.write().await;
+                for (path, loudness) in results {
+                    if let Some(x) = state.by_path.get(&path).copied() {
+                        state.tracks.0[x].loudness = Some(loudness);
+                    }
+                    state.loudness.insert(path, loudness);
+                }
+                if let Err(err) = state.loudness.save() {
+                    unsaved = Some(err);
+                }
+                drop(state);
+                report(
+                    done as f32 / total as f32,
+                    format!("Analysed {done} of {total} tracks"),
+                    true,
+                );
+            }
+
+            state_lock.write().await.analysing = false;
+            let mut status = format!("Analysed {} tracks", total - failed);
+            if failed > 0 {
+                status += &format!(", {failed} could not be read");
+            }
+            if unwritten > 0 {
+                status += &format!(", {unwritten} could not be tagged");
+            }
+            if let Some(err) = unsaved {
+                status += &format!(", could not save the results: {err}");
+            }
+            report(1.0, status, false);
+        })
+        .detach();
+}
+
+/// Keep the queue in step with the player. Events may be about a track that
+/// was since replaced on request, and then they are ignored.
+fn player_event(
+    w_state: &ArcWeak<smol::lock::RwLock<MioPlaysState>>,
+    w_mainui: &SlintWeak<MainWindow>,
+    event: playback::Event,
+) {
+    /// Give up skipping over tracks that cannot be played after this many,
+    /// eg. when a drive is gone.
+    const MAX_FAILURES: usize = 20;
+
+    let Some(state_lock) = w_state.upgrade() else {
+        return;
+    };
+    let w_mainui = w_mainui.clone();
+    ASYNC_RT
+        .spawn(async move {
+            let mut state = state_lock.write().await;
+            let is_current =
+                |state: &MioPlaysState, path: &std::path::Path| state.queue.current() == Some(path);
+            match event {
+                // often, so only the position is updated
+                playback::Event::Position { path, at, duration } => {
+                    if !is_current(&state, &path) {
+                        return;
+                    }
+                    state.position = at;
+                    state.duration = duration;
+                    let view = state.position_view();
+                    let _ = w_mainui.upgrade_in_event_loop(move |mainui| view.show(&mainui));
+                    return;
+                }
+                playback::Event::Error(err) => {
+                    state.player_error = err;
+                    let view = state.queue_view();
+                    let _ = w_mainui.upgrade_in_event_loop(move |mainui| view.show(&mainui));
+                    return;
+                }
+                playback::Event::Started(path) => {
+                    if is_current(&state, &path) {
+                        state.failures = 0;
+                        state.player_error.clear();
+                    }
+                }
+                playback::Event::Advanced { from, to } => {
+                    if !is_current(&state, &from) {
+                        return;
+                    }
+                    state.failures = 0;
+                    state.player_error.clear();
+                    state.finish_current();
+                    // the queue changed after the player got ready
+                    if !is_current(&state, &to) {
+                        state.play_current();
+                    }
+                }
+                playback::Event::Ended(path) => {
+                    if !is_current(&state, &path) {
+                        return;
+                    }
+                    state.finish_current();
+                    state.play_current();
+                }
+                playback::Event::Failed(path, err) => {
+                    if !is_current(&state, &path) {
+                        return;
+                    }
+                    state.player_error = format!("Could not play {}: {err}", path.display());
+                    state.failures += 1;
+                    if state.failures < MAX_FAILURES {
+                        state.queue.next();
+                        state.play_current();
+                    } else {
+                        state.loaded = false;
+                    }
+                }
+            }
+            state.preload_next();
+            let saved = state.queue.save();
+            state.report_save("the queue", saved);
+            // never wait for the state on the event loop, which runs the
+            // tasks that hold it
+            let view = state.queue_view();
+            let _ = w_mainui.upgrade_in_event_loop(move |mainui| view.show(&mainui));
+        })
+        .detach();
+}
+
+/// The tracks of an album from the UI, from the track `from` on if it is set.
+fn album_tracks(state: &MioPlaysState, album: i32, from: Option<i32>) -> Vec<usize> {
+    let tracks = usize::try_from(album)
+        .ok()
+        .and_then(|x| state.albums.get(x))
+        .map(|x| x.tracks.as_slice())
+        .unwrap_or_default();
+    let start = from
+        .and_then(|from| tracks.iter().position(|x| *x as i32 == from))
+        .unwrap_or(0);
+    tracks[start..].to_vec()
+}
+
+fn main() {
+    let state = Arc::new(smol::lock::RwLock::new(MioPlaysState::new()));
+    let mainui = MainWindow::new().unwrap();
+    let player = playback::Engine::spawn(playback::DeviceSink::default, {
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |event| player_event(&w_state, &w_mainui, event)
+    });
+    player.send(playback::Command::SetOptions(
+        state.read_blocking().settings.playback.clone(),
+    ));
+    state.write_blocking().player = Some(player);
+    let browse_state = mainui.global::<MainBrowsingState>();
+
+    browse_state.on_begin_reload_all_tracks({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move || reload_music_files(w_state.clone(), w_mainui.clone())
+    });
+
+    browse_state.on_open_album({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx| {
+            with_state(&w_state, &w_mainui, move |state, mainui| {
+                let Some(album) = usize::try_from(idx).ok() else {
+                    return;
+                };
+                let page = state.make_album_page(album);
+                let browse_state = mainui.global::<MainBrowsingState>();
+                browse_state.set_album_page(page);
+                browse_state.set_album_open(true);
+
+                // the cover is read from the file, so load it in the background
+                let Some(path) = state
+                    .albums
+                    .get(album)
+                    .map(|x| state.tracks.0[x.tracks[0]].path.clone())
+                else {
+                    return;
+                };
+                load_art(mainui, path, move |mainui, art| {
+                    let browse_state = mainui.global::<MainBrowsingState>();
+                    let mut page = browse_state.get_album_page();
+                    // another album may have been opened in the meantime
+                    if page.id == idx {
+                        page.album_art = art;
+                        browse_state.set_album_page(page);
+                    }
+                });
+            })
+        }
+    });
+
+    browse_state.on_open_artist({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx| {
+            with_state(&w_state, &w_mainui, move |state, mainui| {
+                let Some(artist) = usize::try_from(idx).ok() else {
+                    return;
+                };
+                let page = state.make_artist_page(artist);
+                let browse_state = mainui.global::<MainBrowsingState>();
+                browse_state.set_artist_page(page);
+                browse_state.set_artist_open(true);
+            })
+        }
+    });
+
+    browse_state.on_open_genre({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx| {
+            with_state(&w_state, &w_mainui, move |state, mainui| {
+                let Some(genre) = usize::try_from(idx).ok() else {
+                    return;
+                };
+                let page = state.make_genre_page(genre);
+                let browse_state = mainui.global::<MainBrowsingState>();
+                browse_state.set_genre_page(page);
+                browse_state.set_genre_open(true);
+            })
+        }
+    });
+
+    browse_state.on_search({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |query| {
+            with_state(&w_state, &w_mainui, move |state, mainui| {
+                let ([artists, albums, tracks], error) = state.search(&query);
+                let browse_state = mainui.global::<MainBrowsingState>();
+                browse_state.set_search_error(error.unwrap_or_default().into());
+                browse_state
+                    .set_search_artists(slint::ModelRc::new(slint::VecModel::from(artists)));
+                browse_state.set_search_albums(slint::ModelRc::new(slint::VecModel::from(albums)));
+                browse_state.set_search_tracks(slint::ModelRc::new(slint::VecModel::from(tracks)));
+            })
+        }
+    });
+
+    browse_state.on_new_playlist({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |name| {
+            edit_playlists(&w_state, &w_mainui, move |state| {
+                let name = name.trim();
+                if name.is_empty() {
+                    return Err("Give the playlist a name".to_owned());
+                }
+                let playlist = library::Playlist::new(name.to_owned());
+                state.playlists.playlists.push(playlist);
+                Ok(())
+            })
+        }
+    });
+
+    browse_state.on_delete_playlist({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx| {
+            edit_playlists(&w_state, &w_mainui, move |state| {
+                playlist_at(state, idx)?;
+                state.playlists.playlists.remove(idx as usize);
+                Ok(())
+            })
+        }
+    });
+
+    browse_state.on_add_album_to_playlist({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx, album| {
+            edit_playlists(&w_state, &w_mainui, move |state| {
+                let tracks = usize::try_from(album)
+                    .ok()
+                    .and_then(|x| state.albums.get(x))
+                    .map(|x| x.tracks.clone())
+                    .unwrap_or_default();
+                let entries = tracks
+                    .into_iter()
+                    .map(|x| library::PlaylistEntry::of(&state.tracks.0[x]))
+                    .collect::<Vec<_>>();
+                playlist_at(state, idx)?.entries.extend(entries);
+                Ok(())
+            })
+        }
+    });
+
+    browse_state.on_add_track_to_playlist({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx, track| {
+            edit_playlists(&w_state, &w_mainui, move |state| {
+                let entry = usize::try_from(track)
+                    .ok()
+                    .and_then(|x| state.tracks.0.get(x))
+                    .map(library::PlaylistEntry::of)
+                    .ok_or_else(|| "No such track".to_owned())?;
+                playlist_at(state, idx)?.entries.push(entry);
+                Ok(())
+            })
+        }
+    });
+
+    browse_state.on_remove_from_playlist({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx, pos| {
+            edit_playlists(&w_state, &w_mainui, move |state| {
+                playlist_at(state, idx)?.remove(pos.try_into().unwrap_or(usize::MAX));
+                Ok(())
+            })
+        }
+    });
+
+    browse_state.on_move_in_playlist({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx, from, to| {
+            edit_playlists(&w_state, &w_mainui, move |state| {
+                let (Ok(from), Ok(to)) = (usize::try_from(from), usize::try_from(to)) else {
+                    return Ok(());
+                };
+                playlist_at(state, idx)?.move_entry(from, to);
+                Ok(())
+            })
+        }
+    });
+
+    browse_state.on_import_playlist({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |path| {
+            edit_playlists(&w_state, &w_mainui, move |state| {
+                let path = settings::expand_home(std::path::Path::new(path.as_str()));
+                let mut playlist = library::Playlist::import_m3u(&path)
+                    .map_err(|err| format!("Could not read {}: {err}", path.display()))?;
+                playlist.relink(&library::TrackLookup::new(&state.tracks.0));
+                state.playlists.playlists.push(playlist);
+                Ok(())
+            })
+        }
+    });
+
+    browse_state.on_export_playlist({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx, path, relative| {
+            edit_playlists(&w_state, &w_mainui, move |state| {
+                let path = settings::expand_home(std::path::Path::new(path.as_str()));
+                playlist_at(state, idx)?
+                    .export_m3u(&path, relative)
+                    .map_err(|err| format!("Could not write {}: {err}", path.display()))
+            })
+        }
+    });
+
+    browse_state.on_save_smart_playlist({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |name, query, sort, descending, limit| {
+            edit_smart_playlists(&w_state, &w_mainui, move |state| {
+                let name = match name.trim() {
+                    "" => query.trim(),
+                    x => x,
+                };
+                // 0 is library order
+                let sort = usize::try_from(sort - 1)
+                    .ok()
+                    .and_then(|x| library::SortField::ALL.get(x))
+                    .copied();
+                let limit = match limit.trim() {
+                    "" => None,
+                    x => Some(
+                        x.parse::<usize>()
+                            .ok()
+                            .filter(|x| *x > 0)
+                            .ok_or_else(|| format!("Invalid limit '{x}'"))?,
+                    ),
+                };
+                state
+                    .smart_playlists
+                    .playlists
+                    .push(library::SmartPlaylist {
+                        name: name.to_owned(),
+                        query: query.into(),
+                        sort,
+                        descending,
+                        limit,
+                    });
+                Ok(())
+            })
+        }
+    });
+
+    browse_state.on_rename_smart_playlist({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx, name| {
+            edit_smart_playlists(&w_state, &w_mainui, move |state| {
+                let name = name.trim();
+                if name.is_empty() {
+                    return Err("Give the playlist a name".to_owned());
+                }
+                smart_playlist_at(state, idx)?.name = name.to_owned();
+                Ok(())
+            })
+        }
+    });
+
+    browse_state.on_delete_smart_playlist({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx| {
+            edit_smart_playlists(&w_state, &w_mainui, move |state| {
+                smart_playlist_at(state, idx)?;
+                state.smart_playlists.playlists.remove(idx as usize);
+                Ok(())
+            })
+        }
+    });
+
+    let playing_state = mainui.global::<PlayingState>();
+    playing_state.set_speed(state.read_blocking().settings.spoken_speed);
+    playing_state.on_set_speed({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |speed| {
+            with_state(&w_state, &w_mainui, move |state, mainui| {
+                state.settings.spoken_speed = speed;
+                let saved = state.settings.save();
+                state.report_save("settings", saved);
+                // music stays at normal speed
+                if let Some(path) = state.queue.current()
+                    && state.shuffle_key(path).spoken
+                    && let Some(player) = &state.player
+                {
+                    player.send(playback::Command::SetSpeed(speed));
+                }
+                state.preload_next();
+                let playing_state = mainui.global::<PlayingState>();
+                playing_state.set_speed(speed);
+                playing_state.set_player_error(state.player_error.as_str().into());
+            })
+        }
+    });
+    playing_state.set_queue(slint::ModelRc::new(slint::VecModel::<TrackItem>::default()));
+    state.read_blocking().set_playback_options(&mainui);
+
+    playing_state.on_set_crossfade({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |secs| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.crossfade.secs = secs.max(0.0);
+            })
+        }
+    });
+
+    playing_state.on_set_fade_curve({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |curve| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.crossfade.curve = match curve {
+                    0 => playback::FadeCurve::Linear,
+                    2 => playback::FadeCurve::SCurve,
+                    _ => playback::FadeCurve::EqualPower,
+                };
+            })
+        }
+    });
+
+    playing_state.on_set_trim_silence({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |trim| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.trim_silence = trim
+            })
+        }
+    });
+
+    playing_state.on_set_gain_mode({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |mode| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.normalization.mode = match mode {
+                    0 => playback::GainMode::Off,
+                    2 => playback::GainMode::Album,
+                    _ => playback::GainMode::Track,
+                };
+            })
+        }
+    });
+
+    playing_state.on_set_preamp({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |db| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.normalization.preamp = db.clamp(-15.0, 15.0);
+            })
+        }
+    });
+
+    playing_state.on_set_fallback_gain({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |db| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.normalization.fallback = db.clamp(-15.0, 15.0);
+            })
+        }
+    });
+
+    playing_state.on_set_prevent_clipping({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |prevent| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.normalization.prevent_clipping = prevent;
+            })
+        }
+    });
+
+    playing_state.on_enter({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |track| {
+            edit_queue(&w_state, &w_mainui, true, move |state| {
+                let tracks = state.track_paths(&[track as usize]);
+                state.queue.play_now(tracks);
+            })
+        }
+    });
+
+    playing_state.on_enter_at({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |track, secs| {
+            edit_queue(&w_state, &w_mainui, true, move |state| {
+                let tracks = state.track_paths(&[track as usize]);
+                let at = std::time::Duration::from_secs_f32(secs.max(0.0));
+                state.queue.play_now_at(tracks, at);
+            })
+        }
+    });
+
+    playing_state.on_play_album({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |album| {
+            edit_queue(&w_state, &w_mainui, true, move |state| {
+                let tracks = album_tracks(state, album, None);
+                state.play_tracks(&tracks, false);
+            })
+        }
+    });
+
+    playing_state.on_play_from({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |album, track| {
+            edit_queue(&w_state, &w_mainui, true, move |state| {
+                let tracks = album_tracks(state, album, Some(track));
+                state.play_tracks(&tracks, true);
+            })
+        }
+    });
+
+    playing_state.on_play_album_next({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |album| {
+            edit_queue(&w_state, &w_mainui, false, move |state| {
+                let tracks = state.track_paths(&album_tracks(state, album, None));
+                state.queue.play_next(tracks);
+            })
+        }
+    });
+
+    playing_state.on_enqueue_album({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |album| {
+            edit_queue(&w_state, &w_mainui, false, move |state| {
+                let tracks = state.track_paths(&album_tracks(state, album, None));
+                state.queue.add(tracks);
+            })
+        }
+    });
+
+    playing_state.on_play_playlist({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx, pos| {
+            edit_queue(&w_state, &w_mainui, true, move |state| {
+                let Some(playlist) = usize::try_from(idx)
+                    .ok()
+                    .and_then(|x| state.playlists.playlists.get(x))
+                else {
+                    return;
+                };
+                let lookup = library::TrackLookup::new(&state.tracks.0);
+                let tracks = playlist
+                    .resolve(&lookup)
+                    .into_iter()
+                    .skip(pos.max(0) as usize)
+                    .flatten()
+                    .collect::<Vec<_>>();
+                state.play_tracks(&tracks, true);
+            })
+        }
+    });
+
+    playing_state.on_play_smart_playlist({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx, track| {
+            edit_queue(&w_state, &w_mainui, true, move |state| {
+                let Some(Ok(tracks)) = usize::try_from(idx)
+                    .ok()
+                    .and_then(|x| state.smart_results.get(x))
+                else {
+                    return;
+                };
+                let start = tracks.iter().position(|x| *x as i32 == track).unwrap_or(0);
+                let tracks = tracks[start..].to_vec();
+                state.play_tracks(&tracks, true);
+            })
+        }
+    });
+
+    playing_state.on_next({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move || {
+            edit_queue(&w_state, &w_mainui, true, move |state| {
+                state.queue.next();
+            })
+        }
+    });
+
+    playing_state.on_previous({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move || {
+            edit_queue(&w_state, &w_mainui, true, move |state| {
+                state.queue.previous();
+            })
+        }
+    });
+
+    playing_state.on_skip_to({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |pos| {
+            edit_queue(&w_state, &w_mainui, true, move |state| {
+                state.queue.skip_to(pos.try_into().unwrap_or(usize::MAX));
+            })
+        }
+    });
+
+    playing_state.on_move_in_queue({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |from, to| {
+            edit_queue(&w_state, &w_mainui, false, move |state| {
+                if let (Ok(from), Ok(to)) = (usize::try_from(from), usize::try_from(to)) {
+                    state.queue.move_entry(from, to);
+                }
+            })
+        }
+    });
+
+    playing_state.on_remove_from_queue({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |pos| {
+            edit_queue(&w_state, &w_mainui, false, move |state| {
+                state.queue.remove(pos.try_into().unwrap_or(usize::MAX));
+            })
+        }
+    });
+
+    playing_state.on_set_shuffle({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |mode| {
+            edit_queue(&w_state, &w_mainui, false, move |state| {
+                state.queue.shuffle = match mode {
+                    1 => playback::ShuffleMode::Tracks,
+                    2 => playback::ShuffleMode::Albums,
+                    3 => playback::ShuffleMode::Smart,
+                    _ => playback::ShuffleMode::Off,
+                };
+                let mut queue = std::mem::take(&mut state.queue);
+                queue.shuffle_upcoming(|x| state.shuffle_key(x), &mut rand::rng());
+                state.queue = queue;
+            })
+        }
+    });
+
+    playing_state.on_set_repeat({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |mode| {
+            edit_queue(&w_state, &w_mainui, false, move |state| {
+                state.queue.repeat = match mode {
+                    1 => playback::RepeatMode::All,
+                    2 => playback::RepeatMode::One,
+                    _ => playback::RepeatMode::Off,
+                };
+            })
+        }
+    });
+
+    playing_state.on_play({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move || {
+            with_state(&w_state, &w_mainui, |state, mainui| {
+                // after a restart, the current track is not loaded yet
+                if !state.loaded {
+                    state.play_current();
+                    state.preload_next();
+                } else if let Some(player) = &state.player {
+                    player.send(playback::Command::Resume);
+                    state.paused = false;
+                }
+                state.set_queue(mainui);
+            })
+        }
+    });
+
+    playing_state.on_pause({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move || {
+            with_state(&w_state, &w_mainui, |state, mainui| {
+                if let Some(player) = &state.player {
+                    player.send(playback::Command::Pause);
+                    state.paused = true;
+                }
+                state.save_position();
+                state.set_queue(mainui);
+            })
+        }
+    });
+
+    playing_state.on_set_volume({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |volume| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.volume = volume.clamp(0.0, 1.0);
+            })
+        }
+    });
+
+    playing_state.on_set_bit_perfect({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |on| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.output.bit_perfect = on;
+            })
+        }
+    });
+
+    playing_state.on_set_resample_quality({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |quality| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.output.quality = match quality {
+                    0 => playback::ResampleQuality::Balanced,
+                    _ => playback::ResampleQuality::Best,
+                };
+            })
+        }
+    });
+
+    playing_state.on_set_dither({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |on| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.output.dither = on
+            })
+        }
+    });
+
+    playing_state.on_set_equalizer({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |on| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.dsp.equalizer = on
+            })
+        }
+    });
+
+    playing_state.on_set_eq_preamp({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |db| edit_playback_options(&w_state, &w_mainui, move |options| options.dsp.preamp = db)
+    });
+
+    playing_state.on_set_eq_band({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx, kind, freq, gain, q| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                let Some(band) = usize::try_from(idx)
+                    .ok()
+                    .and_then(|x| options.dsp.bands.get_mut(x))
+                else {
+                    return;
+                };
+                *band = playback::EqBand {
+                    kind: match kind {
+                        1 => playback::FilterKind::LowShelf,
+                        2 => playback::FilterKind::HighShelf,
+                        3 => playback::FilterKind::LowPass,
+                        4 => playback::FilterKind::HighPass,
+                        _ => playback::FilterKind::Peak,
+                    },
+                    freq,
+                    gain,
+                    q,
+                };
+            })
+        }
+    });
+
+    playing_state.on_add_eq_band({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move || {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.dsp.bands.push(playback::EqBand {
+                    kind: playback::FilterKind::Peak,
+                    freq: 1000.0,
+                    gain: 0.0,
+                    q: std::f32::consts::SQRT_2,
+                });
+            })
+        }
+    });
+
+    playing_state.on_remove_eq_band({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                if let Ok(idx) = usize::try_from(idx)
+                    && idx < options.dsp.bands.len()
+                {
+                    options.dsp.bands.remove(idx);
+                }
+            })
+        }
+    });
+
+    playing_state.on_apply_eq_preset({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |idx| {
+            let Some(preset) = usize::try_from(idx)
+                .ok()
+                .and_then(|x| playback::EQ_PRESETS.get(x))
+            else {
+                return;
+            };
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.dsp.equalizer = true;
+                options.dsp.bands = preset.bands();
+            })
+        }
+    });
+
+    playing_state.on_set_balance({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |balance| {
+            edit_playback_options(&w_state, &w_mainui, move |options| {
+                options.dsp.balance = balance
+            })
+        }
+    });
+
+    playing_state.on_set_mono({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |mono| {
+            edit_playback_options(&w_state, &w_mainui, move |options| options.dsp.mono = mono)
+        }
+    });
+
+    playing_state.on_analyse_loudness({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |write_tags| analyse_loudness(&w_state, &w_mainui, write_tags)
+    });
+
+    playing_state.on_seek({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |secs| {
+            with_state(&w_state, &w_mainui, move |state, mainui| {
+                state.seek(std::time::Duration::from_secs_f32(secs.max(0.0)));
+                state.set_position(mainui);
+            })
+        }
+    });
+
+    playing_state.on_seek_by({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move |secs| {
+            with_state(&w_state, &w_mainui, move |state, mainui| {
+                if state.queue.current().is_none() {
+                    return;
+                }
+                let at = (state.position.as_secs_f32() + secs).max(0.0);
+                state.seek(std::time::Duration::from_secs_f32(at));
+                state.set_position(mainui);
+            })
+        }
+    });
+
+    playing_state.on_clear_queue({
+        let w_state = Arc::downgrade(&state);
+        let w_mainui = mainui.as_weak();
+        move || edit_queue(&w_state, &w_mainui, false, move |state| state.queue.clear())
+    });
 
     let rt_thread = slint::spawn_local(async {
         loop {
@@ -160,4 +2405,5 @@ fn main() {
     .unwrap();
     mainui.run().unwrap();
     rt_thread.abort();
+    state.write_blocking().save_position();
 }
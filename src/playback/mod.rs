@@ -0,0 +1,21 @@
+mod playback_convert;
+mod playback_decode;
+mod playback_dsp;
+mod playback_engine;
+mod playback_fade;
+mod playback_gain;
+mod playback_queue;
+mod playback_shuffle;
+mod playback_sink;
+mod playback_stretch;
+
+pub use playback_convert::*;
+pub use playback_decode::*;
+pub use playback_dsp::*;
+pub use playback_engine::*;
+pub use playback_fade::*;
+pub use playback_gain::*;
+pub use playback_queue::*;
+pub use playback_shuffle::*;
+pub use playback_sink::*;
+pub use playback_stretch::*;
@@ -0,0 +1,407 @@
+use rubato::{
+    Resampler as _, SincFixedIn, SincInterpolationParameters, SincInterpolationType, WindowFunction,
+};
+use serde::{Deserialize, Serialize};
+
+use super::PlaybackError;
+
+/// How much work to put into resampling.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResampleQuality {
+    /// Cheaper, with a little roll-off at the very top
+    Balanced,
+    /// Flat up to about 20 kHz, with no audible aliasing
+    #[default]
+    Best,
+}
+
+/// How audio gets to the output device.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputOptions {
+    /// Open the device at the sample rate of each file where it supports it,
+    /// and at its deepest sample format, instead of converting to its
+    /// default format
+    pub bit_perfect: bool,
+    pub quality: ResampleQuality,
+    /// Add noise when reducing the bit depth, which hides the distortion
+    /// that plain rounding causes in quiet passages
+    pub dither: bool,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            bit_perfect: false,
+            quality: ResampleQuality::Best,
+            dither: true,
+        }
+    }
+}
+
+/// Converts interleaved samples from one sample rate to another. Input is
+/// gathered into fixed chunks, so that the stream stays continuous across
+/// writes.
+pub struct Resampler {
+    inner: SincFixedIn<f32>,
+    ratio: f64,
+    channels: usize,
+    /// Input not yet resampled, per channel
+    pending: Vec<Vec<f32>>,
+    /// Output frames still to drop, as the filter delays its output
+    delay: usize,
+    initial_delay: usize,
+    /// Input frames so far, and output frames given out, to tell how much
+    /// more there is at the end
+    frames_in: usize,
+    frames_out: usize,
+}
+
+impl Resampler {
+    /// Input frames per chunk
+    const CHUNK: usize = 1024;
+
+    pub fn new(
+        from: u32,
+        to: u32,
+        channels: usize,
+        quality: ResampleQuality,
+    ) -> Result<Self, PlaybackError> {
+        let ratio = to as f64 / from as f64;
+        let parameters = || match quality {
+            ResampleQuality::Balanced => SincInterpolationParameters {
+                sinc_len: 64,
+                f_cutoff: 0.9,
+                oversampling_factor: 128,
+                interpolation: SincInterpolationType::Linear,
+                window: WindowFunction::Blackman2,
+            },
+            ResampleQuality::Best => SincInterpolationParameters {
+                sinc_len: 256,
+                f_cutoff: 0.95,
+                oversampling_factor: 256,
+                interpolation: SincInterpolationType::Cubic,
+                window: WindowFunction::BlackmanHarris2,
+            },
+        };
+        let make = |channels| {
+            SincFixedIn::new(ratio, 1.0, parameters(), Self::CHUNK, channels)
+                .map_err(|err| PlaybackError::Device(err.to_string()))
+        };
+
+        // the delay rubato reports is a few frames off, so find it from
+        // where an impulse comes out instead
+        let mut probe = make(1)?;
+        let mut impulse = vec![0.0f32; Self::CHUNK];
+        impulse[0] = 1.0;
+        let out = probe
+            .process(&[impulse], None)
+            .map_err(|err| PlaybackError::Device(err.to_string()))?;
+        let delay = (0..out[0].len())
+            .max_by(|a, b| out[0][*a].total_cmp(&out[0][*b]))
+            .unwrap_or_default();
+
+        Ok(Self {
+            inner: make(channels)?,
+            delay,
+            initial_delay: delay,
+            ratio,
+            channels,
+            pending: vec![vec![]; channels],
+            frames_in: 0,
+            frames_out: 0,
+        })
+    }
+
+    /// Resample what there is enough input for.
+    pub fn process(&mut self, samples: &[f32]) -> Result<Vec<f32>, PlaybackError> {
+        for frame in samples.chunks_exact(self.channels) {
+            for (c, x) in frame.iter().enumerate() {
+                self.pending[c].push(*x);
+            }
+        }
+        self.frames_in += samples.len() / self.channels;
+        let mut ret = vec![];
+        while self.pending[0].len() >= Self::CHUNK {
+            let out = self
+                .inner
+                .process(&self.pending, None)
+                .map_err(|err| PlaybackError::Device(err.to_string()))?;
+            self.pending.iter_mut().for_each(|x| {
+                x.drain(..Self::CHUNK);
+            });
+            self.output(&out, usize::MAX, &mut ret);
+        }
+        Ok(ret)
+    }
+
+    /// Resample whatever is left, as the end of the stream, and start over.
+    pub fn flush(&mut self) -> Result<Vec<f32>, PlaybackError> {
+        let total = (self.frames_in as f64 * self.ratio).round() as usize;
+        let mut ret = vec![];
+        // the pending input first, then zeros to push the delayed tail out
+        let mut input = Some(std::mem::take(&mut self.pending));
+        while self.frames_out < total {
+            let out = self
+                .inner
+                .process_partial(input.take().as_deref(), None)
+                .map_err(|err| PlaybackError::Device(err.to_string()))?;
+            if out[0].is_empty() {
+                break;
+            }
+            self.output(&out, total, &mut ret);
+        }
+        self.reset();
+        Ok(ret)
+    }
+
+    /// Drop the stream so far.
+    pub fn reset(&mut self) {
+        self.inner.reset();
+        self.pending.iter_mut().for_each(Vec::clear);
+        self.delay = self.initial_delay;
+        self.frames_in = 0;
+        self.frames_out = 0;
+    }
+
+    /// Interleave output, past the delay and up to `total` frames in all.
+    fn output(&mut self, out: &[Vec<f32>], total: usize, ret: &mut Vec<f32>) {
+        let skip = self.delay.min(out[0].len());
+        self.delay -= skip;
+        let frames = (out[0].len() - skip).min(total - self.frames_out);
+        for x in skip..skip + frames {
+            ret.extend(out.iter().map(|c| c[x]));
+        }
+        self.frames_out += frames;
+    }
+}
+
+/// Mixes interleaved samples from one channel count to another, for devices
+/// that cannot take the audio's own layout. Channels are taken to be in the
+/// usual order: front left, front right, centre, LFE, back left and right,
+/// then side left and right.
+pub struct ChannelMixer {
+    from: usize,
+    to: usize,
+    /// How much of each input channel goes into each output channel, by
+    /// output then input
+    matrix: Vec<Vec<f32>>,
+}
+
+impl ChannelMixer {
+    pub fn new(from: usize, to: usize) -> Self {
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let mut matrix = vec![vec![0.0; from]; to];
+        if from == 1 {
+            // mono goes to both front speakers
+            for row in matrix.iter_mut().take(2) {
+                row[0] = 1.0;
+            }
+        } else if from > 2 && to <= 2 {
+            // fold the centre and surrounds into the front pair, dropping
+            // the LFE, and scale down so that the sum cannot clip
+            let mut left = vec![0.0; from];
+            let mut right = vec![0.0; from];
+            left[0] = 1.0;
+            right[1] = 1.0;
+            left[2] = half;
+            right[2] = half;
+            for pair in [4, 6] {
+                if pair + 1 < from {
+                    left[pair] = half;
+                    right[pair + 1] = half;
+                }
+            }
+            let scale = left.iter().sum::<f32>();
+            for x in left.iter_mut().chain(&mut right) {
+                *x /= scale;
+            }
+            Self::fold_front(&mut matrix, left, right);
+        } else if from == 2 && to == 1 {
+            matrix[0] = vec![0.5, 0.5];
+        } else {
+            // the channels both sides have carry over, the rest is silent
+            for (x, row) in matrix.iter_mut().enumerate().take(from) {
+                row[x] = 1.0;
+            }
+        }
+        Self { from, to, matrix }
+    }
+
+    /// Put a stereo mix into `matrix`, or its mean for mono.
+    fn fold_front(matrix: &mut [Vec<f32>], left: Vec<f32>, right: Vec<f32>) {
+        if matrix.len() == 1 {
+            matrix[0] = left
+                .iter()
+                .zip(&right)
+                .map(|(l, r)| (l + r) / 2.0)
+                .collect();
+        } else {
+            matrix[0] = left;
+            matrix[1] = right;
+        }
+    }
+
+    pub fn process(&self, samples: &[f32]) -> Vec<f32> {
+        let mut out = Vec::with_capacity(samples.len() / self.from * self.to);
+        for frame in samples.chunks_exact(self.from) {
+            out.extend(
+                self.matrix
+                    .iter()
+                    .map(|row| row.iter().zip(frame).map(|(x, y)| x * y).sum::<f32>()),
+            );
+        }
+        out
+    }
+}
+
+/// Rounds samples to what an integer sample format can hold, so that the
+/// device does not just truncate them.
+pub struct Quantizer {
+    /// Steps from zero to full scale, or `None` where nothing is lost
+    scale: Option<f32>,
+    dither: bool,
+    /// State of the noise generator
+    noise: u32,
+}
+
+impl Quantizer {
+    /// For a sample format of `bits`, or a float format with `None`.
+    pub fn new(bits: Option<u32>, dither: bool) -> Self {
+        Self {
+            // a float has 24 bits of precision, so only smaller formats
+            // lose any
+            scale: bits.filter(|x| *x < 24).map(|x| (1u32 << (x - 1)) as f32),
+            dither,
+            noise: 0x9e37_79b9,
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let Some(scale) = self.scale else {
+            return;
+        };
+        // audio that already fits, eg. an untouched 16-bit file, stays
+        // bit-perfect
+        let fits = samples.iter().all(|x| (x * scale).fract() == 0.0);
+        let dither = self.dither && !fits;
+        for x in samples {
+            // triangular noise of up to a step either way
+            let noise = if dither {
+                self.uniform() + self.uniform()
+            } else {
+                0.0
+            };
+            *x = ((*x * scale + noise).round().clamp(-scale, scale - 1.0)) / scale;
+        }
+    }
+
+    /// Uniform noise from -0.5 to 0.5, by xorshift.
+    fn uniform(&mut self) -> f32 {
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 17;
+        self.noise ^= self.noise << 5;
+        self.noise as f32 / u32::MAX as f32 - 0.5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resamples_a_sine() {
+        let freq = 1000.0;
+        let input = (0..44100)
+            .flat_map(|x| {
+                let x = (std::f32::consts::TAU * freq * x as f32 / 44100.0).sin() * 0.5;
+                [x, -x]
+            })
+            .collect::<Vec<_>>();
+        let mut resampler = Resampler::new(44100, 48000, 2, ResampleQuality::Best).unwrap();
+        let mut out = vec![];
+        // in uneven pieces, as the decoder gives them
+        for chunk in input.chunks(2 * 999) {
+            out.extend(resampler.process(chunk).unwrap());
+        }
+        out.extend(resampler.flush().unwrap());
+        assert_eq!(out.len(), 2 * 48000);
+
+        // the same sine at the new rate away from the ends, give or take
+        // half a frame of delay
+        let (mut sin, mut cos) = (0.0, 0.0);
+        for x in 1000..47000 {
+            let t = std::f32::consts::TAU * freq * x as f32 / 48000.0;
+            sin += out[2 * x] * t.sin();
+            cos += out[2 * x] * t.cos();
+            assert_eq!(out[2 * x + 1], -out[2 * x]);
+        }
+        let amplitude = 2.0 * sin.hypot(cos) / 46000.0;
+        assert!((amplitude - 0.5).abs() < 0.001, "{amplitude}");
+        let phase = cos.atan2(sin).abs();
+        assert!(phase < std::f32::consts::TAU * 0.5 / 48.0, "{phase}");
+    }
+
+    #[test]
+    fn quantizes_with_dither() {
+        // 16-bit audio passes untouched, even with dither on
+        let mut quantizer = Quantizer::new(Some(16), true);
+        let exact = [0.0, 0.5, -1.0, 1.0 / 32768.0];
+        let mut samples = exact;
+        quantizer.process(&mut samples);
+        assert_eq!(samples, exact);
+
+        let mut samples = vec![0.1; 1000];
+        quantizer.process(&mut samples);
+        let step = 1.0 / 32768.0;
+        for x in &samples {
+            assert_eq!((x * 32768.0).fract(), 0.0);
+            assert!((x - 0.1).abs() <= 1.5 * step);
+        }
+        // the noise averages out
+        let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+        assert!((mean - 0.1).abs() < 0.1 * step);
+
+        // without dither, everything rounds to the nearest step
+        let mut samples = vec![0.1; 10];
+        Quantizer::new(Some(16), false).process(&mut samples);
+        assert!(
+            samples
+                .iter()
+                .all(|x| *x == (0.1f32 * 32768.0).round() / 32768.0)
+        );
+        // and 24 bits or more is left alone
+        let mut samples = vec![0.1; 10];
+        Quantizer::new(Some(24), true).process(&mut samples);
+        assert!(samples.iter().all(|x| *x == 0.1));
+    }
+
+    #[test]
+    fn mixes_channels() {
+        // mono plays on both front speakers
+        let mixer = ChannelMixer::new(1, 2);
+        assert_eq!(mixer.process(&[0.5, -0.25]), [0.5, 0.5, -0.25, -0.25]);
+        let mixer = ChannelMixer::new(1, 6);
+        assert_eq!(mixer.process(&[0.5]), [0.5, 0.5, 0.0, 0.0, 0.0, 0.0]);
+
+        // stereo to mono is the mean
+        let mixer = ChannelMixer::new(2, 1);
+        assert_eq!(mixer.process(&[0.5, 0.25, 1.0, -1.0]), [0.375, 0.0]);
+
+        // 5.1 folds into stereo without the LFE, and cannot clip
+        let mixer = ChannelMixer::new(6, 2);
+        let out = mixer.process(&[1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+        assert!(out[0] > 0.0 && out[1] == 0.0, "{out:?}");
+        let out = mixer.process(&[1.0, 1.0, 1.0, 1.0, 1.0, 1.0]);
+        assert!(out.iter().all(|x| (x - 1.0).abs() < 1e-6), "{out:?}");
+        let out = mixer.process(&[0.0, 0.0, 1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(out[0], out[1]);
+        let out = ChannelMixer::new(6, 1).process(&[1.0, 1.0, 1.0, 0.0, 1.0, 1.0]);
+        assert!((out[0] - 1.0).abs() < 1e-6, "{out:?}");
+
+        // otherwise the channels in common carry over
+        let mixer = ChannelMixer::new(2, 4);
+        assert_eq!(mixer.process(&[0.5, -0.5]), [0.5, -0.5, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,353 @@
+use std::{fmt, fs::File, path::Path, time::Duration};
+
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{CODEC_TYPE_NULL, DecoderOptions},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+    units::TimeBase,
+};
+
+/// Extensions of the containers symphonia can read. Opus is left out, as
+/// symphonia has no decoder for it.
+const DECODER_EXTENSIONS: [&str; 20] = [
+    "aac", "adts", "aif", "aifc", "aiff", "caf", "flac", "m4a", "m4b", "m4p", "mka", "mkv", "mp1",
+    "mp2", "mp3", "mp4", "oga", "ogg", "wav", "webm",
+];
+
+/// Whether a file with this extension could be played.
+pub fn can_decode_extension(ext: &str) -> bool {
+    DECODER_EXTENSIONS
+        .iter()
+        .any(|x| ext.eq_ignore_ascii_case(x))
+}
+
+/// Extensions of audio whose tags can be read but which cannot be played.
+/// Opus needs libopus, which symphonia does not bundle.
+const UNSUPPORTED_EXTENSIONS: [&str; 1] = ["opus"];
+
+/// Whether a file with this extension is audio that cannot be played, and
+/// should be left out of the library rather than fail when played.
+pub fn is_unsupported_extension(ext: &str) -> bool {
+    UNSUPPORTED_EXTENSIONS
+        .iter()
+        .any(|x| ext.eq_ignore_ascii_case(x))
+}
+
+/// The shape of a stream of interleaved `f32` samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamSpec {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+/// Why something could not be played.
+#[derive(Debug)]
+pub enum PlaybackError {
+    Io(std::io::Error),
+    Decode(SymphoniaError),
+    /// The file has no audio track that can be decoded
+    NoTrack,
+    /// The audio device failed or does not take the stream
+    Device(String),
+}
+
+impl fmt::Display for PlaybackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Decode(err) => write!(f, "{err}"),
+            Self::NoTrack => f.write_str("no playable audio track"),
+            Self::Device(err) => write!(f, "audio device: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for PlaybackError {}
+
+impl From<std::io::Error> for PlaybackError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<SymphoniaError> for PlaybackError {
+    fn from(value: SymphoniaError) -> Self {
+        Self::Decode(value)
+    }
+}
+
+/// Encoder delay and padding that the demuxer does not trim by itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Trim {
+    /// Frames to drop from the start
+    delay: u64,
+    /// Frames of actual audio after the delay, if known
+    frames: Option<u64>,
+}
+
+/// Decodes a file into interleaved `f32` samples, without the priming and
+/// padding that encoders add, so that consecutive tracks can be spliced
+/// without a gap.
+///
+/// MP3 (LAME/Xing headers) and most other formats are trimmed by the demuxer.
+/// For AAC in MP4 the `iTunSMPB` tag is used.
+pub struct Decoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn symphonia::core::codecs::Decoder>,
+    track: u32,
+    time_base: Option<TimeBase>,
+    spec: StreamSpec,
+    trim: Trim,
+    /// Length in frames, trimmed, if known
+    len: Option<u64>,
+    /// Frames decoded so far, counted from the start of the untrimmed
+    /// stream
+    frames: u64,
+    /// The first frame to output, which is past the delay, or where a seek
+    /// went to
+    start: u64,
+    /// Frames into the track of the end of the last chunk, trimmed
+    position: u64,
+}
+
+impl Decoder {
+    pub fn open(path: &Path) -> Result<Self, PlaybackError> {
+        let file = File::open(path)?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|x| x.to_str()) {
+            hint.with_extension(ext);
+        }
+        let format_opts = FormatOptions {
+            enable_gapless: true,
+            ..Default::default()
+        };
+        let mut probed = symphonia::default::get_probe().format(
+            &hint,
+            stream,
+            &format_opts,
+            &MetadataOptions::default(),
+        )?;
+        let mut format = probed.format;
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|x| x.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or(PlaybackError::NoTrack)?
+            .clone();
+        let params = &track.codec_params;
+        let spec = StreamSpec {
+            sample_rate: params.sample_rate.ok_or(PlaybackError::NoTrack)?,
+            channels: params.channels.map_or(2, |x| x.count() as u16),
+        };
+
+        // only trim what the demuxer left alone
+        let mut trim = Trim::default();
+        if params.delay.is_none() {
+            let smpb = probed
+                .metadata
+                .get()
+                .and_then(|x| x.current().and_then(|x| find_itunsmpb(x.tags())))
+                .or_else(|| find_itunsmpb(format.metadata().current()?.tags()));
+            if let Some(x) = smpb {
+                trim = x;
+            }
+        }
+
+        // the demuxer has already taken out what it trims
+        let len = trim
+            .frames
+            .or_else(|| Some(params.n_frames?.saturating_sub(trim.delay)));
+        let decoder = symphonia::default::get_codecs().make(params, &DecoderOptions::default())?;
+        Ok(Self {
+            format,
+            decoder,
+            track: track.id,
+            time_base: params.time_base,
+            spec,
+            trim,
+            len,
+            frames: 0,
+            start: trim.delay,
+            position: 0,
+        })
+    }
+
+    pub fn spec(&self) -> StreamSpec {
+        self.spec
+    }
+
+    /// How long the track is, if the container says.
+    pub fn duration(&self) -> Option<Duration> {
+        self.len.map(|x| self.frames_to_time(x))
+    }
+
+    /// How far into the track the samples decoded so far go.
+    pub fn position(&self) -> Duration {
+        self.frames_to_time(self.position)
+    }
+
+    /// Go to a position in the track, returning where the next chunk starts.
+    ///
+    /// The demuxer lands on a packet before the position, eg. through the
+    /// seek table of a FLAC file or by offset in a WAV file, and what comes
+    /// before the exact frame is decoded and dropped. MP3 has no index, so
+    /// the demuxer scans frame headers to get there, which also gets VBR
+    /// files right. Formats that cannot do that are seeked to roughly,
+    /// wherever the container's index says.
+    pub fn seek(&mut self, at: Duration) -> Result<Duration, PlaybackError> {
+        let frame = (at.as_secs_f64() * self.spec.sample_rate as f64).round() as u64;
+        let frame = match self.len {
+            Some(len) => frame.min(len),
+            None => frame,
+        };
+        let target = frame + self.trim.delay;
+        let (ts, track_id) = (self.frames_to_ts(target), self.track);
+        let to = || SeekTo::TimeStamp { ts, track_id };
+        let seeked = match self.format.seek(SeekMode::Accurate, to()) {
+            Ok(x) => x,
+            Err(_) => self.format.seek(SeekMode::Coarse, to())?,
+        };
+        self.decoder.reset();
+        self.frames = self.ts_to_frames(seeked.actual_ts);
+        self.start = target.max(self.frames);
+        self.position = self.start - self.trim.delay;
+        Ok(self.position())
+    }
+
+    fn frames_to_time(&self, frames: u64) -> Duration {
+        Duration::from_secs_f64(frames as f64 / self.spec.sample_rate as f64)
+    }
+
+    /// Convert between frames and timestamps, which some containers count
+    /// in other units.
+    fn frames_to_ts(&self, frames: u64) -> u64 {
+        match self.time_base {
+            Some(x) => {
+                let rate = u128::from(self.spec.sample_rate) * u128::from(x.numer);
+                (u128::from(frames) * u128::from(x.denom) / rate) as u64
+            }
+            None => frames,
+        }
+    }
+
+    fn ts_to_frames(&self, ts: u64) -> u64 {
+        match self.time_base {
+            Some(x) => {
+                let rate = u128::from(self.spec.sample_rate) * u128::from(x.numer);
+                (u128::from(ts) * rate / u128::from(x.denom)) as u64
+            }
+            None => ts,
+        }
+    }
+
+    /// Decode the next chunk of interleaved samples, or `None` at the end of
+    /// the track. Corrupt packets are skipped.
+    pub fn next_chunk(&mut self) -> Result<Option<Vec<f32>>, PlaybackError> {
+        let channels = self.spec.channels as usize;
+        loop {
+            if self
+                .trim
+                .frames
+                .is_some_and(|x| self.frames >= self.trim.delay + x)
+            {
+                return Ok(None);
+            }
+            let packet = match self.format.next_packet() {
+                Ok(x) => x,
+                Err(SymphoniaError::IoError(err))
+                    if err.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    return Ok(None);
+                }
+                Err(SymphoniaError::ResetRequired) => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            if packet.track_id() != self.track {
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(x) => x,
+                // still counted, so that positions stay right
+                Err(SymphoniaError::DecodeError(_)) => {
+                    self.frames += self.ts_to_frames(packet.dur);
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+            buf.copy_interleaved_ref(decoded);
+            let samples = buf.samples();
+
+            // `frames` counts from the start of the untrimmed stream here
+            let len = (samples.len() / channels) as u64;
+            let start = self.start.saturating_sub(self.frames).min(len);
+            let end = match self.trim.frames {
+                Some(x) => (self.trim.delay + x).saturating_sub(self.frames).min(len),
+                None => len,
+            };
+            self.frames += len;
+            if start < end {
+                self.position = (self.frames - len + end).saturating_sub(self.trim.delay);
+                return Ok(Some(
+                    samples[start as usize * channels..end as usize * channels].to_vec(),
+                ));
+            }
+        }
+    }
+}
+
+/// Read the encoder delay and length out of an `iTunSMPB` tag.
+fn find_itunsmpb(tags: &[symphonia::core::meta::Tag]) -> Option<Trim> {
+    let tag = tags.iter().find(|x| x.key.ends_with("iTunSMPB"))?;
+    parse_itunsmpb(&tag.value.to_string())
+}
+
+/// Parse an `iTunSMPB` value, eg.
+/// ` 00000000 00000840 000001CA 00000000003F31F6 ...`: the delay, padding and
+/// length in frames, in hex.
+fn parse_itunsmpb(inp: &str) -> Option<Trim> {
+    let mut fields = inp.split_whitespace().skip(1);
+    let delay = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let _padding = fields.next()?;
+    let frames = u64::from_str_radix(fields.next()?, 16).ok()?;
+    Some(Trim {
+        delay,
+        frames: (frames > 0).then_some(frames),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn itunsmpb() {
+        let inp = " 00000000 00000840 000001CA 00000000003F31F6 00000000 00000000 00000000";
+        assert_eq!(
+            parse_itunsmpb(inp),
+            Some(Trim {
+                delay: 0x840,
+                frames: Some(0x3F31F6),
+            })
+        );
+        assert_eq!(parse_itunsmpb("garbage"), None);
+    }
+
+    #[test]
+    fn decoder_extensions() {
+        assert!(can_decode_extension("flac"));
+        assert!(can_decode_extension("MP3"));
+        assert!(!can_decode_extension("jpg"));
+        assert!(!can_decode_extension("cue"));
+        assert!(!can_decode_extension("log"));
+        assert!(!can_decode_extension("opus"));
+        assert!(is_unsupported_extension("Opus"));
+        assert!(!is_unsupported_extension("ogg"));
+    }
+}
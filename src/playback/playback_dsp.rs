@@ -0,0 +1,403 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use super::{StreamSpec, db_to_factor};
+
+/// A second order IIR filter, in transposed direct form II.
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    /// A filter from its coefficients, with `a0` already divided out.
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self { b, a, z: [0.0; 2] }
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The shape of an equalizer band.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterKind {
+    /// Boosts or cuts around the frequency
+    #[default]
+    Peak,
+    /// Boosts or cuts everything below the frequency
+    LowShelf,
+    /// Boosts or cuts everything above the frequency
+    HighShelf,
+    /// Cuts everything above the frequency, ignoring the gain
+    LowPass,
+    /// Cuts everything below the frequency, ignoring the gain
+    HighPass,
+}
+
+/// One band of the parametric equalizer.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EqBand {
+    pub kind: FilterKind,
+    /// Centre or corner frequency in Hz
+    pub freq: f32,
+    /// In dB
+    pub gain: f32,
+    /// Narrower at higher values. About 1.41 spans an octave.
+    pub q: f32,
+}
+
+impl EqBand {
+    /// The frequency for display, eg. "62 Hz" or "1.5 kHz".
+    pub fn display_freq(&self) -> String {
+        if self.freq >= 1000.0 {
+            format!("{} kHz", (self.freq / 100.0).round() / 10.0)
+        } else {
+            format!("{} Hz", self.freq.round())
+        }
+    }
+
+    /// The filter of the band at a sample rate, per the Audio EQ Cookbook.
+    fn filter(&self, sample_rate: u32) -> Biquad {
+        let rate = sample_rate as f64;
+        // keep below Nyquist, where the formulas fall apart
+        let freq = (self.freq as f64).clamp(1.0, rate * 0.49);
+        let w0 = 2.0 * PI * freq / rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * (self.q as f64).max(0.01));
+        let a = 10f64.powf(self.gain as f64 / 40.0);
+        let root = 2.0 * a.sqrt() * alpha;
+        let (b, a) = match self.kind {
+            FilterKind::Peak => (
+                [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+                [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+            ),
+            FilterKind::LowShelf => (
+                [
+                    a * ((a + 1.0) - (a - 1.0) * cos + root),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - root),
+                ],
+                [
+                    (a + 1.0) + (a - 1.0) * cos + root,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - root,
+                ],
+            ),
+            FilterKind::HighShelf => (
+                [
+                    a * ((a + 1.0) + (a - 1.0) * cos + root),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - root),
+                ],
+                [
+                    (a + 1.0) - (a - 1.0) * cos + root,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - root,
+                ],
+            ),
+            FilterKind::LowPass => (
+                [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterKind::HighPass => (
+                [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+        };
+        Biquad::new(
+            [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            [a[1] / a[0], a[2] / a[0]],
+        )
+    }
+}
+
+/// Centre frequencies of the graphic equalizer, an octave apart.
+pub const GRAPHIC_FREQS: [f32; 10] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+
+/// Gains for the bands of the graphic equalizer.
+#[derive(Debug, Clone, Copy)]
+pub struct EqPreset {
+    pub name: &'static str,
+    pub gains: [f32; 10],
+}
+
+impl EqPreset {
+    pub fn bands(&self) -> Vec<EqBand> {
+        GRAPHIC_FREQS
+            .iter()
+            .zip(self.gains)
+            .map(|(freq, gain)| EqBand {
+                kind: FilterKind::Peak,
+                freq: *freq,
+                gain,
+                q: std::f32::consts::SQRT_2,
+            })
+            .collect()
+    }
+}
+
+pub const EQ_PRESETS: &[EqPreset] = &[
+    EqPreset {
+        name: "Flat",
+        gains: [0.0; 10],
+    },
+    EqPreset {
+        name: "Bass boost",
+        gains: [6.0, 5.0, 4.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    },
+    EqPreset {
+        name: "Treble boost",
+        gains: [0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 4.0, 5.0, 6.0],
+    },
+    EqPreset {
+        name: "Loudness",
+        gains: [5.0, 4.0, 2.0, 0.0, -1.0, -1.0, 0.0, 2.0, 4.0, 5.0],
+    },
+    EqPreset {
+        name: "Vocal",
+        gains: [-2.0, -2.0, -1.0, 1.0, 3.0, 3.0, 2.0, 1.0, 0.0, -1.0],
+    },
+    EqPreset {
+        name: "Rock",
+        gains: [4.0, 3.0, 2.0, 0.0, -1.0, -1.0, 1.0, 2.0, 3.0, 4.0],
+    },
+    EqPreset {
+        name: "Electronic",
+        gains: [5.0, 4.0, 1.0, 0.0, -2.0, 1.0, 0.0, 1.0, 4.0, 5.0],
+    },
+    EqPreset {
+        name: "Classical",
+        gains: [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -2.0, -3.0, -3.0, -4.0],
+    },
+    EqPreset {
+        name: "Spoken word",
+        gains: [-4.0, -3.0, -1.0, 1.0, 3.0, 4.0, 3.0, 1.0, -1.0, -3.0],
+    },
+];
+
+/// The processing applied to everything played, after decoding. Saved per
+/// output device, as it mostly makes up for the speakers or headphones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DspOptions {
+    /// Whether the equalizer and its preamp are on
+    pub equalizer: bool,
+    /// Added before the equalizer, in dB, to make room for boosts
+    pub preamp: f32,
+    pub bands: Vec<EqBand>,
+    /// From -1 for only the left channel to 1 for only the right
+    pub balance: f32,
+    /// Mix the channels together
+    pub mono: bool,
+}
+
+impl Default for DspOptions {
+    fn default() -> Self {
+        Self {
+            equalizer: false,
+            preamp: 0.0,
+            bands: EQ_PRESETS[0].bands(),
+            balance: 0.0,
+            mono: false,
+        }
+    }
+}
+
+/// Applies `DspOptions` to a stream. Filters keep their state across
+/// writes, so that tracks joined back to back stay seamless.
+#[derive(Debug, Default)]
+pub struct Dsp {
+    options: DspOptions,
+    spec: Option<StreamSpec>,
+    /// A filter per band, per channel
+    filters: Vec<Vec<Biquad>>,
+}
+
+impl Dsp {
+    pub fn set_options(&mut self, options: DspOptions) {
+        if options != self.options {
+            self.options = options;
+            self.update_filters();
+        }
+    }
+
+    /// Get ready for samples of `spec`.
+    pub fn configure(&mut self, spec: StreamSpec) {
+        if self.spec != Some(spec) {
+            self.spec = Some(spec);
+            self.filters.clear();
+            self.update_filters();
+        }
+    }
+
+    /// Work the filters out again, carrying their state over where the
+    /// bands are the same, so that changes do not click.
+    fn update_filters(&mut self) {
+        let Some(spec) = self.spec else {
+            return;
+        };
+        let channels = spec.channels as usize;
+        let mut filters = vec![];
+        if self.options.equalizer {
+            for band in &self.options.bands {
+                filters.push(vec![band.filter(spec.sample_rate); channels]);
+            }
+        }
+        for (new, old) in filters.iter_mut().zip(&self.filters) {
+            for (new, old) in new.iter_mut().zip(old) {
+                new.z = old.z;
+            }
+        }
+        self.filters = filters;
+    }
+
+    /// Process interleaved samples in place.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let Some(spec) = self.spec else {
+            return;
+        };
+        let channels = spec.channels as usize;
+        let options = &self.options;
+
+        if options.equalizer {
+            let preamp = db_to_factor(options.preamp) as f64;
+            for frame in samples.chunks_exact_mut(channels) {
+                for (c, x) in frame.iter_mut().enumerate() {
+                    let mut y = *x as f64 * preamp;
+                    for band in &mut self.filters {
+                        y = band[c].process(y);
+                    }
+                    *x = y as f32;
+                }
+            }
+        }
+
+        if options.mono && channels > 1 {
+            for frame in samples.chunks_exact_mut(channels) {
+                let mix = frame.iter().sum::<f32>() / channels as f32;
+                frame.fill(mix);
+            }
+        }
+
+        if options.balance != 0.0 && channels == 2 {
+            let balance = options.balance.clamp(-1.0, 1.0);
+            let (left, right) = ((1.0 - balance).min(1.0), (1.0 + balance).min(1.0));
+            for frame in samples.chunks_exact_mut(2) {
+                frame[0] *= left;
+                frame[1] *= right;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    /// The gain in dB of a sine at `freq` through the chain, once the
+    /// filters have settled.
+    fn response(options: &DspOptions, freq: f32) -> f32 {
+        let mut dsp = Dsp::default();
+        dsp.set_options(options.clone());
+        dsp.configure(StreamSpec {
+            sample_rate: RATE,
+            channels: 1,
+        });
+        let mut samples = (0..RATE as usize)
+            .map(|x| 0.1 * (std::f32::consts::TAU * freq * x as f32 / RATE as f32).sin())
+            .collect::<Vec<_>>();
+        dsp.process(&mut samples);
+        let rms = |x: &[f32]| (x.iter().map(|x| x * x).sum::<f32>() / x.len() as f32).sqrt();
+        let settled = &samples[RATE as usize / 2..];
+        20.0 * (rms(settled) / (0.1 / std::f32::consts::SQRT_2)).log10()
+    }
+
+    fn with_bands(bands: Vec<EqBand>) -> DspOptions {
+        DspOptions {
+            equalizer: true,
+            bands,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn peak_band() {
+        let options = with_bands(vec![EqBand {
+            kind: FilterKind::Peak,
+            freq: 1000.0,
+            gain: 6.0,
+            q: 1.41,
+        }]);
+        assert!((response(&options, 1000.0) - 6.0).abs() < 0.1);
+        assert!(response(&options, 50.0).abs() < 0.1);
+        assert!(response(&options, 15000.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn shelves_and_passes() {
+        let band = |kind, freq| EqBand {
+            kind,
+            freq,
+            gain: -6.0,
+            q: std::f32::consts::FRAC_1_SQRT_2,
+        };
+        let low_shelf = with_bands(vec![band(FilterKind::LowShelf, 200.0)]);
+        assert!((response(&low_shelf, 40.0) + 6.0).abs() < 0.3);
+        assert!(response(&low_shelf, 5000.0).abs() < 0.1);
+
+        let high_shelf = with_bands(vec![band(FilterKind::HighShelf, 4000.0)]);
+        assert!((response(&high_shelf, 15000.0) + 6.0).abs() < 0.3);
+        assert!(response(&high_shelf, 100.0).abs() < 0.1);
+
+        // Butterworth filters are 3 dB down at the corner
+        let low_pass = with_bands(vec![band(FilterKind::LowPass, 1000.0)]);
+        assert!((response(&low_pass, 1000.0) + 3.0).abs() < 0.1);
+        assert!(response(&low_pass, 8000.0) < -30.0);
+        let high_pass = with_bands(vec![band(FilterKind::HighPass, 1000.0)]);
+        assert!(response(&high_pass, 100.0) < -30.0);
+        assert!(response(&high_pass, 10000.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn graphic_preset() {
+        let mut options = with_bands(EQ_PRESETS[1].bands());
+        // neighbouring bands add up a little
+        assert!(response(&options, 31.0) > 6.0);
+        assert!(response(&options, 4000.0).abs() < 0.5);
+        options.preamp = -6.0;
+        assert!((response(&options, 4000.0) + 6.0).abs() < 0.5);
+        // off leaves everything alone
+        options.equalizer = false;
+        assert!(response(&options, 31.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn mono_and_balance() {
+        let mut dsp = Dsp::default();
+        dsp.set_options(DspOptions {
+            mono: true,
+            balance: 0.5,
+            ..Default::default()
+        });
+        dsp.configure(StreamSpec {
+            sample_rate: RATE,
+            channels: 2,
+        });
+        let mut samples = [1.0, 0.0, 0.5, 0.5];
+        dsp.process(&mut samples);
+        assert_eq!(samples, [0.25, 0.5, 0.25, 0.5]);
+    }
+}
@@ -0,0 +1,935 @@
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::mpsc::{self, TryRecvError},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    AudioSink, Crossfade, Decoder, Dsp, DspOptions, Normalization, OutputOptions, PlaybackError,
+    ReplayGain, SilenceTrim, StreamSpec, TimeStretch, mix_crossfade,
+};
+
+/// What the player tells the rest of the app.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A track started on request
+    Started(PathBuf),
+    /// A track ended and the one lined up after it started, or failed to
+    /// with a `Failed` right after
+    Advanced { from: PathBuf, to: PathBuf },
+    /// A track ended and nothing was lined up after it
+    Ended(PathBuf),
+    /// A track could not be played, so nothing is playing
+    Failed(PathBuf, String),
+    /// Something went wrong that did not stop playback, eg. with the device
+    Error(String),
+    /// How far into a track playback is, and how long it is if known, sent
+    /// every so often while playing and after seeking
+    Position {
+        path: PathBuf,
+        at: Duration,
+        duration: Option<Duration>,
+    },
+}
+
+/// Settings that change how tracks play, persisted with the other settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlaybackOptions {
+    /// From 0 for silence to 1 for full volume, along a curve that sounds
+    /// even
+    pub volume: f32,
+    pub crossfade: Crossfade,
+    /// Skip digital silence at the start and end of tracks
+    pub trim_silence: bool,
+    pub normalization: Normalization,
+    pub output: OutputOptions,
+    /// Saved per output device rather than with the rest
+    #[serde(skip)]
+    pub dsp: DspOptions,
+}
+
+impl Default for PlaybackOptions {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            crossfade: Default::default(),
+            trim_silence: false,
+            normalization: Default::default(),
+            output: Default::default(),
+            dsp: Default::default(),
+        }
+    }
+}
+
+/// A track to play, with what the library knows about it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub path: PathBuf,
+    pub replay_gain: ReplayGain,
+    /// How fast to play, keeping the pitch
+    pub speed: f32,
+}
+
+impl From<PathBuf> for Source {
+    fn from(path: PathBuf) -> Self {
+        Self {
+            path,
+            replay_gain: Default::default(),
+            speed: 1.0,
+        }
+    }
+}
+
+/// A track being decoded.
+struct Track {
+    path: PathBuf,
+    replay_gain: ReplayGain,
+    speed: f32,
+    decoder: Decoder,
+    trim: Option<SilenceTrim>,
+    /// Only there when not playing at normal speed
+    stretch: Option<TimeStretch>,
+}
+
+impl Track {
+    fn open(source: Source, options: &PlaybackOptions) -> Result<Self, PlaybackError> {
+        let mut ret = Self {
+            decoder: Decoder::open(&source.path)?,
+            path: source.path,
+            replay_gain: source.replay_gain,
+            speed: 1.0,
+            trim: options.trim_silence.then(SilenceTrim::new),
+            stretch: None,
+        };
+        ret.set_speed(source.speed);
+        Ok(ret)
+    }
+
+    fn source(&self) -> Source {
+        Source {
+            path: self.path.clone(),
+            replay_gain: self.replay_gain,
+            speed: self.speed,
+        }
+    }
+
+    fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+        match &mut self.stretch {
+            Some(stretch) => stretch.set_speed(speed),
+            None if speed != 1.0 => {
+                let spec = self.decoder.spec();
+                self.stretch = Some(TimeStretch::new(speed, spec.sample_rate, spec.channels));
+            }
+            None => {}
+        }
+    }
+
+    /// Go to a position in the track, returning where it got to.
+    fn seek(&mut self, at: Duration) -> Result<Duration, PlaybackError> {
+        let at = self.decoder.seek(at)?;
+        if let Some(trim) = &mut self.trim {
+            trim.reset(at.is_zero());
+        }
+        if let Some(stretch) = &mut self.stretch {
+            stretch.reset();
+        }
+        Ok(at)
+    }
+
+    /// The next chunk of samples, or `None` at the end of the track.
+    fn next_chunk(&mut self, options: &PlaybackOptions) -> Result<Option<Vec<f32>>, PlaybackError> {
+        let channels = self.decoder.spec().channels as usize;
+        loop {
+            let mut chunk = match self.decoder.next_chunk()? {
+                Some(chunk) => {
+                    let chunk = match &mut self.trim {
+                        Some(trim) => trim.process(&chunk, channels),
+                        None => chunk,
+                    };
+                    match &mut self.stretch {
+                        Some(stretch) => stretch.process(&chunk),
+                        None => chunk,
+                    }
+                }
+                // the stretch still has the end of the track
+                None => match self.stretch.as_mut().map(TimeStretch::finish) {
+                    Some(rest) if !rest.is_empty() => rest,
+                    _ => return Ok(None),
+                },
+            };
+            if chunk.is_empty() {
+                continue;
+            }
+            // looked up every time, so that changes apply right away
+            let factor = options.normalization.factor(&self.replay_gain);
+            if factor != 1.0 {
+                chunk.iter_mut().for_each(|x| *x *= factor);
+            }
+            return Ok(Some(chunk));
+        }
+    }
+}
+
+/// The next track, opened and with its first samples decoded ahead of time.
+struct Preloaded {
+    track: Track,
+    first: Vec<f32>,
+    /// Whether to crossfade into it
+    fade: bool,
+}
+
+/// Decodes the current track into a sink, with the next one ready to follow
+/// it without a gap, or with a crossfade.
+pub struct Pipeline<S> {
+    sink: S,
+    dsp: Dsp,
+    options: PlaybackOptions,
+    current: Option<Track>,
+    next: Option<Preloaded>,
+    /// The end of the current track, held back to crossfade from
+    held: VecDeque<f32>,
+}
+
+impl<S: AudioSink> Pipeline<S> {
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink,
+            dsp: Default::default(),
+            options: Default::default(),
+            current: None,
+            next: None,
+            held: VecDeque::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.sink.set_paused(paused);
+    }
+
+    /// Change the options. The current track keeps its silence trimming,
+    /// but the next one is opened again to pick up the change.
+    pub fn set_options(&mut self, options: PlaybackOptions) {
+        let reopen = options.trim_silence != self.options.trim_silence;
+        self.dsp.set_options(options.dsp.clone());
+        self.sink.set_output(options.output);
+        self.options = options;
+        if reopen && let Some(next) = self.next.take() {
+            self.set_next(Some(next.track.source()), next.fade);
+        }
+    }
+
+    /// Start playing `path` right away, dropping whatever is still buffered.
+    pub fn play(&mut self, source: Source, events: &mut impl FnMut(Event)) {
+        self.sink.discard();
+        self.held.clear();
+        self.current = None;
+        let path = source.path.clone();
+        let ret = Track::open(source, &self.options).and_then(|x| {
+            self.configure(x.decoder.spec())?;
+            Ok(x)
+        });
+        match ret {
+            Ok(track) => {
+                self.current = Some(track);
+                events(Event::Started(path));
+            }
+            Err(err) => events(Event::Failed(path, err.to_string())),
+        }
+    }
+
+    /// Line up the track to follow the current one, and whether to crossfade
+    /// into it. Failing to open it is not an error yet, as it may be replaced
+    /// before it is needed.
+    pub fn set_next(&mut self, source: Option<Source>, fade: bool) {
+        if let Some(next) = &mut self.next
+            && source.as_ref() == Some(&next.track.source())
+        {
+            next.fade = fade;
+            return;
+        }
+        self.next = source.and_then(|source| {
+            let mut track = Track::open(source, &self.options).ok()?;
+            // a track that is all silence is still a track
+            let first = track.next_chunk(&self.options).ok()?.unwrap_or_default();
+            Some(Preloaded { track, first, fade })
+        });
+    }
+
+    /// Go to a position in the current track, dropping whatever is still
+    /// buffered. If that fails, the track plays on from where it was.
+    pub fn seek(&mut self, at: Duration, events: &mut impl FnMut(Event)) {
+        let Some(track) = &mut self.current else {
+            return;
+        };
+        self.sink.discard();
+        self.held.clear();
+        if let Err(err) = track.seek(at) {
+            let path = track.path.display();
+            events(Event::Error(format!("Could not seek in {path}: {err}")));
+        }
+    }
+
+    /// Change how fast the current track plays.
+    pub fn set_speed(&mut self, speed: f32) {
+        if let Some(track) = &mut self.current {
+            track.set_speed(speed);
+        }
+    }
+
+    /// Tell how far into the current track playback is, leaving out what is
+    /// held back or buffered and so not heard yet.
+    pub fn report_position(&self, events: &mut impl FnMut(Event)) {
+        let Some(track) = &self.current else {
+            return;
+        };
+        let spec = track.decoder.spec();
+        let frames = self.held.len() as f64 / spec.channels as f64;
+        // what is not heard yet has been sped up already
+        let ahead = (Duration::from_secs_f64(frames / spec.sample_rate as f64)
+            + self.sink.buffered())
+        .mul_f32(track.speed);
+        events(Event::Position {
+            path: track.path.clone(),
+            at: track.decoder.position().saturating_sub(ahead),
+            duration: track.decoder.duration(),
+        });
+    }
+
+    /// Tell about errors from the sink that did not stop playback.
+    pub fn report_errors(&mut self, events: &mut impl FnMut(Event)) {
+        if let Some(err) = self.sink.take_error() {
+            events(Event::Error(err.to_string()));
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.sink.discard();
+        self.held.clear();
+        self.current = None;
+        self.next = None;
+    }
+
+    /// Decode and write one chunk of the current track, moving on to the next
+    /// one when it ends.
+    pub fn step(&mut self, events: &mut impl FnMut(Event)) {
+        let Some(track) = &mut self.current else {
+            return;
+        };
+        let spec = track.decoder.spec();
+        let ret = match track.next_chunk(&self.options) {
+            Ok(Some(chunk)) => self.hold(chunk, spec),
+            Ok(None) => {
+                self.advance(events);
+                return;
+            }
+            Err(err) => Err(err),
+        };
+        if let Err(err) = ret
+            && let Some(track) = self.current.take()
+        {
+            self.held.clear();
+            events(Event::Failed(track.path, err.to_string()));
+        }
+    }
+
+    fn configure(&mut self, spec: StreamSpec) -> Result<(), PlaybackError> {
+        self.sink.configure(spec)?;
+        self.dsp.configure(spec);
+        Ok(())
+    }
+
+    /// Write samples out through the DSP chain, at the volume.
+    fn write(&mut self, mut samples: Vec<f32>) -> Result<(), PlaybackError> {
+        self.dsp.process(&mut samples);
+        let factor = self.options.volume.clamp(0.0, 1.0).powi(3);
+        if factor != 1.0 {
+            samples.iter_mut().for_each(|x| *x *= factor);
+        }
+        self.sink.write(&samples)
+    }
+
+    /// Write samples, keeping back as much as a crossfade needs.
+    fn hold(&mut self, chunk: Vec<f32>, spec: StreamSpec) -> Result<(), PlaybackError> {
+        let keep = self.options.crossfade.frames(spec.sample_rate) * spec.channels as usize;
+        self.held.extend(chunk);
+        let excess = self.held.len().saturating_sub(keep);
+        if excess > 0 {
+            let out = self.held.drain(..excess).collect();
+            self.write(out)?;
+        }
+        Ok(())
+    }
+
+    /// Write whatever was held back.
+    fn flush(&mut self) -> Result<(), PlaybackError> {
+        let out = self.held.drain(..).collect();
+        self.write(out)
+    }
+
+    /// Move on to the preloaded track once the current one has been decoded.
+    fn advance(&mut self, events: &mut impl FnMut(Event)) {
+        let Some(from) = self.current.take() else {
+            return;
+        };
+        let Some(mut next) = self.next.take() else {
+            match self.flush() {
+                Ok(()) => events(Event::Ended(from.path)),
+                Err(err) => events(Event::Failed(from.path, err.to_string())),
+            }
+            return;
+        };
+        let ret = self.splice(from.decoder.spec(), &mut next);
+        let to = next.track.path.clone();
+        events(Event::Advanced {
+            from: from.path,
+            to: to.clone(),
+        });
+        match ret {
+            Ok(()) => self.current = Some(next.track),
+            Err(err) => {
+                self.held.clear();
+                events(Event::Failed(to, err.to_string()));
+            }
+        }
+    }
+
+    /// Join the start of the next track on to what is left of the current
+    /// one.
+    fn splice(&mut self, spec: StreamSpec, next: &mut Preloaded) -> Result<(), PlaybackError> {
+        let next_spec = next.track.decoder.spec();
+        let head = std::mem::take(&mut next.first);
+        // a change of format cannot be gapless, let alone crossfade, but is
+        // fine otherwise
+        if !next.fade || next_spec != spec || self.held.is_empty() {
+            self.flush()?;
+            self.configure(next_spec)?;
+            return self.hold(head, next_spec);
+        }
+
+        let mut head = head;
+        while head.len() < self.held.len() {
+            match next.track.next_chunk(&self.options)? {
+                Some(x) => head.extend(x),
+                None => break,
+            }
+        }
+        let tail = self.held.drain(..).collect::<Vec<_>>();
+        let len = tail.len().min(head.len());
+        let (tail, fading) = tail.split_at(tail.len() - len);
+        self.write(tail.to_vec())?;
+        let channels = spec.channels as usize;
+        let curve = self.options.crossfade.curve;
+        self.write(mix_crossfade(fading, &head[..len], channels, curve))?;
+        self.hold(head[len..].to_vec(), spec)
+    }
+}
+
+/// What the player can be told to do.
+#[derive(Debug)]
+pub enum Command {
+    Play(Source),
+    /// The track to play after the current one, and whether to crossfade
+    /// into it
+    SetNext(Option<Source>, bool),
+    SetOptions(PlaybackOptions),
+    /// Go to a position in the current track
+    Seek(Duration),
+    /// Change how fast the current track plays
+    SetSpeed(f32),
+    Pause,
+    Resume,
+    Stop,
+}
+
+/// Runs a `Pipeline` on its own thread, so that decoding never waits on the
+/// UI or the library.
+pub struct Engine {
+    commands: mpsc::Sender<Command>,
+}
+
+impl Engine {
+    /// How often to tell the position while playing
+    const POSITION_INTERVAL: Duration = Duration::from_millis(250);
+
+    /// Start the player. The sink is made on the player thread, as audio
+    /// streams often cannot move between threads.
+    pub fn spawn<S: AudioSink + 'static>(
+        make_sink: impl FnOnce() -> S + Send + 'static,
+        mut events: impl FnMut(Event) + Send + 'static,
+    ) -> Self {
+        let (commands, rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("playback".to_owned())
+            .spawn(move || {
+                let mut pipeline = Pipeline::new(make_sink());
+                let mut paused = false;
+                let mut reported = Instant::now();
+                loop {
+                    // block while there is nothing to decode
+                    let command = if paused || !pipeline.is_playing() {
+                        match rx.recv() {
+                            Ok(x) => Some(x),
+                            Err(_) => return,
+                        }
+                    } else {
+                        match rx.try_recv() {
+                            Ok(x) => Some(x),
+                            Err(TryRecvError::Empty) => None,
+                            Err(TryRecvError::Disconnected) => return,
+                        }
+                    };
+                    match command {
+                        Some(Command::Play(source)) => {
+                            paused = false;
+                            pipeline.set_paused(false);
+                            pipeline.play(source, &mut events);
+                        }
+                        Some(Command::SetNext(path, fade)) => pipeline.set_next(path, fade),
+                        Some(Command::SetOptions(options)) => pipeline.set_options(options),
+                        Some(Command::Seek(at)) => {
+                            pipeline.seek(at, &mut events);
+                            pipeline.report_position(&mut events);
+                            reported = Instant::now();
+                        }
+                        Some(Command::SetSpeed(speed)) => pipeline.set_speed(speed),
+                        Some(Command::Pause) => {
+                            paused = true;
+                            pipeline.set_paused(true);
+                        }
+                        Some(Command::Resume) => {
+                            paused = false;
+                            pipeline.set_paused(false);
+                        }
+                        Some(Command::Stop) => pipeline.stop(),
+                        None => {
+                            pipeline.step(&mut events);
+                            if reported.elapsed() >= Self::POSITION_INTERVAL {
+                                pipeline.report_position(&mut events);
+                                reported = Instant::now();
+                            }
+                        }
+                    }
+                    pipeline.report_errors(&mut events);
+                }
+            })
+            .unwrap();
+        Self { commands }
+    }
+
+    pub fn send(&self, command: Command) {
+        let _ = self.commands.send(command);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::playback::{FadeCurve, GainMode, WavSink};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mioplays-{}-{name}", std::process::id()))
+    }
+
+    /// A stereo sine wave that never touches zero, so that any inserted
+    /// silence stands out.
+    fn tone(freq: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|x| {
+                let t = x as f32 / 44100.0;
+                let sample = 0.5 + 0.25 * (t * freq * std::f32::consts::TAU).sin();
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    fn write_wav(path: &Path, samples: &[f32]) {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for x in samples {
+            writer.write_sample(*x).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    /// An atom: its size, its type, then its body.
+    fn atom(kind: &[u8; 4], body: &[&[u8]]) -> Vec<u8> {
+        let body = body.concat();
+        let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend(body);
+        out
+    }
+
+    /// Write an M4A that starts with `delay` frames and ends with `padding`
+    /// frames of junk around the samples, in 1024-frame packets like AAC's,
+    /// and says so in an `iTunSMPB` tag. The samples are stored as PCM so
+    /// that they come back exactly.
+    fn write_m4a(path: &Path, samples: &[f32], delay: usize, padding: usize) {
+        let junk = |frames: usize| vec![-0.75; frames * 2];
+        let stored = [junk(delay), samples.to_vec(), junk(padding)].concat();
+        let frames = (stored.len() / 2) as u32;
+        let packets = stored.chunks(2 * 1024).map(|x| x.len() as u32 / 2);
+        let smpb = format!(
+            " 00000000 {delay:08X} {padding:08X} {:016X} 00000000 00000000",
+            samples.len() / 2
+        );
+        let moov = |offset: u32| {
+            let u32s = |x: &[u32]| x.iter().flat_map(|x| x.to_be_bytes()).collect::<Vec<_>>();
+            let full = [0u8; 4];
+            let entry = atom(
+                b"lpcm",
+                &[
+                    &[0; 6],
+                    &1u16.to_be_bytes(),
+                    &2u16.to_be_bytes(),
+                    &[0; 6],
+                    &3u16.to_be_bytes(),
+                    &16u16.to_be_bytes(),
+                    &0xfffeu16.to_be_bytes(),
+                    &0u16.to_be_bytes(),
+                    &0x10000u32.to_be_bytes(),
+                    &72u32.to_be_bytes(),
+                    &44100f64.to_be_bytes(),
+                    // channels, a fixed marker, bits, big-endian float,
+                    // bytes per frame and frames per packet
+                    &u32s(&[2, 0x7f00_0000, 32, 0x3, 8, 1024]),
+                ],
+            );
+            let stts = packets.clone().flat_map(|x| [1, x]).collect::<Vec<_>>();
+            let stbl = atom(
+                b"stbl",
+                &[
+                    &atom(b"stsd", &[&full, &u32s(&[1]), &entry]),
+                    &atom(
+                        b"stts",
+                        &[&full, &u32s(&[stts.len() as u32 / 2]), &u32s(&stts)],
+                    ),
+                    &atom(b"stsc", &[&full, &u32s(&[1, 1, packets.len() as u32, 1])]),
+                    &atom(
+                        b"stsz",
+                        &[
+                            &full,
+                            &u32s(&[0, packets.len() as u32]),
+                            &u32s(&packets.clone().map(|x| x * 8).collect::<Vec<_>>()),
+                        ],
+                    ),
+                    &atom(b"stco", &[&full, &u32s(&[1, offset])]),
+                ],
+            );
+            let mdia = atom(
+                b"mdia",
+                &[
+                    &atom(
+                        b"mdhd",
+                        &[&full, &u32s(&[0, 0, 44100, frames]), &[0x55, 0xc4, 0, 0]],
+                    ),
+                    &atom(b"hdlr", &[&full, &[0; 4], b"soun", &[0; 13]]),
+                    &atom(b"minf", &[&atom(b"smhd", &[&full, &[0; 4]]), &stbl]),
+                ],
+            );
+            let trak = atom(
+                b"trak",
+                &[
+                    &atom(
+                        b"tkhd",
+                        &[
+                            &[0, 0, 0, 7],
+                            &u32s(&[0, 0, 1, 0, frames, 0, 0, 0]),
+                            &[1, 0, 0, 0],
+                        ],
+                    ),
+                    &mdia,
+                ],
+            );
+            let tag = atom(
+                b"----",
+                &[
+                    &atom(b"mean", &[&full, b"com.apple.iTunes"]),
+                    &atom(b"name", &[&full, b"iTunSMPB"]),
+                    &atom(b"data", &[&[0, 0, 0, 1], &[0; 4], smpb.as_bytes()]),
+                ],
+            );
+            let meta = atom(b"meta", &[&full, &atom(b"ilst", &[&tag])]);
+            atom(
+                b"moov",
+                &[
+                    &atom(
+                        b"mvhd",
+                        &[&full, &u32s(&[0, 0, 44100, frames, 0x10000]), &[1, 0]],
+                    ),
+                    &trak,
+                    &atom(b"udta", &[&meta]),
+                ],
+            )
+        };
+
+        let ftyp = atom(b"ftyp", &[b"M4A ", &[0; 4], b"M4A ", b"mp42", b"isom"]);
+        let offset = ftyp.len() + moov(0).len() + 8;
+        let data = stored
+            .iter()
+            .flat_map(|x| x.to_be_bytes())
+            .collect::<Vec<_>>();
+        let file = [ftyp, moov(offset as u32), atom(b"mdat", &[&data])].concat();
+        std::fs::write(path, file).unwrap();
+    }
+
+    /// Options that leave the samples alone, so that they can be compared.
+    fn plain() -> PlaybackOptions {
+        PlaybackOptions {
+            normalization: Normalization {
+                mode: GainMode::Off,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// How a render plays its tracks.
+    struct Setup {
+        options: PlaybackOptions,
+        /// Whether to crossfade from one track to the next
+        fade: bool,
+        /// Where to seek to right after the first track starts
+        seek: Option<Duration>,
+        speed: f32,
+        replay_gain: ReplayGain,
+    }
+
+    impl Default for Setup {
+        fn default() -> Self {
+            Self {
+                options: plain(),
+                fade: false,
+                seek: None,
+                speed: 1.0,
+                replay_gain: Default::default(),
+            }
+        }
+    }
+
+    /// Play the tracks back to back into a WAV file, and read it back.
+    fn render(name: &str, setup: Setup, tracks: &[&[f32]]) -> (Vec<f32>, Vec<Event>) {
+        let paths = (0..tracks.len())
+            .map(|x| temp_path(&format!("{name}-{x}.wav")))
+            .collect::<Vec<_>>();
+        for (path, samples) in paths.iter().zip(tracks) {
+            write_wav(path, samples);
+        }
+        let rendered = render_files(name, setup, &paths);
+        for path in &paths {
+            let _ = std::fs::remove_file(path);
+        }
+        rendered
+    }
+
+    /// Play the files back to back into a WAV file, and read it back.
+    fn render_files(name: &str, setup: Setup, paths: &[PathBuf]) -> (Vec<f32>, Vec<Event>) {
+        let out = temp_path(&format!("{name}-out.wav"));
+        let source = |path: &PathBuf| Source {
+            path: path.clone(),
+            replay_gain: setup.replay_gain,
+            speed: setup.speed,
+        };
+
+        let mut events = vec![];
+        let mut on_event = |x| events.push(x);
+        let mut pipeline = Pipeline::new(WavSink::new(out.clone()));
+        pipeline.set_options(setup.options);
+        pipeline.play(source(&paths[0]), &mut on_event);
+        if let Some(at) = setup.seek {
+            pipeline.seek(at, &mut on_event);
+            pipeline.report_position(&mut on_event);
+        }
+        for path in &paths[1..] {
+            pipeline.set_next(Some(source(path)), setup.fade);
+            while pipeline.is_playing() && pipeline.next.is_some() {
+                pipeline.step(&mut on_event);
+            }
+        }
+        while pipeline.is_playing() {
+            pipeline.step(&mut on_event);
+        }
+        pipeline.into_sink().finish().unwrap();
+
+        let rendered = hound::WavReader::open(&out)
+            .unwrap()
+            .samples::<f32>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let _ = std::fs::remove_file(&out);
+
+        let mut expected = vec![Event::Started(paths[0].clone())];
+        expected.extend(paths.windows(2).map(|x| Event::Advanced {
+            from: x[0].clone(),
+            to: x[1].clone(),
+        }));
+        expected.push(Event::Ended(paths[paths.len() - 1].clone()));
+        let played = events
+            .iter()
+            .filter(|x| !matches!(x, Event::Position { .. }))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(played, expected);
+        (rendered, events)
+    }
+
+    #[test]
+    fn gapless() {
+        // lengths that are not a multiple of any packet size
+        let a = tone(440.0, 44100 + 123);
+        let b = tone(660.0, 22050 + 77);
+        let (rendered, _) = render("gapless", Setup::default(), &[&a, &b]);
+        assert_eq!(rendered.len(), a.len() + b.len());
+        assert!(rendered.iter().all(|x| *x > 0.0), "silence was inserted");
+        assert_eq!(rendered, [a, b].concat());
+    }
+
+    #[test]
+    fn gapless_with_encoder_delay() {
+        let a = tone(440.0, 44100 + 123);
+        let b = tone(660.0, 22050 + 77);
+        let paths = [temp_path("delay-a.m4a"), temp_path("delay-b.m4a")];
+        // the usual AAC delay, and padding up to a whole packet
+        write_m4a(&paths[0], &a, 2112, 1024 - (2112 + 44100 + 123) % 1024);
+        write_m4a(&paths[1], &b, 2112, 1024 - (2112 + 22050 + 77) % 1024);
+        let (rendered, _) = render_files("delay", Setup::default(), &paths);
+        for path in &paths {
+            let _ = std::fs::remove_file(path);
+        }
+        assert!(
+            rendered.iter().all(|x| *x > 0.0),
+            "silence or padding was played"
+        );
+        assert_eq!(rendered, [a, b].concat());
+    }
+
+    #[test]
+    fn crossfade() {
+        let a = tone(440.0, 44100 + 123);
+        let b = tone(660.0, 44100 + 77);
+        let options = PlaybackOptions {
+            crossfade: Crossfade {
+                secs: 0.5,
+                curve: FadeCurve::Linear,
+            },
+            ..plain()
+        };
+        let setup = Setup {
+            options: options.clone(),
+            fade: true,
+            ..Default::default()
+        };
+        let (rendered, _) = render("crossfade", setup, &[&a, &b]);
+        let fade = 22050 * 2;
+        let mut expected = a[..a.len() - fade].to_vec();
+        expected.extend(mix_crossfade(
+            &a[a.len() - fade..],
+            &b[..fade],
+            2,
+            FadeCurve::Linear,
+        ));
+        expected.extend_from_slice(&b[fade..]);
+        assert_eq!(rendered, expected);
+
+        // the same album stays gapless
+        let setup = Setup {
+            options,
+            ..Default::default()
+        };
+        let (rendered, _) = render("no-crossfade", setup, &[&a, &b]);
+        assert_eq!(rendered, [a, b].concat());
+    }
+
+    #[test]
+    fn trims_silence() {
+        let silence = |frames: usize| vec![0.0; frames * 2];
+        let (tone_a, tone_b) = (tone(440.0, 5000), tone(660.0, 4000));
+        let a = [silence(1000), tone_a.clone(), silence(2000)].concat();
+        let b = [silence(300), tone_b.clone(), silence(10)].concat();
+        let setup = Setup {
+            options: PlaybackOptions {
+                trim_silence: true,
+                ..plain()
+            },
+            ..Default::default()
+        };
+        let (rendered, _) = render("trim", setup, &[&a, &b]);
+        assert_eq!(rendered, [tone_a, tone_b].concat());
+    }
+
+    #[test]
+    fn seeks_to_the_frame() {
+        let samples = tone(440.0, 30000);
+        let setup = Setup {
+            // not on a packet boundary
+            seek: Some(Duration::from_secs_f64(12345.0 / 44100.0)),
+            ..Default::default()
+        };
+        let (rendered, events) = render("seek", setup, &[&samples]);
+        assert_eq!(rendered, samples[2 * 12345..]);
+        let Event::Position { at, duration, .. } = &events[1] else {
+            panic!("{events:?}");
+        };
+        assert_eq!((at.as_secs_f64() * 44100.0).round(), 12345.0);
+        assert_eq!(*duration, Some(Duration::from_secs_f64(30000.0 / 44100.0)));
+    }
+
+    #[test]
+    fn plays_faster() {
+        let setup = Setup {
+            speed: 1.5,
+            ..Default::default()
+        };
+        let (rendered, _) = render("speed", setup, &[&tone(440.0, 44100)]);
+        let frames = rendered.len() / 2;
+        assert!(frames.abs_diff(29400) < 2205, "{frames} frames");
+    }
+
+    #[test]
+    fn applies_replay_gain() {
+        let samples = tone(440.0, 10000);
+        let setup = Setup {
+            options: PlaybackOptions {
+                normalization: Normalization {
+                    prevent_clipping: false,
+                    ..Default::default()
+                },
+                ..plain()
+            },
+            replay_gain: ReplayGain {
+                track_gain: Some(-20.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (rendered, _) = render("gain", setup, &[&samples]);
+        assert_eq!(rendered.len(), samples.len());
+        assert!(
+            rendered
+                .iter()
+                .zip(&samples)
+                .all(|(a, b)| (a - b * 0.1).abs() < 1e-6)
+        );
+    }
+}
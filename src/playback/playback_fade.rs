@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+
+/// How the volumes of two tracks change over a crossfade.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FadeCurve {
+    /// Straight lines, which dips in the middle for unrelated tracks
+    Linear,
+    /// Constant power, so that the overall loudness stays the same
+    #[default]
+    EqualPower,
+    /// Slow at both ends and quick in the middle
+    SCurve,
+}
+
+impl FadeCurve {
+    /// The gains of the outgoing and incoming tracks, `t` of the way
+    /// through.
+    pub fn gains(self, t: f32) -> (f32, f32) {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Self::Linear => (1.0 - t, t),
+            Self::EqualPower => {
+                let angle = t * std::f32::consts::FRAC_PI_2;
+                (angle.cos(), angle.sin())
+            }
+            Self::SCurve => {
+                let x = t * t * (3.0 - 2.0 * t);
+                (1.0 - x, x)
+            }
+        }
+    }
+}
+
+/// Crossfading between tracks. Tracks from the same album never crossfade,
+/// so that they stay gapless.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Crossfade {
+    /// Length in seconds, off at 0
+    pub secs: f32,
+    pub curve: FadeCurve,
+}
+
+impl Crossfade {
+    /// Length in frames at a sample rate.
+    pub fn frames(&self, sample_rate: u32) -> usize {
+        (self.secs.max(0.0) * sample_rate as f32) as usize
+    }
+}
+
+/// Mix the end of one track into the start of the next, both interleaved
+/// with `channels` and of the same length.
+pub fn mix_crossfade(tail: &[f32], head: &[f32], channels: usize, curve: FadeCurve) -> Vec<f32> {
+    let frames = tail.len().min(head.len()) / channels;
+    let mut ret = Vec::with_capacity(frames * channels);
+    for x in 0..frames {
+        let (fade_out, fade_in) = curve.gains((x as f32 + 0.5) / frames as f32);
+        for c in 0..channels {
+            let at = x * channels + c;
+            ret.push(tail[at] * fade_out + head[at] * fade_in);
+        }
+    }
+    ret
+}
+
+/// Samples at or below one step of 16-bit audio count as digital silence.
+const SILENCE: f32 = 1.0 / 32768.0;
+
+/// Silence longer than this in the middle of a track is kept as a count
+/// instead, and played back as exact zeros, eg. before a hidden track.
+const MAX_HELD_FRAMES: usize = 48000;
+
+/// Drops digital silence at the start and end of a track. Silence in the
+/// middle is held back until it turns out not to be at the end.
+#[derive(Debug, Default)]
+pub struct SilenceTrim {
+    /// Whether anything but silence came yet
+    started: bool,
+    /// Silent samples that may be at the end
+    held: Vec<f32>,
+    /// Silent frames beyond those held
+    more_frames: usize,
+}
+
+impl SilenceTrim {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start over after a seek. Away from the start of the track, there is
+    /// no leading silence to drop.
+    pub fn reset(&mut self, at_start: bool) {
+        *self = Self {
+            started: !at_start,
+            ..Self::default()
+        };
+    }
+
+    /// Pass on a chunk of the track, without any silence at its start, or
+    /// that may be at its end.
+    pub fn process(&mut self, chunk: &[f32], channels: usize) -> Vec<f32> {
+        let is_silent = |frame: &[f32]| frame.iter().all(|x| x.abs() <= SILENCE);
+        let mut ret = vec![];
+        let mut frames = chunk.chunks_exact(channels);
+        if !self.started {
+            match frames.position(|x| !is_silent(x)) {
+                Some(x) => {
+                    self.started = true;
+                    ret.extend_from_slice(&chunk[x * channels..(x + 1) * channels]);
+                }
+                None => return ret,
+            }
+        }
+        for frame in frames {
+            if !is_silent(frame) {
+                self.flush_into(&mut ret, channels);
+                ret.extend_from_slice(frame);
+            } else if self.held.len() < MAX_HELD_FRAMES * channels {
+                self.held.extend_from_slice(frame);
+            } else {
+                self.more_frames += 1;
+            }
+        }
+        ret
+    }
+
+    /// Silence that was followed by more of the track after all.
+    fn flush_into(&mut self, out: &mut Vec<f32>, channels: usize) {
+        out.append(&mut self.held);
+        out.resize(out.len() + self.more_frames * channels, 0.0);
+        self.more_frames = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_power_keeps_power() {
+        for x in 0..=10 {
+            let (a, b) = FadeCurve::EqualPower.gains(x as f32 / 10.0);
+            assert!((a * a + b * b - 1.0).abs() < 1e-5);
+        }
+        assert_eq!(FadeCurve::SCurve.gains(0.0), (1.0, 0.0));
+        assert_eq!(FadeCurve::SCurve.gains(1.0), (0.0, 1.0));
+    }
+
+    #[test]
+    fn trims_silence_at_the_ends_only() {
+        let mut trim = SilenceTrim::new();
+        let mut out = trim.process(&[0.0, 0.0, 0.0, 0.0, 0.5, 0.5], 2);
+        out.extend(trim.process(&[0.0, 0.0, 0.5, -0.5, 0.0, 0.0], 2));
+        out.extend(trim.process(&[0.0, 0.0, 1e-6, 0.0], 2));
+        assert_eq!(out, [0.5, 0.5, 0.0, 0.0, 0.5, -0.5]);
+    }
+}
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+
+/// The ReplayGain tags of a track. Gains are in dB, peaks are linear with 1
+/// at full scale.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplayGain {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+/// Which gain to play tracks at.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GainMode {
+    /// As they are, without normalising
+    Off,
+    /// Every track at the same loudness
+    #[default]
+    Track,
+    /// Every album at the same loudness, keeping the differences between its
+    /// tracks. Tracks without an album gain use their track gain.
+    Album,
+}
+
+/// Loudness normalisation with ReplayGain.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Normalization {
+    pub mode: GainMode,
+    /// Added to the tagged gain, in dB
+    pub preamp: f32,
+    /// Used instead for tracks without ReplayGain tags, in dB
+    pub fallback: f32,
+    /// Lower the gain where the peak would otherwise clip
+    pub prevent_clipping: bool,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Self {
+            mode: GainMode::Track,
+            preamp: 0.0,
+            // untagged files are mostly modern masters, which are loud
+            fallback: -6.0,
+            prevent_clipping: true,
+        }
+    }
+}
+
+impl Normalization {
+    /// The factor to multiply the samples of a track by.
+    pub fn factor(&self, tags: &ReplayGain) -> f32 {
+        let (gain, peak) = match self.mode {
+            GainMode::Off => return 1.0,
+            GainMode::Album if tags.album_gain.is_some() => (tags.album_gain, tags.album_peak),
+            _ => (tags.track_gain, tags.track_peak),
+        };
+        let factor = match gain {
+            Some(gain) => db_to_factor(gain + self.preamp),
+            None => db_to_factor(self.fallback),
+        };
+        match peak {
+            Some(peak) if self.prevent_clipping && peak > 0.0 => factor.min(1.0 / peak),
+            _ => factor,
+        }
+    }
+}
+
+pub fn db_to_factor(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gain_and_clipping() {
+        let tags = ReplayGain {
+            track_gain: Some(6.0),
+            track_peak: Some(0.8),
+            album_gain: Some(-4.0),
+            album_peak: Some(1.0),
+        };
+        let mut norm = Normalization::default();
+        // +6 dB would clip a peak of 0.8
+        assert_eq!(norm.factor(&tags), 1.25);
+        norm.prevent_clipping = false;
+        assert!((norm.factor(&tags) - 1.995).abs() < 0.001);
+        norm.mode = GainMode::Album;
+        assert!((norm.factor(&tags) - 0.631).abs() < 0.001);
+        assert!((norm.factor(&ReplayGain::default()) - 0.501).abs() < 0.001);
+        norm.mode = GainMode::Off;
+        assert_eq!(norm.factor(&tags), 1.0);
+    }
+}
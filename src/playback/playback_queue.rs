@@ -0,0 +1,361 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::settings;
+
+use super::{RepeatMode, ShuffleKey, ShuffleMode, shuffle_order};
+
+/// How many played tracks are remembered for going back.
+const HISTORY_LEN: usize = 200;
+
+/// What is playing, what plays next, and what played before. Tracks are
+/// kept by path so that the queue survives rescans and restarts. Persisted in
+/// `queue.toml`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayQueue {
+    #[serde(with = "settings::path_text::option")]
+    current: Option<PathBuf>,
+    /// Seconds into the current track
+    position: f64,
+    #[serde(with = "settings::path_text::list")]
+    upcoming: Vec<PathBuf>,
+    /// Most recent last
+    #[serde(with = "settings::path_text::list")]
+    history: VecDeque<PathBuf>,
+    /// Tracks played since the queue was last started or repeated, in the
+    /// order they played, to go around again with `RepeatMode::All`. A
+    /// shuffled queue thus repeats in the same order.
+    #[serde(with = "settings::path_text::list")]
+    cycle: Vec<PathBuf>,
+    pub shuffle: ShuffleMode,
+    pub repeat: RepeatMode,
+}
+
+impl PlayQueue {
+    pub fn load() -> Self {
+        settings::load("queue.toml")
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        settings::save("queue.toml", self)
+    }
+
+    pub fn current(&self) -> Option<&Path> {
+        self.current.as_deref()
+    }
+
+    /// Where to start or resume the current track.
+    pub fn position(&self) -> Duration {
+        Duration::from_secs_f64(self.position)
+    }
+
+    pub fn set_position(&mut self, at: Duration) {
+        self.position = at.as_secs_f64();
+    }
+
+    pub fn upcoming(&self) -> &[PathBuf] {
+        &self.upcoming
+    }
+
+    /// Played tracks, most recent last.
+    pub fn history(&self) -> impl DoubleEndedIterator<Item = &Path> {
+        self.history.iter().map(PathBuf::as_path)
+    }
+
+    /// Play `tracks` now, replacing what was coming up.
+    pub fn play_now(&mut self, tracks: impl IntoIterator<Item = PathBuf>) {
+        self.upcoming.clear();
+        self.upcoming.extend(tracks);
+        self.cycle.clear();
+        self.next();
+    }
+
+    /// Play the first of `tracks` now, from `at`, replacing what was coming up.
+    pub fn play_now_at(&mut self, tracks: impl IntoIterator<Item = PathBuf>, at: Duration) {
+        self.play_now(tracks);
+        self.set_position(at);
+    }
+
+    /// Play `tracks` now in the shuffle order, replacing what was coming up.
+    /// With `keep_first`, the first track plays first anyway, as when picking
+    /// a track out of an album.
+    pub fn play_shuffled(
+        &mut self,
+        tracks: impl IntoIterator<Item = PathBuf>,
+        keep_first: bool,
+        key: impl Fn(&Path) -> ShuffleKey,
+        rng: &mut impl Rng,
+    ) {
+        self.upcoming.clear();
+        self.upcoming.extend(tracks);
+        self.cycle.clear();
+        if keep_first {
+            self.next();
+            self.shuffle_upcoming(key, rng);
+        } else {
+            self.shuffle_upcoming(key, rng);
+            self.next();
+        }
+    }
+
+    /// Put the upcoming tracks in a new shuffle order. Does nothing with
+    /// shuffle off.
+    pub fn shuffle_upcoming(&mut self, key: impl Fn(&Path) -> ShuffleKey, rng: &mut impl Rng) {
+        if self.shuffle == ShuffleMode::Off {
+            return;
+        }
+        let keys = self.upcoming.iter().map(|x| key(x)).collect::<Vec<_>>();
+        let mut upcoming = std::mem::take(&mut self.upcoming)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        self.upcoming = shuffle_order(self.shuffle, &keys, rng)
+            .into_iter()
+            .filter_map(|x| upcoming[x].take())
+            .collect();
+    }
+
+    /// Play `tracks` after the current one, before the rest of the queue.
+    pub fn play_next(&mut self, tracks: impl IntoIterator<Item = PathBuf>) {
+        self.upcoming.splice(0..0, tracks);
+    }
+
+    /// Play `tracks` after everything else.
+    pub fn add(&mut self, tracks: impl IntoIterator<Item = PathBuf>) {
+        self.upcoming.extend(tracks);
+    }
+
+    /// Move the upcoming track at `from` so that it ends up at `to`.
+    pub fn move_entry(&mut self, from: usize, to: usize) {
+        if from >= self.upcoming.len() {
+            return;
+        }
+        let entry = self.upcoming.remove(from);
+        let to = to.min(self.upcoming.len());
+        self.upcoming.insert(to, entry);
+    }
+
+    pub fn remove(&mut self, pos: usize) -> Option<PathBuf> {
+        (pos < self.upcoming.len()).then(|| self.upcoming.remove(pos))
+    }
+
+    /// Forget the upcoming tracks. The current one keeps playing.
+    pub fn clear(&mut self) {
+        self.upcoming.clear();
+    }
+
+    /// Skip to the upcoming track at `pos`, as if the ones before it had
+    /// played.
+    pub fn skip_to(&mut self, pos: usize) -> Option<&Path> {
+        if pos >= self.upcoming.len() {
+            return None;
+        }
+        for _ in 0..pos {
+            self.next();
+        }
+        self.next()
+    }
+
+    /// Move on to the next track, returning it. At the end of the queue, this
+    /// starts over if repeating, and otherwise leaves the queue empty.
+    pub fn next(&mut self) -> Option<&Path> {
+        if let Some(x) = self.current.take() {
+            self.cycle.push(x.clone());
+            self.history.push_back(x);
+            if self.history.len() > HISTORY_LEN {
+                self.history.pop_front();
+            }
+        }
+        if self.upcoming.is_empty() && self.repeat != RepeatMode::Off {
+            self.upcoming = std::mem::take(&mut self.cycle);
+        }
+        self.position = 0.0;
+        self.current = (!self.upcoming.is_empty()).then(|| self.upcoming.remove(0));
+        self.current.as_deref()
+    }
+
+    /// Go back to the track played before the current one, which is put back
+    /// at the front of the queue. Does nothing without history.
+    pub fn previous(&mut self) -> Option<&Path> {
+        let prev = self.history.pop_back()?;
+        if self.cycle.last() == Some(&prev) {
+            self.cycle.pop();
+        }
+        if let Some(x) = self.current.replace(prev) {
+            self.upcoming.insert(0, x);
+        }
+        self.position = 0.0;
+        self.current.as_deref()
+    }
+
+    /// The track that will play once the current one finishes, to get it
+    /// ready ahead of time.
+    pub fn peek_next(&self) -> Option<&Path> {
+        if self.repeat == RepeatMode::One && self.current.is_some() {
+            return self.current.as_deref();
+        }
+        match self.upcoming.first() {
+            Some(x) => Some(x.as_path()),
+            None if self.repeat == RepeatMode::All => self
+                .cycle
+                .first()
+                .or(self.current.as_ref())
+                .map(PathBuf::as_path),
+            None => None,
+        }
+    }
+
+    /// Move on once the current track has finished. Unlike skipping with
+    /// `next`, this plays the same track again with `RepeatMode::One`.
+    pub fn finished(&mut self) -> Option<&Path> {
+        if self.repeat == RepeatMode::One && self.current.is_some() {
+            self.position = 0.0;
+            return self.current.as_deref();
+        }
+        self.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    fn play_all(queue: &mut PlayQueue, count: usize) -> Vec<String> {
+        let mut ret = vec![];
+        for _ in 0..count {
+            let Some(x) = queue.current() else {
+                break;
+            };
+            ret.push(x.to_string_lossy().into_owned());
+            queue.finished();
+        }
+        ret
+    }
+
+    #[test]
+    fn plays_through_and_stops() {
+        let mut queue = PlayQueue::default();
+        queue.play_now(paths(&["a", "b"]));
+        assert_eq!(queue.current(), Some(Path::new("a")));
+        assert_eq!(queue.next(), Some(Path::new("b")));
+        assert_eq!(queue.next(), None);
+        assert_eq!(queue.current(), None);
+        assert!(
+            queue
+                .history()
+                .eq(paths(&["a", "b"]).iter().map(PathBuf::as_path))
+        );
+    }
+
+    #[test]
+    fn previous_goes_back() {
+        let mut queue = PlayQueue::default();
+        queue.play_now(paths(&["a", "b", "c"]));
+        queue.next();
+        assert_eq!(queue.previous(), Some(Path::new("a")));
+        assert_eq!(queue.upcoming(), paths(&["b", "c"]));
+        assert_eq!(queue.previous(), None);
+        assert_eq!(queue.current(), Some(Path::new("a")));
+    }
+
+    #[test]
+    fn history_is_limited() {
+        let names = (0..HISTORY_LEN + 10)
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        let mut queue = PlayQueue::default();
+        queue.play_now(names.iter().map(PathBuf::from));
+        while queue.next().is_some() {}
+        assert_eq!(queue.history().count(), HISTORY_LEN);
+        assert_eq!(queue.history().next(), Some(Path::new("10")));
+    }
+
+    #[test]
+    fn skip_to_adds_the_skipped_to_history() {
+        let mut queue = PlayQueue::default();
+        queue.play_now(paths(&["a", "b", "c", "d"]));
+        assert_eq!(queue.skip_to(1), Some(Path::new("c")));
+        assert_eq!(queue.upcoming(), paths(&["d"]));
+        assert_eq!(queue.history().next_back(), Some(Path::new("b")));
+        assert_eq!(queue.skip_to(5), None);
+    }
+
+    #[test]
+    fn persists() {
+        let mut queue = PlayQueue::default();
+        queue.play_now(paths(&["a", "b", "c"]));
+        queue.next();
+        queue.set_position(Duration::from_secs_f64(12.5));
+        let loaded = toml::from_str::<PlayQueue>(&toml::to_string(&queue).unwrap()).unwrap();
+        assert_eq!(loaded.current(), Some(Path::new("b")));
+        assert_eq!(loaded.position(), Duration::from_secs_f64(12.5));
+        assert_eq!(loaded.upcoming(), paths(&["c"]));
+        assert!(loaded.history().eq([Path::new("a")]));
+
+        // missing fields fall back to their defaults
+        let loaded = toml::from_str::<PlayQueue>("current = \"a\"").unwrap();
+        assert_eq!(loaded.current(), Some(Path::new("a")));
+        assert!(loaded.upcoming().is_empty());
+    }
+
+    #[test]
+    fn repeat_off_stops() {
+        let mut queue = PlayQueue::default();
+        queue.play_now(paths(&["a", "b", "c"]));
+        assert_eq!(play_all(&mut queue, 10), ["a", "b", "c"]);
+        assert_eq!(queue.current(), None);
+    }
+
+    #[test]
+    fn repeat_all_goes_around() {
+        let mut queue = PlayQueue {
+            repeat: RepeatMode::All,
+            ..Default::default()
+        };
+        queue.play_now(paths(&["a", "b"]));
+        queue.add(paths(&["c"]));
+        assert_eq!(play_all(&mut queue, 7), ["a", "b", "c", "a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn repeat_one_until_skipped() {
+        let mut queue = PlayQueue {
+            repeat: RepeatMode::One,
+            ..Default::default()
+        };
+        queue.play_now(paths(&["a", "b"]));
+        assert_eq!(play_all(&mut queue, 3), ["a", "a", "a"]);
+        queue.next();
+        assert_eq!(play_all(&mut queue, 2), ["b", "b"]);
+    }
+
+    #[test]
+    fn shuffled_queue_repeats_in_the_same_order() {
+        let mut queue = PlayQueue {
+            shuffle: ShuffleMode::Tracks,
+            repeat: RepeatMode::All,
+            ..Default::default()
+        };
+        let names = (0..20).map(|x| x.to_string()).collect::<Vec<_>>();
+        let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+        let mut rng = StdRng::seed_from_u64(9);
+        queue.play_shuffled(paths(&names), true, |_| ShuffleKey::default(), &mut rng);
+        let played = play_all(&mut queue, 40);
+        assert_eq!(played[0], "0");
+        assert_eq!(played[..20], played[20..]);
+        assert_ne!(played[..20], names[..]);
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use rand::{Rng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+
+/// How the queue is shuffled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShuffleMode {
+    #[default]
+    Off,
+    /// Every track on its own
+    Tracks,
+    /// Whole albums, with their tracks kept in order
+    Albums,
+    /// Tracks, but avoiding the same artist twice in a row
+    Smart,
+}
+
+/// What happens at the end of the queue, or of a track.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    /// Start the queue over
+    All,
+    /// Play the current track again
+    One,
+}
+
+/// What shuffling needs to know about a track. Names should already be
+/// normalised, as they are compared exactly.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShuffleKey {
+    pub album: Option<String>,
+    pub artist: Option<String>,
+    /// Podcasts and audiobooks stay where they are
+    pub spoken: bool,
+}
+
+/// The order to play tracks in, as indexes into `keys`.
+pub fn shuffle_order(mode: ShuffleMode, keys: &[ShuffleKey], rng: &mut impl Rng) -> Vec<usize> {
+    let mut order = (0..keys.len())
+        .filter(|x| !keys[*x].spoken)
+        .collect::<Vec<_>>();
+    match mode {
+        ShuffleMode::Off => {}
+        ShuffleMode::Tracks => order.shuffle(rng),
+        ShuffleMode::Albums => {
+            // albums in the order they first appear, tracks without an album
+            // on their own
+            let mut albums = vec![];
+            let mut lookup = HashMap::new();
+            for x in order {
+                match &keys[x].album {
+                    Some(album) => {
+                        let at = *lookup.entry(album).or_insert_with(|| {
+                            albums.push(vec![]);
+                            albums.len() - 1
+                        });
+                        albums[at].push(x);
+                    }
+                    None => albums.push(vec![x]),
+                }
+            }
+            albums.shuffle(rng);
+            order = albums.into_iter().flatten().collect();
+        }
+        ShuffleMode::Smart => {
+            order.shuffle(rng);
+            spread_artists(&mut order, keys);
+        }
+    }
+
+    // put the spoken tracks back where they were
+    let mut order = order.into_iter();
+    (0..keys.len())
+        .map(|x| {
+            if keys[x].spoken {
+                x
+            } else {
+                order.next().unwrap()
+            }
+        })
+        .collect()
+}
+
+/// Reorder shuffled tracks so that no artist plays twice in a row, where that
+/// is possible, and otherwise keep the shuffled order.
+fn spread_artists(order: &mut Vec<usize>, keys: &[ShuffleKey]) {
+    let mut left: HashMap<&str, usize> = HashMap::new();
+    for x in order.iter() {
+        if let Some(artist) = &keys[*x].artist {
+            *left.entry(artist).or_default() += 1;
+        }
+    }
+    let mut rest = std::mem::take(order);
+    let mut prev = None;
+    while !rest.is_empty() {
+        // an artist with more than half of what is left has to go now, or it
+        // will end up back to back later
+        let forced = left
+            .iter()
+            .find(|(artist, count)| **count * 2 > rest.len() && prev != Some(**artist))
+            .map(|(artist, _)| *artist);
+        let pos = rest
+            .iter()
+            .position(|x| {
+                let artist = keys[*x].artist.as_deref();
+                match forced {
+                    Some(forced) => artist == Some(forced),
+                    None => artist.is_none() || artist != prev,
+                }
+            })
+            .unwrap_or(0);
+        let x = rest.remove(pos);
+        prev = keys[x].artist.as_deref();
+        if let Some(artist) = prev {
+            *left.get_mut(artist).unwrap() -= 1;
+        }
+        order.push(x);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    fn key(album: &str, artist: &str) -> ShuffleKey {
+        ShuffleKey {
+            album: Some(album.to_owned()),
+            artist: Some(artist.to_owned()),
+            spoken: false,
+        }
+    }
+
+    fn is_permutation(order: &[usize], len: usize) -> bool {
+        let mut sorted = order.to_vec();
+        sorted.sort_unstable();
+        sorted == (0..len).collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn off_keeps_order() {
+        let keys = vec![key("a", "x"); 5];
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(
+            shuffle_order(ShuffleMode::Off, &keys, &mut rng),
+            [0, 1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn same_seed_same_order() {
+        let keys = (0..50)
+            .map(|x| key(&(x / 10).to_string(), &(x % 7).to_string()))
+            .collect::<Vec<_>>();
+        for mode in [ShuffleMode::Tracks, ShuffleMode::Albums, ShuffleMode::Smart] {
+            let a = shuffle_order(mode, &keys, &mut StdRng::seed_from_u64(42));
+            let b = shuffle_order(mode, &keys, &mut StdRng::seed_from_u64(42));
+            assert_eq!(a, b);
+            assert!(is_permutation(&a, keys.len()));
+        }
+        let a = shuffle_order(ShuffleMode::Tracks, &keys, &mut StdRng::seed_from_u64(1));
+        let b = shuffle_order(ShuffleMode::Tracks, &keys, &mut StdRng::seed_from_u64(2));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn albums_stay_together_in_order() {
+        let mut keys = vec![];
+        for album in ["a", "b", "c", "d"] {
+            for _ in 0..4 {
+                keys.push(key(album, "x"));
+            }
+        }
+        let mut rng = StdRng::seed_from_u64(7);
+        let order = shuffle_order(ShuffleMode::Albums, &keys, &mut rng);
+        assert!(is_permutation(&order, keys.len()));
+        for chunk in order.chunks(4) {
+            // one album per chunk, in track order
+            assert!(chunk.iter().all(|x| keys[*x].album == keys[chunk[0]].album));
+            assert!(chunk.windows(2).all(|x| x[0] + 1 == x[1]));
+        }
+        assert_ne!(order, (0..keys.len()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn smart_avoids_repeated_artists() {
+        let keys = (0..30)
+            .map(|x| key(&x.to_string(), ["x", "y", "z"][x % 3]))
+            .collect::<Vec<_>>();
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let order = shuffle_order(ShuffleMode::Smart, &keys, &mut rng);
+            assert!(is_permutation(&order, keys.len()));
+            assert!(
+                order
+                    .windows(2)
+                    .all(|x| keys[x[0]].artist != keys[x[1]].artist)
+            );
+        }
+    }
+
+    #[test]
+    fn smart_repeats_when_it_must() {
+        let mut keys = vec![key("a", "x"); 4];
+        keys.push(key("b", "y"));
+        let mut rng = StdRng::seed_from_u64(3);
+        let order = shuffle_order(ShuffleMode::Smart, &keys, &mut rng);
+        assert!(is_permutation(&order, keys.len()));
+    }
+
+    #[test]
+    fn spoken_stays_in_place() {
+        let mut keys = (0..10)
+            .map(|x| key(&x.to_string(), &x.to_string()))
+            .collect::<Vec<_>>();
+        keys[3].spoken = true;
+        keys[7].spoken = true;
+        for mode in [ShuffleMode::Tracks, ShuffleMode::Albums, ShuffleMode::Smart] {
+            let mut rng = StdRng::seed_from_u64(5);
+            let order = shuffle_order(mode, &keys, &mut rng);
+            assert!(is_permutation(&order, keys.len()));
+            assert_eq!(order[3], 3);
+            assert_eq!(order[7], 7);
+        }
+    }
+}
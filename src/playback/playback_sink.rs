@@ -0,0 +1,377 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+use super::{ChannelMixer, OutputOptions, PlaybackError, Quantizer, Resampler, StreamSpec};
+
+/// Where decoded audio goes. Writes are a continuous stream, so anything
+/// written back to back plays without a gap.
+pub trait AudioSink {
+    /// Get ready for samples of `spec`. This is called before the first
+    /// write, and again whenever the spec changes.
+    fn configure(&mut self, spec: StreamSpec) -> Result<(), PlaybackError>;
+
+    /// Queue interleaved samples, blocking while the sink is full.
+    fn write(&mut self, samples: &[f32]) -> Result<(), PlaybackError>;
+
+    /// Drop whatever was written but has not played yet, eg. when skipping.
+    fn discard(&mut self) {}
+
+    fn set_paused(&mut self, _paused: bool) {}
+
+    /// How much of what was written has not played yet.
+    fn buffered(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Change how audio is converted for the output, if it is.
+    fn set_output(&mut self, _options: OutputOptions) {}
+
+    /// An error since the last call that did not stop playback, eg. from the
+    /// device.
+    fn take_error(&mut self) -> Option<PlaybackError> {
+        None
+    }
+}
+
+/// Writes audio into a 32-bit float WAV file, for tests.
+#[cfg(test)]
+pub struct WavSink {
+    path: std::path::PathBuf,
+    writer: Option<hound::WavWriter<std::io::BufWriter<std::fs::File>>>,
+}
+
+#[cfg(test)]
+impl WavSink {
+    /// The file is created once the format is known.
+    pub fn new(path: std::path::PathBuf) -> Self {
+        Self { path, writer: None }
+    }
+
+    /// Write the header out. Dropping the sink does the same, but ignores
+    /// errors.
+    pub fn finish(self) -> Result<(), PlaybackError> {
+        if let Some(writer) = self.writer {
+            writer.finalize().map_err(wav_error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl AudioSink for WavSink {
+    fn configure(&mut self, spec: StreamSpec) -> Result<(), PlaybackError> {
+        let wav_spec = hound::WavSpec {
+            channels: spec.channels,
+            sample_rate: spec.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        match &self.writer {
+            Some(x) if x.spec() == wav_spec => Ok(()),
+            // a WAV file has a single format throughout
+            Some(_) => Err(PlaybackError::Device(
+                "cannot change the format of a WAV file".to_owned(),
+            )),
+            None => {
+                self.writer =
+                    Some(hound::WavWriter::create(&self.path, wav_spec).map_err(wav_error)?);
+                Ok(())
+            }
+        }
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), PlaybackError> {
+        let writer = self
+            .writer
+            .as_mut()
+            .ok_or_else(|| PlaybackError::Device("not configured".to_owned()))?;
+        for x in samples {
+            writer.write_sample(*x).map_err(wav_error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn wav_error(err: hound::Error) -> PlaybackError {
+    match err {
+        hound::Error::IoError(err) => PlaybackError::Io(err),
+        err => PlaybackError::Device(err.to_string()),
+    }
+}
+
+/// The name of the default output device, which some settings are saved
+/// per.
+pub fn output_name() -> String {
+    cpal::default_host()
+        .default_output_device()
+        .and_then(|x| x.name().ok())
+        .unwrap_or_else(|| "Default".to_owned())
+}
+
+/// Samples waiting for the audio callback, already at the device's rate.
+#[derive(Default)]
+struct Buffer {
+    samples: VecDeque<f32>,
+    paused: bool,
+    /// The last error from the audio callback
+    error: Option<String>,
+}
+
+/// Plays audio on the default output device, converting it to a format the
+/// device supports.
+#[derive(Default)]
+pub struct DeviceSink {
+    shared: Arc<(Mutex<Buffer>, Condvar)>,
+    stream: Option<cpal::Stream>,
+    options: OutputOptions,
+    /// What is written, and the sample rate and channels of the device
+    spec: Option<StreamSpec>,
+    rate: u32,
+    channels: u16,
+    mixer: Option<ChannelMixer>,
+    resampler: Option<Resampler>,
+    quantizer: Option<Quantizer>,
+    /// Failing to open the device again with new options
+    error: Option<PlaybackError>,
+}
+
+impl DeviceSink {
+    /// How much audio to keep buffered ahead of the device
+    const BUFFER_SECS: f32 = 0.25;
+
+    fn capacity(&self) -> usize {
+        (self.rate as f32 * self.channels as f32 * Self::BUFFER_SECS) as usize
+    }
+
+    /// Pick the channels, rate and sample format to open the device at.
+    /// The audio's own channels are kept where the device takes them, and
+    /// otherwise the device's default layout. Bit-perfect playback asks for
+    /// the rate of the audio and the deepest format, otherwise the device's
+    /// defaults are kept, so that the system does not convert again.
+    fn choose_config(
+        &self,
+        device: &cpal::Device,
+        spec: StreamSpec,
+    ) -> Result<(cpal::StreamConfig, cpal::SampleFormat), PlaybackError> {
+        let default = device.default_output_config().map_err(device_error)?;
+        let supported = device
+            .supported_output_configs()
+            .map(|x| {
+                x.filter(|x| bits(x.sample_format()).is_some())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let channels = [spec.channels, default.channels()]
+            .into_iter()
+            .find(|x| supported.iter().any(|y| y.channels() == *x))
+            .or_else(|| supported.first().map(|x| x.channels()));
+        let configs = supported
+            .into_iter()
+            .filter(|x| Some(x.channels()) == channels)
+            .collect::<Vec<_>>();
+        let at_rate = |rate: u32| {
+            configs
+                .iter()
+                .filter(move |x| x.min_sample_rate().0 <= rate && rate <= x.max_sample_rate().0)
+        };
+
+        let native = self
+            .options
+            .bit_perfect
+            .then(|| {
+                at_rate(spec.sample_rate)
+                    .max_by_key(|x| bits(x.sample_format()))
+                    .map(|x| x.with_sample_rate(cpal::SampleRate(spec.sample_rate)))
+            })
+            .flatten();
+        let chosen = native.or_else(|| {
+            at_rate(default.sample_rate().0)
+                .max_by_key(|x| x.sample_format() == default.sample_format())
+                .map(|x| x.with_sample_rate(default.sample_rate()))
+        });
+        Ok(match chosen {
+            Some(x) => (x.config(), x.sample_format()),
+            // the device does not say, so go with its default
+            None => (default.config(), default.sample_format()),
+        })
+    }
+
+    /// Open the device for `spec`, letting the old stream play out first.
+    fn open(&mut self, spec: StreamSpec) -> Result<(), PlaybackError> {
+        // let the old stream play out before switching
+        if let Some(resampler) = &mut self.resampler {
+            let tail = resampler.flush()?;
+            self.push(&tail);
+        }
+        {
+            let (buffer, cond) = &*self.shared;
+            let mut buffer = buffer.lock().unwrap();
+            while !buffer.samples.is_empty() && !buffer.paused && self.stream.is_some() {
+                buffer = cond.wait(buffer).unwrap();
+            }
+            buffer.samples.clear();
+        }
+        self.stream = None;
+        self.spec = None;
+        self.mixer = None;
+        self.resampler = None;
+
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| PlaybackError::Device("no output device".to_owned()))?;
+        let (config, format) = self.choose_config(&device, spec)?;
+        let shared = self.shared.clone();
+        let stream = match format {
+            cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, shared),
+            cpal::SampleFormat::I32 => build_stream::<i32>(&device, &config, shared),
+            _ => build_stream::<f32>(&device, &config, shared),
+        }?;
+        stream.play().map_err(device_error)?;
+
+        let rate = config.sample_rate.0;
+        let channels = config.channels;
+        self.mixer = (channels != spec.channels)
+            .then(|| ChannelMixer::new(spec.channels as usize, channels as usize));
+        self.resampler = if rate == spec.sample_rate {
+            None
+        } else {
+            let channels = channels as usize;
+            let quality = self.options.quality;
+            Some(Resampler::new(spec.sample_rate, rate, channels, quality)?)
+        };
+        let bits = bits(format).filter(|_| format != cpal::SampleFormat::F32);
+        self.quantizer = Some(Quantizer::new(bits, self.options.dither));
+        self.stream = Some(stream);
+        self.spec = Some(spec);
+        self.rate = rate;
+        self.channels = channels;
+        Ok(())
+    }
+
+    /// Queue samples at the device's rate, blocking while the buffer is
+    /// full.
+    fn push(&mut self, samples: &[f32]) {
+        let capacity = self.capacity();
+        let (buffer, cond) = &*self.shared;
+        let mut buffer = buffer.lock().unwrap();
+        // nothing plays while paused, so that would never end
+        while buffer.samples.len() >= capacity && !buffer.paused {
+            buffer = cond.wait(buffer).unwrap();
+        }
+        buffer.samples.extend(samples);
+    }
+}
+
+/// Bits per sample of the formats the device can be fed, ordered so that
+/// deeper is better. Floats hold 24 bits exactly.
+fn bits(format: cpal::SampleFormat) -> Option<u32> {
+    match format {
+        cpal::SampleFormat::I16 => Some(16),
+        cpal::SampleFormat::F32 => Some(24),
+        cpal::SampleFormat::I32 => Some(32),
+        _ => None,
+    }
+}
+
+fn device_error(err: impl std::fmt::Display) -> PlaybackError {
+    PlaybackError::Device(err.to_string())
+}
+
+/// Open a stream that plays from the buffer.
+fn build_stream<T: cpal::SizedSample + cpal::FromSample<f32>>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    shared: Arc<(Mutex<Buffer>, Condvar)>,
+) -> Result<cpal::Stream, PlaybackError> {
+    let errors = shared.clone();
+    device
+        .build_output_stream(
+            config,
+            move |out: &mut [T], _| {
+                let (buffer, cond) = &*shared;
+                let mut buffer = buffer.lock().unwrap();
+                for x in out {
+                    *x = T::from_sample(if buffer.paused {
+                        0.0
+                    } else {
+                        buffer.samples.pop_front().unwrap_or(0.0)
+                    });
+                }
+                cond.notify_all();
+            },
+            move |err| errors.0.lock().unwrap().error = Some(err.to_string()),
+            None,
+        )
+        .map_err(device_error)
+}
+
+impl AudioSink for DeviceSink {
+    fn configure(&mut self, spec: StreamSpec) -> Result<(), PlaybackError> {
+        if self.spec == Some(spec) {
+            return Ok(());
+        }
+        self.open(spec)
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), PlaybackError> {
+        if self.stream.is_none() {
+            return Err(PlaybackError::Device("not configured".to_owned()));
+        }
+        let mixed = self.mixer.as_ref().map(|x| x.process(samples));
+        let samples = mixed.as_deref().unwrap_or(samples);
+        let mut samples = match &mut self.resampler {
+            Some(resampler) => resampler.process(samples)?,
+            None => samples.to_vec(),
+        };
+        if let Some(quantizer) = &mut self.quantizer {
+            quantizer.process(&mut samples);
+        }
+        self.push(&samples);
+        Ok(())
+    }
+
+    fn discard(&mut self) {
+        if let Some(resampler) = &mut self.resampler {
+            resampler.reset();
+        }
+        self.shared.0.lock().unwrap().samples.clear();
+        self.shared.1.notify_all();
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.shared.0.lock().unwrap().paused = paused;
+        self.shared.1.notify_all();
+    }
+
+    fn buffered(&self) -> Duration {
+        if self.spec.is_none() {
+            return Duration::ZERO;
+        }
+        let samples = self.shared.0.lock().unwrap().samples.len();
+        Duration::from_secs_f64(samples as f64 / self.channels as f64 / self.rate as f64)
+    }
+
+    fn set_output(&mut self, options: OutputOptions) {
+        if options == self.options {
+            return;
+        }
+        self.options = options;
+        // open the device again, once what is buffered has played
+        if let Some(spec) = self.spec
+            && let Err(err) = self.open(spec)
+        {
+            self.error = Some(err);
+        }
+    }
+
+    fn take_error(&mut self) -> Option<PlaybackError> {
+        let error = self.shared.0.lock().unwrap().error.take();
+        self.error.take().or(error.map(PlaybackError::Device))
+    }
+}
@@ -0,0 +1,229 @@
+use std::f32::consts::TAU;
+
+/// Changes the speed of a stream without changing its pitch, by waveform
+/// similarity overlap-add (WSOLA): windows of the input are taken at the
+/// speed, each shifted a little to line up with the one before it, then
+/// overlapped at the original rate.
+///
+/// This is meant for speech, where it sounds natural well past double speed.
+#[derive(Debug)]
+pub struct TimeStretch {
+    speed: f64,
+    channels: usize,
+    /// Length of a window in frames. Windows overlap by half.
+    window: usize,
+    /// How far a window may be shifted to line up, in frames
+    search: usize,
+    /// Hann window, which adds up to 1 when overlapped by half
+    weights: Vec<f32>,
+    /// Interleaved input not yet done with
+    input: Vec<f32>,
+    /// Where the next window would be taken from at exactly the speed, in
+    /// frames into `input`
+    at: f64,
+    /// Where the window after the last one would have been taken from at
+    /// normal speed, which the next window is lined up with
+    natural: Option<usize>,
+    /// The second half of the last window, still to be overlapped
+    tail: Vec<f32>,
+}
+
+impl TimeStretch {
+    /// Window length, which should be a few pitch periods of a voice
+    const WINDOW: f64 = 0.04;
+    /// How far a window may be shifted, which covers one period down to 50 Hz
+    const SEARCH: f64 = 0.01;
+    /// Only every so many frames are compared when lining up, which is close
+    /// enough and a lot faster
+    const STRIDE: usize = 2;
+
+    pub fn new(speed: f32, sample_rate: u32, channels: u16) -> Self {
+        let window = ((sample_rate as f64 * Self::WINDOW) as usize / 2 * 2).max(2);
+        let weights = (0..window)
+            .map(|x| 0.5 - 0.5 * (x as f32 / window as f32 * TAU).cos())
+            .collect();
+        Self {
+            speed: speed.into(),
+            channels: channels.max(1).into(),
+            window,
+            search: (sample_rate as f64 * Self::SEARCH) as usize,
+            weights,
+            input: vec![],
+            at: 0.0,
+            natural: None,
+            tail: vec![],
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.into();
+    }
+
+    /// Stretch some interleaved samples. The output lags behind by about a
+    /// window, which `finish` gives back at the end.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.input.extend_from_slice(samples);
+        let mut ret = vec![];
+        while let Some(x) = self.step() {
+            ret.extend(x);
+        }
+        ret
+    }
+
+    /// The rest of the output, at the end of the stream.
+    pub fn finish(&mut self) -> Vec<f32> {
+        if self.input.is_empty() && self.tail.is_empty() {
+            return vec![];
+        }
+        // pad with silence, so that every frame makes it into some window
+        let end = (self.input.len() / self.channels) as f64;
+        let pad = (self.window + self.search) * self.channels;
+        self.input.extend(std::iter::repeat_n(0.0, pad));
+        let mut ret = vec![];
+        while self.at < end
+            && let Some(x) = self.step()
+        {
+            ret.extend(x);
+        }
+        ret.append(&mut self.tail);
+        self.reset();
+        ret
+    }
+
+    /// Forget the stream so far, eg. after seeking.
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.at = 0.0;
+        self.natural = None;
+        self.tail.clear();
+    }
+
+    /// Overlap the next window, giving back half a window of output, or
+    /// `None` if more input is needed first.
+    fn step(&mut self) -> Option<Vec<f32>> {
+        let (channels, half) = (self.channels, self.window / 2);
+        let ideal = self.at.round() as usize;
+        if self.input.len() / channels < ideal + self.search + self.window {
+            return None;
+        }
+        let at = match self.natural {
+            Some(natural) => self.line_up(ideal, natural),
+            None => ideal,
+        };
+
+        let window = &self.input[at * channels..(at + self.window) * channels];
+        let weight = |e: usize| self.weights[e / channels];
+        let ret = (0..half * channels)
+            .map(|e| self.tail.get(e).copied().unwrap_or(0.0) + window[e] * weight(e))
+            .collect();
+        self.tail = (half * channels..self.window * channels)
+            .map(|e| window[e] * weight(e))
+            .collect();
+        self.at += half as f64 * self.speed;
+
+        // drop the input that no window can be taken from anymore
+        let natural = at + half;
+        let done = natural.min((self.at as usize).saturating_sub(self.search));
+        self.input.drain(..done * channels);
+        self.at -= done as f64;
+        self.natural = Some(natural - done);
+        Some(ret)
+    }
+
+    /// Where around `ideal` the window best continues the one before it,
+    /// that is, looks the most like the input from `natural` on.
+    fn line_up(&self, ideal: usize, natural: usize) -> usize {
+        let channels = self.channels;
+        let len = self.window / 2;
+        let target = &self.input[natural * channels..(natural + len) * channels];
+        let low = ideal.saturating_sub(self.search);
+        (low..=ideal + self.search)
+            .map(|at| {
+                let candidate = &self.input[at * channels..(at + len) * channels];
+                let (mut dot, mut energy) = (0.0, 1e-9);
+                for e in (0..target.len()).step_by(channels * Self::STRIDE) {
+                    for c in e..e + channels {
+                        dot += target[c] * candidate[c];
+                        energy += candidate[c] * candidate[c];
+                    }
+                }
+                (at, dot / energy.sqrt())
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(ideal, |(at, _)| at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(freq: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|x| {
+                let sample = 0.5 * (x as f32 / 44100.0 * freq * TAU).sin();
+                [sample, -sample]
+            })
+            .collect()
+    }
+
+    /// Run samples through in uneven chunks, as the decoder would.
+    fn stretch(speed: f32, samples: &[f32]) -> Vec<f32> {
+        let mut stretch = TimeStretch::new(speed, 44100, 2);
+        let mut ret = vec![];
+        for chunk in samples.chunks(2 * 1153) {
+            ret.extend(stretch.process(chunk));
+        }
+        ret.extend(stretch.finish());
+        ret
+    }
+
+    /// The frequency of the left channel, from how often it crosses zero.
+    fn frequency(samples: &[f32]) -> f32 {
+        let left = samples.iter().step_by(2).collect::<Vec<_>>();
+        let crossings = left
+            .windows(2)
+            .filter(|x| (*x[0] < 0.0) != (*x[1] < 0.0))
+            .count();
+        crossings as f32 / 2.0 / (left.len() as f32 / 44100.0)
+    }
+
+    #[test]
+    fn keeps_the_pitch() {
+        let samples = tone(440.0, 44100);
+        for speed in [0.75, 1.0, 1.5, 2.0] {
+            let stretched = stretch(speed, &samples);
+            let frames = stretched.len() / 2;
+            let expected = 44100.0 / speed;
+            assert!(
+                (frames as f32 - expected).abs() < 0.05 * 44100.0,
+                "{speed}: {frames} frames"
+            );
+            // away from the fade in and the padding at the end
+            let middle = &stretched[2 * 4410..2 * (frames - 4410)];
+            let freq = frequency(middle);
+            assert!((freq - 440.0).abs() < 440.0 * 0.02, "{speed}: {freq} Hz");
+            assert!(
+                middle.chunks(2).all(|x| x[0] == -x[1]),
+                "{speed}: channels mixed"
+            );
+            // windows line up without clicks
+            let jump = middle
+                .iter()
+                .step_by(2)
+                .collect::<Vec<_>>()
+                .windows(2)
+                .map(|x| (x[1] - x[0]).abs())
+                .fold(0.0, f32::max);
+            assert!(jump < 0.04, "{speed}: jumps by {jump}");
+        }
+    }
+
+    #[test]
+    fn finishes_once() {
+        let mut stretch = TimeStretch::new(1.5, 44100, 2);
+        stretch.process(&tone(440.0, 1000));
+        assert!(!stretch.finish().is_empty());
+        assert!(stretch.finish().is_empty());
+    }
+}
@@ -0,0 +1,388 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+/// Where settings and other persisted state live. Follows the XDG base
+/// directory spec, falling back on `~/.config/mioplays`.
+static CONFIG_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".config")))
+        .unwrap_or_default()
+        .join("mioplays")
+});
+
+/// Expand a leading "~" into the home directory.
+pub fn expand_home(inp: &std::path::Path) -> PathBuf {
+    match (inp.strip_prefix("~"), std::env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => inp.to_owned(),
+    }
+}
+
+/// Read a toml file out of the config directory, falling back on the default
+/// if it does not exist or cannot be parsed.
+pub fn load<T: DeserializeOwned + Default>(name: &str) -> T {
+    load_from(&CONFIG_DIR.join(name))
+}
+
+/// Read a toml file, falling back on the default if it does not exist or
+/// cannot be parsed. A file that cannot be parsed is moved aside first, so
+/// that the next save does not overwrite what was in it.
+fn load_from<T: DeserializeOwned + Default>(path: &Path) -> T {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return Default::default();
+    };
+    toml::from_str(&text).unwrap_or_else(|_| {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(format!(".{}.bak", jiff::Timestamp::now().as_second()));
+        // if even that fails, there is nowhere else to keep it
+        let _ = std::fs::rename(path, backup);
+        Default::default()
+    })
+}
+
+/// Write a toml file into the config directory.
+pub fn save<T: Serialize>(name: &str, value: &T) -> std::io::Result<()> {
+    std::fs::create_dir_all(&*CONFIG_DIR)?;
+    let value = toml::to_string_pretty(value).map_err(std::io::Error::other)?;
+    std::fs::write(CONFIG_DIR.join(name), value)
+}
+
+/// Paths as text, for TOML, which can only hold UTF-8. Paths that are not
+/// UTF-8 are kept as a NUL, which no path can contain, and then their bytes
+/// in hex, so that nothing is lost.
+pub mod path_text {
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    };
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn encode(path: &Path) -> String {
+        match path.to_str() {
+            Some(x) => x.to_owned(),
+            None => {
+                let bytes = path.as_os_str().as_encoded_bytes();
+                std::iter::once('\0'.to_string())
+                    .chain(bytes.iter().map(|x| format!("{x:02x}")))
+                    .collect()
+            }
+        }
+    }
+
+    pub fn decode(text: &str) -> PathBuf {
+        let Some(hex) = text.strip_prefix('\0') else {
+            return PathBuf::from(text);
+        };
+        let bytes = (0..hex.len() / 2)
+            .map(|x| u8::from_str_radix(hex.get(2 * x..2 * x + 2)?, 16).ok())
+            .collect::<Option<Vec<_>>>();
+        match bytes {
+            #[cfg(unix)]
+            Some(bytes) => PathBuf::from(
+                <std::ffi::OsString as std::os::unix::ffi::OsStringExt>::from_vec(bytes),
+            ),
+            // elsewhere paths that are not Unicode do not come up
+            #[cfg(not(unix))]
+            Some(bytes) => PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()),
+            None => PathBuf::from(text),
+        }
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        encode(path).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        Ok(decode(&String::deserialize(deserializer)?))
+    }
+
+    /// For an optional path.
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            path: &Option<PathBuf>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            path.as_deref().map(encode).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<PathBuf>, D::Error> {
+            Ok(Option::<String>::deserialize(deserializer)?.map(|x| decode(&x)))
+        }
+    }
+
+    /// For a list of paths, such as a `Vec` or `VecDeque`.
+    pub mod list {
+        use super::*;
+
+        pub fn serialize<'a, C, S>(paths: &'a C, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            &'a C: IntoIterator<Item = &'a PathBuf>,
+            S: Serializer,
+        {
+            serializer.collect_seq(paths.into_iter().map(|x| encode(x)))
+        }
+
+        pub fn deserialize<'de, C, D>(deserializer: D) -> Result<C, D::Error>
+        where
+            C: FromIterator<PathBuf>,
+            D: Deserializer<'de>,
+        {
+            Ok(Vec::<String>::deserialize(deserializer)?
+                .iter()
+                .map(|x| decode(x))
+                .collect())
+        }
+    }
+
+    /// For a map keyed by path.
+    pub mod keys {
+        use super::*;
+
+        pub fn serialize<V: Serialize, S: Serializer>(
+            map: &HashMap<PathBuf, V>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(map.iter().map(|(k, v)| (encode(k), v)))
+        }
+
+        pub fn deserialize<'de, V: Deserialize<'de>, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<HashMap<PathBuf, V>, D::Error> {
+            Ok(HashMap::<String, V>::deserialize(deserializer)?
+                .into_iter()
+                .map(|(k, v)| (decode(&k), v))
+                .collect())
+        }
+    }
+}
+
+/// User settings, persisted in `settings.toml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Anything under these folders is treated as a podcast.
+    pub podcast_dirs: Vec<PathBuf>,
+    /// Anything under these folders is treated as an audiobook.
+    pub audiobook_dirs: Vec<PathBuf>,
+    /// Playback speed for podcasts and audiobooks.
+    pub spoken_speed: f32,
+    pub playback: crate::playback::PlaybackOptions,
+    /// Equalizer and other processing, per output device name
+    pub dsp: HashMap<String, crate::playback::DspOptions>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            podcast_dirs: vec![PathBuf::from("~/Podcasts")],
+            audiobook_dirs: vec![PathBuf::from("~/Audiobooks")],
+            spoken_speed: 1.0,
+            playback: Default::default(),
+            dsp: HashMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        load("settings.toml")
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        save("settings.toml", self)
+    }
+}
+
+/// Genre aliases and hierarchy, persisted in `genres.toml`.
+///
+/// Genres are already matched regardless of case and punctuation, so "Hip-Hop"
+/// and "hip hop" are the same genre without an alias.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenreConfig {
+    /// Genre name to the other names that mean the same genre.
+    pub aliases: HashMap<String, Vec<String>>,
+    /// Genre name to its parent genre, eg. "Post-Rock" = "Rock".
+    pub parents: HashMap<String, String>,
+}
+
+impl Default for GenreConfig {
+    fn default() -> Self {
+        Self {
+            aliases: HashMap::from([
+                (
+                    "Hip Hop".to_owned(),
+                    vec!["Rap/Hip Hop".to_owned(), "Hip-Hop/Rap".to_owned()],
+                ),
+                (
+                    "R&B".to_owned(),
+                    vec!["RnB".to_owned(), "Rhythm and Blues".to_owned()],
+                ),
+            ]),
+            parents: HashMap::new(),
+        }
+    }
+}
+
+impl GenreConfig {
+    pub fn load() -> Self {
+        load("genres.toml")
+    }
+}
+
+/// Where playback was left off for podcasts and audiobooks, persisted in
+/// `resume.toml`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResumePositions {
+    /// Seconds into each file
+    #[serde(with = "path_text::keys")]
+    positions: HashMap<PathBuf, f64>,
+}
+
+impl ResumePositions {
+    pub fn load() -> Self {
+        load("resume.toml")
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        save("resume.toml", self)
+    }
+
+    pub fn get(&self, path: &std::path::Path) -> Option<Duration> {
+        self.positions
+            .get(path)
+            .map(|x| Duration::from_secs_f64(*x))
+    }
+
+    /// Forget where a file was stopped, returning whether it was known.
+    pub fn forget(&mut self, path: &std::path::Path) -> bool {
+        self.positions.remove(path).is_some()
+    }
+
+    /// Remember where playback stopped. Positions near the very start or end
+    /// of the file are forgotten instead, so that finished episodes start over.
+    pub fn remember(&mut self, path: PathBuf, at: Duration, length: Duration) {
+        const MARGIN: Duration = Duration::from_secs(10);
+        if at < MARGIN || at + MARGIN > length {
+            self.positions.remove(&path);
+        } else {
+            self.positions.insert(path, at.as_secs_f64());
+        }
+    }
+}
+
+/// How often and when a file was played, and when it was first seen.
+/// Times are in seconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayStat {
+    pub plays: u32,
+    pub last_played: Option<i64>,
+    pub added: Option<i64>,
+}
+
+/// Play stats of every file, persisted in `stats.toml`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayStats {
+    #[serde(with = "path_text::keys")]
+    stats: HashMap<PathBuf, PlayStat>,
+}
+
+impl PlayStats {
+    pub fn load() -> Self {
+        load("stats.toml")
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        save("stats.toml", self)
+    }
+
+    /// The stats of a file found during a scan. Files not seen before are
+    /// marked as added now.
+    pub fn scanned(&mut self, path: &std::path::Path) -> PlayStat {
+        let stat = self.stats.entry(path.to_owned()).or_default();
+        stat.added
+            .get_or_insert_with(|| jiff::Timestamp::now().as_second());
+        *stat
+    }
+
+    /// Count a play of a file, returning its new stats.
+    pub fn played(&mut self, path: &std::path::Path) -> PlayStat {
+        let stat = self.stats.entry(path.to_owned()).or_default();
+        stat.plays += 1;
+        stat.last_played = Some(jiff::Timestamp::now().as_second());
+        *stat
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_survive_as_text() {
+        let plain = Path::new("/music/Motörhead/01 Ace of Spades.flac");
+        assert_eq!(path_text::encode(plain), plain.to_str().unwrap());
+        assert_eq!(path_text::decode(&path_text::encode(plain)), plain);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let latin1 = Path::new(std::ffi::OsStr::from_bytes(b"/music/Mot\xf6rhead.flac"));
+            let text = path_text::encode(latin1);
+            assert!(text.starts_with('\0'));
+            assert_eq!(path_text::decode(&text), latin1);
+
+            // and in a file, as a key
+            let mut stats = PlayStats::default();
+            stats.played(latin1);
+            stats.played(plain);
+            let text = toml::to_string_pretty(&stats).unwrap();
+            let back = toml::from_str::<PlayStats>(&text).unwrap();
+            assert_eq!(back.stats[latin1].plays, 1);
+            assert_eq!(back.stats[plain].plays, 1);
+        }
+    }
+
+    #[test]
+    fn keeps_files_it_cannot_read() {
+        let path = std::env::temp_dir().join(format!("mioplays-{}-stats.toml", std::process::id()));
+        std::fs::write(&path, "stats = 'not a table'").unwrap();
+        let stats = load_from::<PlayStats>(&path);
+        assert!(stats.stats.is_empty());
+        assert!(!path.exists());
+
+        // the file was moved aside, not lost
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let backup = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .find(|x| {
+                let x = x.file_name().unwrap().to_str().unwrap_or_default();
+                x.starts_with(name) && x.ends_with(".bak")
+            })
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&backup).unwrap(),
+            "stats = 'not a table'"
+        );
+        std::fs::remove_file(backup).unwrap();
+
+        // and a missing file is just the default
+        assert!(load_from::<PlayStats>(&path).stats.is_empty());
+    }
+}
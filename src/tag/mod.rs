@@ -1,5 +1,17 @@
+mod tag_art;
+mod tag_chapter;
+mod tag_date;
+mod tag_lang;
+mod tag_name;
+mod tag_pos;
 mod tag_read;
 mod tag_set;
 
+pub use tag_art::*;
+pub use tag_chapter::*;
+pub use tag_date::*;
+pub use tag_lang::*;
+pub(crate) use tag_name::*;
+pub use tag_pos::*;
 pub use tag_read::*;
 pub use tag_set::*;
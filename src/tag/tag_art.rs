@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use lofty::{file::TaggedFileExt, picture::PictureType};
+
+/// Names of the image files that may hold the cover of the album in a folder,
+/// without their extensions.
+const FOLDER_ART: [&str; 4] = ["cover", "folder", "front", "album"];
+
+/// Read the cover art of a file. This is the front cover out of its tags,
+/// falling back on any other picture in the tags, then on a "cover.jpg" or
+/// similar image next to it.
+///
+/// Cover art is not kept around after scanning, as it would take up far too
+/// much memory, so this reads it again on demand.
+pub fn read_cover_art(path: &Path) -> Option<image::RgbaImage> {
+    read_tagged_art(path)
+        .or_else(|| read_folder_art(path.parent()?))
+        .map(|x| x.into_rgba8())
+}
+
+fn read_tagged_art(path: &Path) -> Option<image::DynamicImage> {
+    let file = lofty::probe::Probe::open(path)
+        .ok()?
+        .options(lofty::config::ParseOptions::new().read_properties(false))
+        .guess_file_type()
+        .ok()?
+        .read()
+        .ok()?;
+    let mut pictures = file.tags().iter().flat_map(|x| x.pictures());
+    let picture = pictures
+        .clone()
+        .find(|x| x.pic_type() == PictureType::CoverFront)
+        .or_else(|| pictures.next())?;
+    image::load_from_memory(picture.data()).ok()
+}
+
+fn read_folder_art(dir: &Path) -> Option<image::DynamicImage> {
+    let mut candidates = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|x| x.ok())
+        .map(|x| x.path())
+        .filter(|x| {
+            let stem = x.file_stem().and_then(|x| x.to_str()).unwrap_or_default();
+            let ext = x.extension().and_then(|x| x.to_str()).unwrap_or_default();
+            FOLDER_ART.iter().any(|x| stem.eq_ignore_ascii_case(x))
+                && ["jpg", "jpeg", "png"]
+                    .iter()
+                    .any(|x| ext.eq_ignore_ascii_case(x))
+        })
+        .collect::<Vec<_>>();
+    // prefer the names in the order they are listed
+    candidates.sort_by_key(|x| {
+        let stem = x.file_stem().and_then(|x| x.to_str()).unwrap_or_default();
+        FOLDER_ART.iter().position(|y| stem.eq_ignore_ascii_case(y))
+    });
+    candidates.into_iter().find_map(|x| image::open(x).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folder_art() {
+        let dir = std::env::temp_dir().join(format!("mioplays-art-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = |width| image::RgbaImage::new(width, 1);
+        image(1).save(dir.join("folder.png")).unwrap();
+        image(2).save(dir.join("Cover.png")).unwrap();
+        std::fs::write(dir.join("cover.txt"), "not an image").unwrap();
+        std::fs::write(dir.join("track.flac"), "not a flac").unwrap();
+
+        // untagged tracks fall back on the folder, preferring "cover"
+        let art = read_cover_art(&dir.join("track.flac")).unwrap();
+        assert_eq!(art.width(), 2);
+        std::fs::remove_file(dir.join("Cover.png")).unwrap();
+        let art = read_cover_art(&dir.join("track.flac")).unwrap();
+        assert_eq!(art.width(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+
+use lofty::file::FileType;
+
+/// A chapter marker, as found in audiobooks and podcasts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    pub start: Duration,
+    pub title: String,
+}
+
+/// Read the chapters out of a file. lofty does not handle chapters, so this
+/// reads ID3v2 `CHAP` frames and Nero `chpl` MP4 atoms directly. QuickTime
+/// chapter tracks are not supported.
+///
+/// Any error while reading just results in no chapters.
+pub fn read_chapters(path: &Path, file_type: FileType) -> Vec<Chapter> {
+    let Ok(mut file) = File::open(path) else {
+        return vec![];
+    };
+    let mut ret = match file_type {
+        FileType::Mpeg | FileType::Aac | FileType::Aiff | FileType::Wav => {
+            read_id3v2_chapters(&mut file)
+        }
+        FileType::Mp4 => read_mp4_chapters(&mut file),
+        _ => None,
+    }
+    .unwrap_or_default();
+    ret.sort_by_key(|x| x.start);
+    ret
+}
+
+fn read_id3v2_chapters(file: &mut File) -> Option<Vec<Chapter>> {
+    // the tag is expected at the start of the file
+    let mut header = [0; 10];
+    file.read_exact(&mut header).ok()?;
+    if &header[0..3] != b"ID3" || !(3..=4).contains(&header[3]) {
+        return None;
+    }
+    let version = header[3];
+    let mut tag = vec![0; syncsafe(&header[6..10]) as usize];
+    file.read_exact(&mut tag).ok()?;
+
+    // skip the extended header
+    let mut frames = tag.as_slice();
+    if header[5] & 0x40 != 0 {
+        let size = frames.get(0..4)?;
+        let size = if version == 4 {
+            syncsafe(size) as usize
+        } else {
+            u32::from_be_bytes(size.try_into().unwrap()) as usize + 4
+        };
+        frames = frames.get(size..)?;
+    }
+
+    let mut ret = vec![];
+    for (id, frame) in id3v2_frames(frames, version) {
+        if &id != b"CHAP" {
+            continue;
+        }
+        // element id, then start/end times and offsets
+        let id_end = frame.iter().position(|x| *x == 0)?;
+        let times = frame.get(id_end + 1..id_end + 17)?;
+        let start = u32::from_be_bytes(times[0..4].try_into().unwrap());
+        let sub_frames = &frame[id_end + 17..];
+        let title = id3v2_frames(sub_frames, version)
+            .find(|(id, _)| id == b"TIT2")
+            .and_then(|(_, x)| decode_id3v2_text(x))
+            .unwrap_or_else(|| String::from_utf8_lossy(&frame[..id_end]).into_owned());
+        ret.push(Chapter {
+            start: Duration::from_millis(start.into()),
+            title,
+        });
+    }
+    Some(ret)
+}
+
+/// Iterate over the (id, body) of the frames in an ID3v2.3/ID3v2.4 tag body.
+fn id3v2_frames(mut inp: &[u8], version: u8) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        let header = inp.get(0..10)?;
+        if header[0] == 0 {
+            // padding
+            return None;
+        }
+        let size = if version == 4 {
+            syncsafe(&header[4..8])
+        } else {
+            u32::from_be_bytes(header[4..8].try_into().unwrap())
+        } as usize;
+        let body = inp.get(10..10 + size)?;
+        let id = header[0..4].try_into().unwrap();
+        inp = &inp[10 + size..];
+        Some((id, body))
+    })
+}
+
+fn syncsafe(inp: &[u8]) -> u32 {
+    inp.iter()
+        .fold(0, |acc, x| (acc << 7) | u32::from(x & 0x7f))
+}
+
+fn decode_id3v2_text(inp: &[u8]) -> Option<String> {
+    let (encoding, text) = inp.split_first()?;
+    let utf16 = |text: &[u8], be: bool| {
+        let text = text
+            .chunks_exact(2)
+            .map(|x| {
+                let x = [x[0], x[1]];
+                if be {
+                    u16::from_be_bytes(x)
+                } else {
+                    u16::from_le_bytes(x)
+                }
+            })
+            .collect::<Vec<_>>();
+        String::from_utf16_lossy(&text)
+    };
+    let ret = match encoding {
+        0 => text.iter().map(|x| char::from(*x)).collect(),
+        1 => match text {
+            [0xfe, 0xff, rest @ ..] => utf16(rest, true),
+            [0xff, 0xfe, rest @ ..] => utf16(rest, false),
+            _ => return None,
+        },
+        2 => utf16(text, true),
+        3 => String::from_utf8_lossy(text).into_owned(),
+        _ => return None,
+    };
+    Some(ret.trim_end_matches('\0').to_owned())
+}
+
+fn read_mp4_chapters(file: &mut File) -> Option<Vec<Chapter>> {
+    let len = file.seek(SeekFrom::End(0)).ok()?;
+    let moov = find_mp4_atom(file, 0, len, b"moov")?;
+    let udta = find_mp4_atom(file, moov.0, moov.1, b"udta")?;
+    let chpl = find_mp4_atom(file, udta.0, udta.1, b"chpl")?;
+
+    let mut body = vec![0; (chpl.1 - chpl.0) as usize];
+    file.seek(SeekFrom::Start(chpl.0)).ok()?;
+    file.read_exact(&mut body).ok()?;
+
+    // version, flags, (reserved in version 1), then the chapter count
+    let (&version, body) = body.split_first()?;
+    let body = body.get(if version == 1 { 7 } else { 3 }..)?;
+    let (&count, mut body) = body.split_first()?;
+    let mut ret = vec![];
+    for _ in 0..count {
+        // start in 100ns units, then a pascal string title
+        let start = u64::from_be_bytes(body.get(0..8)?.try_into().unwrap()).checked_mul(100)?;
+        let title_len = *body.get(8)? as usize;
+        let title = body.get(9..9 + title_len)?;
+        ret.push(Chapter {
+            start: Duration::from_nanos(start),
+            title: String::from_utf8_lossy(title).into_owned(),
+        });
+        body = &body[9 + title_len..];
+    }
+    Some(ret)
+}
+
+/// Find a child atom between `start` and `end`, returning the range of its body.
+fn find_mp4_atom(file: &mut File, start: u64, end: u64, name: &[u8; 4]) -> Option<(u64, u64)> {
+    let mut at = start;
+    while at.checked_add(8)? <= end {
+        let mut header = [0; 8];
+        file.seek(SeekFrom::Start(at)).ok()?;
+        file.read_exact(&mut header).ok()?;
+        let (mut size, mut header_len) = (
+            u64::from(u32::from_be_bytes(header[0..4].try_into().unwrap())),
+            8,
+        );
+        if size == 1 {
+            // 64 bit size follows
+            let mut large = [0; 8];
+            file.read_exact(&mut large).ok()?;
+            size = u64::from_be_bytes(large);
+            header_len = 16;
+        } else if size == 0 {
+            // extends to the end
+            size = end - at;
+        }
+        if size < header_len {
+            return None;
+        }
+        let next = at.checked_add(size)?;
+        if &header[4..8] == name {
+            return Some((at + header_len, next.min(end)));
+        }
+        at = next;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn read(name: &str, udta: &[u8]) -> Option<Vec<Chapter>> {
+        let path = std::env::temp_dir().join(format!("mioplays-{}-{name}", std::process::id()));
+        std::fs::write(&path, atom(b"moov", &atom(b"udta", udta))).unwrap();
+        let ret = read_mp4_chapters(&mut File::open(&path).unwrap());
+        let _ = std::fs::remove_file(&path);
+        ret
+    }
+
+    fn chpl(starts: &[u64]) -> Vec<u8> {
+        let mut body = vec![0, 0, 0, 0, starts.len() as u8];
+        for start in starts {
+            body.extend(start.to_be_bytes());
+            body.extend([1, b'x']);
+        }
+        atom(b"chpl", &body)
+    }
+
+    #[test]
+    fn mp4_chapters() {
+        assert_eq!(
+            read("chpl", &chpl(&[0, 15_000_000])),
+            Some(vec![
+                Chapter {
+                    start: Duration::ZERO,
+                    title: "x".to_owned()
+                },
+                Chapter {
+                    start: Duration::from_millis(1500),
+                    title: "x".to_owned()
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn overflow_is_malformed() {
+        assert_eq!(read("chpl-start", &chpl(&[0, u64::MAX])), None);
+
+        // a 64 bit size that runs past the end of any file
+        let mut huge = 1u32.to_be_bytes().to_vec();
+        huge.extend(b"free");
+        huge.extend((u64::MAX - 4).to_be_bytes());
+        assert_eq!(read("chpl-size", &[huge, chpl(&[0])].concat()), None);
+    }
+}
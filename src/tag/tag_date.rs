@@ -0,0 +1,291 @@
+use std::fmt;
+
+use crate::tag::tag_set::{self, TagSet};
+
+/// A date which may only be known down to the year or the month, as is
+/// common for release dates in tags.
+///
+/// Ordering is chronological, with a less precise date sorting before a more
+/// precise one in the same year/month ("1997" < "1997-05" < "1997-05-21").
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PartialDate {
+    year: i16,
+    month: Option<i8>,
+    day: Option<i8>,
+}
+
+impl PartialDate {
+    pub fn year(&self) -> i16 {
+        self.year
+    }
+
+    pub fn month(&self) -> Option<i8> {
+        self.month
+    }
+
+    pub fn day(&self) -> Option<i8> {
+        self.day
+    }
+
+    /// Leniently parse a date out of a tag value.
+    ///
+    /// Accepts "1997", "1997-05", "1997-05-21", "19970521", full RFC 3339
+    /// timestamps, '/' or '.' as separators, and anything else beginning with a
+    /// four digit year (eg. "1997 (Remaster)"). Parts which are out of range are
+    /// dropped, so "1997-13-40" becomes "1997".
+    pub fn parse(inp: &str) -> Option<Self> {
+        let inp = inp.trim();
+        // drop any time component
+        let date = inp
+            .split(['T', ' '])
+            .next()
+            .expect("split always has one item");
+
+        let mut parts = if date.len() == 8 && date.bytes().all(|x| x.is_ascii_digit()) {
+            // compact form: YYYYMMDD
+            vec![&date[0..4], &date[4..6], &date[6..8]]
+        } else {
+            date.split(['-', '/', '.']).collect()
+        };
+        if parts.len() > 3 || !parts.iter().all(|x| x.bytes().all(|x| x.is_ascii_digit())) {
+            // fallback on a leading year
+            let year = inp.get(0..4)?;
+            let after = inp[4..].chars().next();
+            if after.is_some_and(|x| x.is_ascii_digit()) {
+                return None;
+            }
+            parts = vec![year];
+        }
+
+        let mut parts = parts.into_iter();
+        let year = parts.next()?;
+        if year.len() != 4 {
+            return None;
+        }
+        let year = year.parse().ok()?;
+        let month = parts
+            .next()
+            .and_then(|x| x.parse::<i8>().ok())
+            .filter(|x| (1..=12).contains(x));
+        let day = parts
+            .next()
+            .and_then(|x| x.parse::<i8>().ok())
+            .filter(|day| {
+                month.is_some_and(|month| jiff::civil::Date::new(year, month, *day).is_ok())
+            });
+
+        Some(Self { year, month, day })
+    }
+
+    /// Fill in the month and day from an ID3v2.3 `TDAT` frame ("DDMM"), if this
+    /// date is only a year.
+    pub fn with_id3_tdat(self, tdat: &str) -> Self {
+        let tdat = tdat.trim();
+        if self.month.is_some() || tdat.len() != 4 || !tdat.bytes().all(|x| x.is_ascii_digit()) {
+            return self;
+        }
+        Self::parse(&format!("{:04}-{}-{}", self.year, &tdat[2..4], &tdat[0..2])).unwrap_or(self)
+    }
+
+    /// Use a more precise date from another source, if it agrees with this one.
+    fn refine(self, other: Self) -> Self {
+        let agrees = self.year == other.year && (self.month.is_none() || self.month == other.month);
+        if agrees && other.precision() > self.precision() {
+            other
+        } else {
+            self
+        }
+    }
+
+    fn precision(&self) -> u8 {
+        1 + self.month.is_some() as u8 + self.day.is_some() as u8
+    }
+}
+
+impl fmt::Display for PartialDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}", self.year)?;
+        if let Some(month) = self.month {
+            write!(f, "-{month:02}")?;
+            if let Some(day) = self.day {
+                write!(f, "-{day:02}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// All of the date-ish items found while reading a file, before they are
+/// collapsed into `ReleaseDate`/`OriginalReleaseDate`.
+#[derive(Debug, Default)]
+pub(crate) struct DateCandidates {
+    pub release: Option<PartialDate>,
+    pub recording: Option<PartialDate>,
+    pub year: Option<PartialDate>,
+    pub original: Option<PartialDate>,
+    pub id3_tdat: Option<String>,
+}
+
+impl DateCandidates {
+    /// Resolve the candidates into the release date and original release date.
+    ///
+    /// The release date is taken from the first available of `ReleaseDate`,
+    /// `RecordingDate` and `Year`; lower precedence sources may still add a
+    /// month or day if they agree on the year. The original release date is
+    /// only ever taken from `OriginalReleaseDate`, and is dropped if it is not
+    /// actually before the release date.
+    pub fn resolve(self) -> (Option<PartialDate>, Option<PartialDate>) {
+        let year = match (self.year, &self.id3_tdat) {
+            (Some(year), Some(tdat)) => Some(year.with_id3_tdat(tdat)),
+            (year, _) => year,
+        };
+        let release = [self.release, self.recording, year]
+            .into_iter()
+            .flatten()
+            .reduce(PartialDate::refine);
+        let original = match (self.original, release) {
+            (Some(original), Some(release)) if original.year >= release.year => None,
+            (original, _) => original,
+        };
+        (release, original)
+    }
+}
+
+// Date helpers
+impl TagSet {
+    /// The date to sort albums by: the original release if it is known,
+    /// otherwise this release.
+    pub fn sort_date(&self) -> Option<PartialDate> {
+        self.get_typed_tag::<tag_set::OriginalReleaseDate>()
+            .map(|x| x.0)
+            .or_else(|| self.get_typed_tag::<tag_set::ReleaseDate>().map(|x| x.0))
+    }
+
+    /// The release year for display. Reissues show the original year
+    /// alongside, eg. "2009 (1969)".
+    pub fn display_year(&self) -> Option<String> {
+        let release = self.get_typed_tag::<tag_set::ReleaseDate>().map(|x| x.0);
+        let original = self
+            .get_typed_tag::<tag_set::OriginalReleaseDate>()
+            .map(|x| x.0);
+        match (release, original) {
+            (Some(release), Some(original)) => {
+                Some(format!("{} ({})", release.year, original.year))
+            }
+            (Some(date), None) | (None, Some(date)) => Some(date.year.to_string()),
+            (None, None) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i16, month: Option<i8>, day: Option<i8>) -> PartialDate {
+        PartialDate { year, month, day }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(PartialDate::parse("1997"), Some(date(1997, None, None)));
+        assert_eq!(
+            PartialDate::parse("1997-05"),
+            Some(date(1997, Some(5), None))
+        );
+        let day = Some(date(1997, Some(5), Some(21)));
+        assert_eq!(PartialDate::parse("1997-05-21"), day);
+        assert_eq!(PartialDate::parse("1997-05-21T00:00:00Z"), day);
+        assert_eq!(PartialDate::parse("19970521"), day);
+        assert_eq!(PartialDate::parse("1997/05/21"), day);
+        assert_eq!(
+            PartialDate::parse("1997 (Remaster)"),
+            Some(date(1997, None, None))
+        );
+        assert_eq!(
+            PartialDate::parse("1997-13-40"),
+            Some(date(1997, None, None))
+        );
+        assert_eq!(
+            PartialDate::parse("1997-02-30"),
+            Some(date(1997, Some(2), None))
+        );
+    }
+
+    #[test]
+    fn parse_garbage() {
+        assert_eq!(PartialDate::parse(""), None);
+        assert_eq!(PartialDate::parse("unknown"), None);
+        assert_eq!(PartialDate::parse("97"), None);
+        assert_eq!(PartialDate::parse("199705"), None);
+    }
+
+    #[test]
+    fn id3_tdat() {
+        let candidates = DateCandidates {
+            year: PartialDate::parse("1997"),
+            id3_tdat: Some("2105".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            candidates.resolve(),
+            (Some(date(1997, Some(5), Some(21))), None)
+        );
+        // only fills in a bare year, dropping what is out of range
+        let year = date(1997, None, None);
+        assert_eq!(year.with_id3_tdat("3102"), date(1997, Some(2), None));
+        assert_eq!(year.with_id3_tdat("garbage"), year);
+        let month = date(1997, Some(6), None);
+        assert_eq!(month.with_id3_tdat("2105"), month);
+    }
+
+    #[test]
+    fn precedence() {
+        let candidates = DateCandidates {
+            release: PartialDate::parse("2001"),
+            recording: PartialDate::parse("1999-03-04"),
+            year: PartialDate::parse("1998"),
+            ..Default::default()
+        };
+        assert_eq!(candidates.resolve().0, PartialDate::parse("2001"));
+        let candidates = DateCandidates {
+            recording: PartialDate::parse("1999-03-04"),
+            year: PartialDate::parse("1998"),
+            ..Default::default()
+        };
+        assert_eq!(candidates.resolve().0, PartialDate::parse("1999-03-04"));
+        let candidates = DateCandidates {
+            year: PartialDate::parse("1998"),
+            ..Default::default()
+        };
+        assert_eq!(candidates.resolve().0, PartialDate::parse("1998"));
+
+        // a lower precedence source adds precision when it agrees
+        let candidates = DateCandidates {
+            release: PartialDate::parse("1997"),
+            recording: PartialDate::parse("1997-05-21"),
+            ..Default::default()
+        };
+        assert_eq!(candidates.resolve().0, PartialDate::parse("1997-05-21"));
+    }
+
+    #[test]
+    fn original_release() {
+        let candidates = DateCandidates {
+            release: PartialDate::parse("2009"),
+            original: PartialDate::parse("1969"),
+            ..Default::default()
+        };
+        assert_eq!(
+            candidates.resolve(),
+            (PartialDate::parse("2009"), PartialDate::parse("1969"))
+        );
+        // not a reissue
+        let candidates = DateCandidates {
+            release: PartialDate::parse("2009"),
+            original: PartialDate::parse("2009"),
+            ..Default::default()
+        };
+        assert_eq!(candidates.resolve(), (PartialDate::parse("2009"), None));
+    }
+}
@@ -0,0 +1,176 @@
+use std::sync::LazyLock;
+
+use crate::tag::tag_set::{self, TagSet};
+
+/// A text value tagged with a language and description, as in ID3v2 `USLT`
+/// and `COMM` frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalizedText {
+    /// ISO 639-2 code, lowercased. `None` if the tag did not specify one.
+    pub lang: Option<String>,
+    pub description: String,
+    pub text: String,
+}
+
+impl LocalizedText {
+    pub fn new(lang: [u8; 3], description: &str, text: String) -> Self {
+        let lang = std::str::from_utf8(&lang)
+            .ok()
+            .map(|x| x.trim_matches(char::from(0)).to_ascii_lowercase())
+            // "XXX" is the ID3 unknown language, "und" is ISO 639-2's
+            .filter(|x| x.len() == 3 && x != "xxx" && x != "und");
+        Self {
+            lang,
+            description: description.to_owned(),
+            text,
+        }
+    }
+}
+
+/// Pick the entry best matching the user's locale.
+///
+/// The fallback order is: the user's language, entries without a language,
+/// English, then whatever came first. Within a language, entries without a
+/// description are preferred, as those tend to be the "main" value rather
+/// than something like iTunes' normalisation comments.
+pub fn pick_localized(inp: &[LocalizedText]) -> Option<&LocalizedText> {
+    pick_localized_for(inp, USER_LANGUAGES.as_slice())
+}
+
+/// Same as `pick_localized`, but for a specific set of ISO 639-2 codes.
+pub fn pick_localized_for<'a>(
+    inp: &'a [LocalizedText],
+    langs: &[&str],
+) -> Option<&'a LocalizedText> {
+    let with_lang = |lang: Option<&str>| {
+        let mut matching = inp.iter().filter(move |x| x.lang.as_deref() == lang);
+        let first = matching.clone().next();
+        matching.find(|x| x.description.is_empty()).or(first)
+    };
+
+    langs
+        .iter()
+        .find_map(|x| with_lang(Some(x)))
+        .or_else(|| with_lang(None))
+        .or_else(|| with_lang(Some("eng")))
+        .or_else(|| inp.first())
+}
+
+/// The ISO 639-2 codes (both bibliographic and terminological, where they
+/// differ) of the user's language, from the usual POSIX locale variables.
+static USER_LANGUAGES: LazyLock<Vec<&'static str>> = LazyLock::new(|| {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|x| std::env::var(x).ok())
+        .find(|x| !x.is_empty())
+        .unwrap_or_default();
+    // "de_DE.UTF-8" -> "de"
+    let lang = locale
+        .split(['_', '.', '@'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    iso_639_1_to_2(&lang).to_vec()
+});
+
+/// Convert a two letter ISO 639-1 code into its three letter ISO 639-2 codes.
+/// Only covers common languages; anything else has no codes.
+fn iso_639_1_to_2(inp: &str) -> &'static [&'static str] {
+    match inp {
+        "ar" => &["ara"],
+        "cs" => &["cze", "ces"],
+        "da" => &["dan"],
+        "de" => &["ger", "deu"],
+        "el" => &["gre", "ell"],
+        "en" => &["eng"],
+        "es" => &["spa"],
+        "fi" => &["fin"],
+        "fr" => &["fre", "fra"],
+        "he" => &["heb"],
+        "hi" => &["hin"],
+        "hu" => &["hun"],
+        "it" => &["ita"],
+        "ja" => &["jpn"],
+        "ko" => &["kor"],
+        "nl" => &["dut", "nld"],
+        "no" | "nb" | "nn" => &["nor", "nob", "nno"],
+        "pl" => &["pol"],
+        "pt" => &["por"],
+        "ro" => &["rum", "ron"],
+        "ru" => &["rus"],
+        "sv" => &["swe"],
+        "tr" => &["tur"],
+        "uk" => &["ukr"],
+        "zh" => &["chi", "zho"],
+        _ => &[],
+    }
+}
+
+// Localized helpers
+impl TagSet {
+    /// The comment best matching the user's locale.
+    pub fn localized_comment(&self) -> Option<&str> {
+        let comments = &self.get_typed_tag::<tag_set::Comment>()?.0;
+        pick_localized(comments).map(|x| x.text.as_str())
+    }
+
+    /// The lyrics best matching the user's locale.
+    pub fn localized_lyrics(&self) -> Option<&str> {
+        let lyrics = &self.get_typed_tag::<tag_set::Lyrics>()?.0;
+        pick_localized(lyrics).map(|x| x.text.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(lang: &[u8; 3], description: &str, text: &str) -> LocalizedText {
+        LocalizedText::new(*lang, description, text.to_owned())
+    }
+
+    #[test]
+    fn languages() {
+        assert_eq!(text(b"DEU", "", "").lang.as_deref(), Some("deu"));
+        assert_eq!(text(b"XXX", "", "").lang, None);
+        assert_eq!(text(b"und", "", "").lang, None);
+        assert_eq!(text(b"\0\0\0", "", "").lang, None);
+        assert_eq!(iso_639_1_to_2("de"), &["ger", "deu"]);
+        assert!(iso_639_1_to_2("xx").is_empty());
+    }
+
+    #[test]
+    fn fallback_order() {
+        let german = text(b"deu", "", "german");
+        let none = text(b"XXX", "", "none");
+        let english = text(b"eng", "", "english");
+        let french = text(b"fra", "", "french");
+        let langs = ["ger", "deu"];
+        let pick = |inp: &[LocalizedText]| pick_localized_for(inp, &langs).map(|x| x.text.clone());
+
+        let all = [french.clone(), english.clone(), none.clone(), german];
+        assert_eq!(pick(&all).as_deref(), Some("german"));
+        assert_eq!(pick(&all[..3]).as_deref(), Some("none"));
+        assert_eq!(pick(&all[..2]).as_deref(), Some("english"));
+        assert_eq!(pick(&all[..1]).as_deref(), Some("french"));
+        assert_eq!(pick(&[]), None);
+        // no locale skips straight to entries without a language
+        assert_eq!(
+            pick_localized_for(&all, &[]).map(|x| x.text.as_str()),
+            Some("none")
+        );
+    }
+
+    #[test]
+    fn prefers_no_description() {
+        let inp = [
+            text(b"eng", "iTunNORM", "normalisation"),
+            text(b"eng", "", "main"),
+            text(b"eng", "other", "other"),
+        ];
+        let pick = pick_localized_for(&inp, &["eng"]);
+        assert_eq!(pick.map(|x| x.text.as_str()), Some("main"));
+        let pick = pick_localized_for(&inp[..1], &["eng"]);
+        assert_eq!(pick.map(|x| x.text.as_str()), Some("normalisation"));
+    }
+}
@@ -0,0 +1,185 @@
+use crate::tag::tag_set::{self, TagSet};
+
+/// All of the title and artist items found while reading a file, before they
+/// are paired up with their sort orders.
+#[derive(Debug, Default)]
+pub(crate) struct NameCandidates {
+    pub album: Option<String>,
+    pub album_sort: Option<String>,
+    pub track: Option<String>,
+    pub track_sort: Option<String>,
+    pub disc: Option<String>,
+    pub album_artist: Vec<String>,
+    pub album_artist_sort: Vec<String>,
+    pub track_artist: Vec<String>,
+    pub track_artist_sort: Vec<String>,
+    pub composer: Option<String>,
+    pub composer_sort: Option<String>,
+    pub conductor: Vec<String>,
+    pub performer: Vec<String>,
+    pub remixer: Vec<String>,
+    pub work: Option<String>,
+    pub movement: Option<String>,
+    pub show: Option<String>,
+    pub show_sort: Option<String>,
+}
+
+impl NameCandidates {
+    /// Add a multi-valued name, skipping blanks and duplicates.
+    pub fn push_name(list: &mut Vec<String>, value: &str) {
+        let value = value.trim();
+        if !value.is_empty() && !list.iter().any(|x| x == value) {
+            list.push(value.to_owned());
+        }
+    }
+
+    /// Push the names into the set. Sort orders without a name to go with are
+    /// dropped.
+    pub fn resolve_into(self, set: &mut TagSet) {
+        if let Some(inner) = self.album {
+            let _ = set.push_typed_tag(tag_set::AlbumTitle {
+                inner,
+                sort_order: self.album_sort,
+            });
+        }
+        if let Some(inner) = self.track {
+            let _ = set.push_typed_tag(tag_set::TrackTitle {
+                inner,
+                sort_order: self.track_sort,
+            });
+        }
+        if let Some(disc) = self.disc {
+            let _ = set.push_typed_tag(tag_set::DiscTitle(disc));
+        }
+        if !self.album_artist.is_empty() {
+            let _ = set.push_typed_tag(tag_set::AlbumArtist {
+                inner: self.album_artist,
+                sort_order: self.album_artist_sort,
+            });
+        }
+        if !self.track_artist.is_empty() {
+            let _ = set.push_typed_tag(tag_set::TrackArtist {
+                inner: self.track_artist,
+                sort_order: self.track_artist_sort,
+            });
+        }
+        if let Some(inner) = self.composer {
+            let _ = set.push_typed_tag(tag_set::Composer {
+                inner,
+                sort_order: self.composer_sort,
+            });
+        }
+        if !self.conductor.is_empty() {
+            let _ = set.push_typed_tag(tag_set::Conductor(self.conductor));
+        }
+        if !self.performer.is_empty() {
+            let _ = set.push_typed_tag(tag_set::Performer(self.performer));
+        }
+        if !self.remixer.is_empty() {
+            let _ = set.push_typed_tag(tag_set::Remixer(self.remixer));
+        }
+        if let Some(work) = self.work {
+            let _ = set.push_typed_tag(tag_set::Work(work));
+        }
+        if let Some(movement) = self.movement {
+            let _ = set.push_typed_tag(tag_set::Movement(movement));
+        }
+        if let Some(inner) = self.show {
+            let _ = set.push_typed_tag(tag_set::ShowName {
+                inner,
+                sort_order: self.show_sort,
+            });
+        }
+    }
+}
+
+// Name helpers
+impl TagSet {
+    /// The artists credited for the whole album, falling back on the track
+    /// artists.
+    pub fn album_artists(&self) -> &[String] {
+        if let Some(x) = self.get_typed_tag::<tag_set::AlbumArtist>() {
+            &x.inner
+        } else if let Some(x) = self.get_typed_tag::<tag_set::TrackArtist>() {
+            &x.inner
+        } else {
+            &[]
+        }
+    }
+
+    pub fn album_title(&self) -> Option<&str> {
+        Some(&self.get_typed_tag::<tag_set::AlbumTitle>()?.inner)
+    }
+
+    /// The album title to order by, which is its sort order if it has one.
+    pub fn album_sort_title(&self) -> Option<&str> {
+        let x = self.get_typed_tag::<tag_set::AlbumTitle>()?;
+        Some(x.sort_order.as_deref().unwrap_or(&x.inner))
+    }
+
+    pub fn track_title(&self) -> Option<&str> {
+        Some(&self.get_typed_tag::<tag_set::TrackTitle>()?.inner)
+    }
+
+    /// The tagged sort order of the track title, if there is one.
+    pub fn track_title_sort_order(&self) -> Option<&str> {
+        self.get_typed_tag::<tag_set::TrackTitle>()?
+            .sort_order
+            .as_deref()
+    }
+
+    pub fn genres(&self) -> &[String] {
+        self.get_typed_tag::<tag_set::Genre>()
+            .map(|x| x.0.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn track_artists(&self) -> &[String] {
+        self.get_typed_tag::<tag_set::TrackArtist>()
+            .map(|x| x.inner.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// The tagged sort orders of the track artists, which only pair up with
+    /// them if there are as many.
+    pub fn track_artist_sort_orders(&self) -> &[String] {
+        self.get_typed_tag::<tag_set::TrackArtist>()
+            .map(|x| x.sort_order.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+// Classical helpers
+impl TagSet {
+    pub fn composer(&self) -> Option<&str> {
+        Some(&self.get_typed_tag::<tag_set::Composer>()?.inner)
+    }
+
+    pub fn work(&self) -> Option<&str> {
+        Some(&self.get_typed_tag::<tag_set::Work>()?.0)
+    }
+
+    /// The movement name, falling back on the track title.
+    pub fn movement(&self) -> Option<&str> {
+        self.get_typed_tag::<tag_set::Movement>()
+            .map(|x| x.0.as_str())
+            .or_else(|| self.track_title())
+    }
+
+    /// The title to show for a track. When the work tags are present this is
+    /// "Composer: Work — Movement", otherwise it is just the track title.
+    pub fn display_title(&self) -> Option<String> {
+        let Some(work) = self.work() else {
+            return self.track_title().map(ToOwned::to_owned);
+        };
+        let mut ret = match self.composer() {
+            Some(composer) => format!("{composer}: {work}"),
+            None => work.to_owned(),
+        };
+        if let Some(movement) = self.movement().filter(|x| *x != work) {
+            ret.push_str(" — ");
+            ret.push_str(movement);
+        }
+        Some(ret)
+    }
+}
@@ -0,0 +1,180 @@
+use crate::tag::tag_set::{self, TagSet};
+
+/// A parsed track/disc position, eg. "3/12", "03", or "B2" for vinyl.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Position {
+    pub pos: Option<u32>,
+    pub total: Option<u32>,
+    /// The side letter(s) of a vinyl position, eg. "B" in "B2"
+    pub side: Option<String>,
+    /// The position as written in the tag, for vinyl positions
+    pub label: Option<String>,
+}
+
+impl Position {
+    /// Leniently parse a position out of a tag value.
+    ///
+    /// Accepts a plain number ("3", " 03 "), a number with a total ("3/12"),
+    /// a vinyl position ("A1", "b2", "AA1"), and an empty position with a total
+    /// ("/12"). Returns `None` if nothing in the value makes sense.
+    pub fn parse(inp: &str) -> Option<Self> {
+        let (pos, total) = match inp.split_once('/') {
+            Some((pos, total)) => (pos.trim(), Some(total.trim())),
+            None => (inp.trim(), None),
+        };
+        let pos_label = pos;
+
+        let total = match total {
+            Some(total) => Some(total.parse().ok()?),
+            None => None,
+        };
+        let digits = pos.trim_start_matches(|x: char| x.is_ascii_alphabetic());
+        let side = &pos[..pos.len() - digits.len()];
+        if side.len() > 2 {
+            return None;
+        }
+        let pos = match digits {
+            "" => None,
+            digits => Some(digits.parse().ok()?),
+        };
+        if pos.is_none() && total.is_none() && side.is_empty() {
+            return None;
+        }
+
+        let vinyl = !side.is_empty();
+        Some(Self {
+            pos,
+            total,
+            side: vinyl.then(|| side.to_ascii_uppercase()),
+            label: vinyl.then(|| pos_label.to_owned()),
+        })
+    }
+}
+
+/// All of the track/disc/movement number items found while reading a file, before they
+/// are collapsed into their typed tags.
+#[derive(Debug, Default)]
+pub(crate) struct PositionCandidates {
+    pub track: Option<Position>,
+    pub track_total: Option<u32>,
+    pub disc: Option<Position>,
+    pub disc_total: Option<u32>,
+    pub movement: Option<Position>,
+    pub movement_total: Option<u32>,
+}
+
+impl PositionCandidates {
+    /// Push the resolved positions into the set. A dedicated total item takes
+    /// precedence over a total embedded in the position ("3/12").
+    pub fn resolve_into(self, set: &mut TagSet) {
+        let track = self.track.unwrap_or_default();
+        let disc = self.disc.unwrap_or_default();
+        if let Some(pos) = track.pos {
+            let _ = set.push_typed_tag(tag_set::TrackPos(pos));
+        }
+        if let Some(total) = self.track_total.or(track.total) {
+            let _ = set.push_typed_tag(tag_set::TrackTotal(total));
+        }
+        if let (Some(side), Some(label)) = (track.side, track.label) {
+            let _ = set.push_typed_tag(tag_set::TrackSide { side, label });
+        }
+        if let Some(pos) = disc.pos {
+            let _ = set.push_typed_tag(tag_set::DiscPos(pos));
+        }
+        if let Some(total) = self.disc_total.or(disc.total) {
+            let _ = set.push_typed_tag(tag_set::DiscTotal(total));
+        }
+        let movement = self.movement.unwrap_or_default();
+        if let Some(pos) = movement.pos {
+            let _ = set.push_typed_tag(tag_set::MovementPos(pos));
+        }
+        if let Some(total) = self.movement_total.or(movement.total) {
+            let _ = set.push_typed_tag(tag_set::MovementTotal(total));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(pos: Option<u32>, total: Option<u32>) -> Position {
+        Position {
+            pos,
+            total,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Position::parse("3/12"), Some(pos(Some(3), Some(12))));
+        assert_eq!(Position::parse(" 03 "), Some(pos(Some(3), None)));
+        assert_eq!(Position::parse(" 3 / 12 "), Some(pos(Some(3), Some(12))));
+        assert_eq!(Position::parse("/12"), Some(pos(None, Some(12))));
+    }
+
+    #[test]
+    fn parse_vinyl() {
+        let vinyl = |pos, side: &str, label: &str| Position {
+            pos,
+            total: None,
+            side: Some(side.to_owned()),
+            label: Some(label.to_owned()),
+        };
+        assert_eq!(Position::parse("A1"), Some(vinyl(Some(1), "A", "A1")));
+        assert_eq!(Position::parse("b2"), Some(vinyl(Some(2), "B", "b2")));
+        assert_eq!(Position::parse("AA1"), Some(vinyl(Some(1), "AA", "AA1")));
+        assert_eq!(Position::parse("C"), Some(vinyl(None, "C", "C")));
+    }
+
+    #[test]
+    fn parse_garbage() {
+        assert_eq!(Position::parse(""), None);
+        assert_eq!(Position::parse("/"), None);
+        assert_eq!(Position::parse("ABC1"), None);
+        assert_eq!(Position::parse("3/twelve"), None);
+        assert_eq!(Position::parse("3a"), None);
+        assert_eq!(Position::parse("-1"), None);
+    }
+
+    #[test]
+    fn resolve() {
+        let mut set = TagSet::new();
+        PositionCandidates {
+            track: Position::parse("3/12"),
+            track_total: Some(14),
+            disc: Position::parse("/2"),
+            ..Default::default()
+        }
+        .resolve_into(&mut set);
+        assert_eq!(
+            set.get_typed_tag::<tag_set::TrackPos>().map(|x| x.0),
+            Some(3)
+        );
+        // the dedicated total wins over the embedded one
+        assert_eq!(
+            set.get_typed_tag::<tag_set::TrackTotal>().map(|x| x.0),
+            Some(14)
+        );
+        assert!(set.get_typed_tag::<tag_set::TrackSide>().is_none());
+        assert!(set.get_typed_tag::<tag_set::DiscPos>().is_none());
+        assert_eq!(
+            set.get_typed_tag::<tag_set::DiscTotal>().map(|x| x.0),
+            Some(2)
+        );
+
+        let mut set = TagSet::new();
+        PositionCandidates {
+            track: Position::parse("B2"),
+            ..Default::default()
+        }
+        .resolve_into(&mut set);
+        let side = set.get_typed_tag::<tag_set::TrackSide>().unwrap();
+        assert_eq!((side.side.as_str(), side.label.as_str()), ("B", "B2"));
+        assert_eq!(
+            set.get_typed_tag::<tag_set::TrackPos>().map(|x| x.0),
+            Some(2)
+        );
+    }
+}
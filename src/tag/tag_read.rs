@@ -1,11 +1,28 @@
-use std::{num::NonZeroUsize, ops::Deref, path::PathBuf, sync::LazyLock};
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
 
-use lofty::{file::TaggedFileExt, tag::ItemKey};
+use lofty::{
+    file::{AudioFile, TaggedFileExt},
+    tag::{ItemKey, ItemValue, TagType},
+};
 use smol::lock::Semaphore;
 
-use crate::tag::tag_set;
+use crate::audio::AudioField;
+use crate::tag::{
+    DateCandidates, LocalizedText, NameCandidates, PartialDate, Position, PositionCandidates,
+    read_chapters, tag_set,
+};
 
-pub async fn decode_tags(inp: PathBuf) -> tag_set::TagSet {
+/// Read the tags of a file, along with its audio properties if
+/// `read_properties` is set. Returns `None` if the file cannot be opened, or
+/// is not in a format lofty can read.
+pub async fn decode_tags(
+    inp: PathBuf,
+    read_properties: bool,
+) -> Option<(tag_set::TagSet, Option<AudioField>)> {
     // shared state to prevent multiple decodes at once
     //
     // POSS TODO: make this configurable by the user?
@@ -21,147 +38,626 @@ pub async fn decode_tags(inp: PathBuf) -> tag_set::TagSet {
     let _lock = READING_THREADS.acquire().await;
     let (tx, rx) = oneshot::channel();
     std::thread::spawn(move || {
-        let mut ret = tag_set::TagSet::new();
-        // probe the item
-        let probe = lofty::probe::Probe::open(inp)
-            .expect("file cannot be opened")
-            .options(
-                lofty::config::ParseOptions::new()
-                    .parsing_mode(lofty::config::ParsingMode::Relaxed)
-                    .max_junk_bytes(4096)
-                    .read_cover_art(true)
-                    .read_tags(true)
-                    .read_properties(false),
-            )
-            .guess_file_type()
-            .expect("unable to guess file type")
-            .read()
-            .expect("unable to read file tags");
-
-        for tag in probe.tags() {
-            for item in tag.items() {
-                // TODO: possibly use item.lang()
-                let (k, v) = (item.key(), item.value());
-                let tag = match k {
-                    ItemKey::AlbumTitle => todo!(),
-                    ItemKey::SetSubtitle => todo!(),
-                    ItemKey::ShowName => todo!(),
-                    ItemKey::ContentGroup => todo!(),
-                    ItemKey::TrackTitle => todo!(),
-                    ItemKey::TrackSubtitle => todo!(),
-                    ItemKey::OriginalAlbumTitle => todo!(),
-                    ItemKey::OriginalArtist => todo!(),
-                    ItemKey::OriginalLyricist => todo!(),
-                    ItemKey::AlbumTitleSortOrder => todo!(),
-                    ItemKey::AlbumArtistSortOrder => todo!(),
-                    ItemKey::TrackTitleSortOrder => todo!(),
-                    ItemKey::TrackArtistSortOrder => todo!(),
-                    ItemKey::ShowNameSortOrder => todo!(),
-                    ItemKey::ComposerSortOrder => todo!(),
-                    ItemKey::AlbumArtist => todo!(),
-                    ItemKey::TrackArtist | ItemKey::TrackArtists => todo!(),
-                    ItemKey::Arranger => todo!(),
-                    ItemKey::Writer => todo!(),
-                    ItemKey::Composer => todo!(),
-                    ItemKey::Conductor => todo!(),
-                    ItemKey::Director => todo!(),
-                    ItemKey::Engineer => todo!(),
-                    ItemKey::Lyricist => todo!(),
-                    ItemKey::MixDj => todo!(),
-                    ItemKey::MixEngineer => todo!(),
-                    ItemKey::MusicianCredits => todo!(),
-                    ItemKey::Performer => todo!(),
-                    ItemKey::Producer => todo!(),
-                    ItemKey::Publisher => todo!(),
-                    ItemKey::Label => todo!(),
-                    ItemKey::InternetRadioStationName => todo!(),
-                    ItemKey::InternetRadioStationOwner => todo!(),
-                    ItemKey::Remixer => todo!(),
-                    ItemKey::DiscNumber => todo!(),
-                    ItemKey::DiscTotal => todo!(),
-                    ItemKey::TrackNumber => todo!(),
-                    ItemKey::TrackTotal => todo!(),
-                    ItemKey::Popularimeter => todo!(),
-                    ItemKey::ParentalAdvisory => todo!(),
-                    ItemKey::RecordingDate => todo!(),
-                    ItemKey::Year => todo!(),
-                    ItemKey::ReleaseDate => todo!(),
-                    ItemKey::OriginalReleaseDate => todo!(),
-                    ItemKey::Isrc => todo!(),
-                    ItemKey::Barcode => todo!(),
-                    ItemKey::CatalogNumber => todo!(),
-                    ItemKey::Work => todo!(),
-                    ItemKey::Movement => todo!(),
-                    ItemKey::MovementNumber => todo!(),
-                    ItemKey::MovementTotal => todo!(),
-                    ItemKey::MusicBrainzRecordingId => todo!(),
-                    ItemKey::MusicBrainzTrackId => todo!(),
-                    ItemKey::MusicBrainzReleaseId => todo!(),
-                    ItemKey::MusicBrainzReleaseGroupId => todo!(),
-                    ItemKey::MusicBrainzArtistId => todo!(),
-                    ItemKey::MusicBrainzReleaseArtistId => todo!(),
-                    ItemKey::MusicBrainzWorkId => todo!(),
-                    ItemKey::FlagCompilation => todo!(),
-                    ItemKey::FlagPodcast => todo!(),
-                    ItemKey::FileType => todo!(),
-                    ItemKey::FileOwner => todo!(),
-                    ItemKey::TaggingTime => todo!(),
-                    ItemKey::Length => todo!(),
-                    ItemKey::OriginalFileName => todo!(),
-                    ItemKey::OriginalMediaType => todo!(),
-                    ItemKey::EncodedBy => todo!(),
-                    ItemKey::EncoderSoftware => todo!(),
-                    ItemKey::EncoderSettings => todo!(),
-                    ItemKey::EncodingTime => todo!(),
-                    ItemKey::ReplayGainAlbumGain => todo!(),
-                    ItemKey::ReplayGainAlbumPeak => todo!(),
-                    ItemKey::ReplayGainTrackGain => todo!(),
-                    ItemKey::ReplayGainTrackPeak => todo!(),
-                    ItemKey::AudioFileUrl => todo!(),
-                    ItemKey::AudioSourceUrl => todo!(),
-                    ItemKey::CommercialInformationUrl => todo!(),
-                    ItemKey::CopyrightUrl => todo!(),
-                    ItemKey::TrackArtistUrl => todo!(),
-                    ItemKey::RadioStationUrl => todo!(),
-                    ItemKey::PaymentUrl => todo!(),
-                    ItemKey::PublisherUrl => todo!(),
-                    ItemKey::Genre => todo!(),
-                    ItemKey::InitialKey => todo!(),
-                    ItemKey::Color => todo!(),
-                    ItemKey::Mood => todo!(),
-                    ItemKey::Bpm => todo!(),
-                    ItemKey::IntegerBpm => todo!(),
-                    ItemKey::CopyrightMessage => todo!(),
-                    ItemKey::License => todo!(),
-                    ItemKey::PodcastDescription => todo!(),
-                    ItemKey::PodcastSeriesCategory => todo!(),
-                    ItemKey::PodcastUrl => todo!(),
-                    ItemKey::PodcastGlobalUniqueId => todo!(),
-                    ItemKey::PodcastKeywords => todo!(),
-                    ItemKey::Comment => todo!(),
-                    ItemKey::Description => todo!(),
-                    ItemKey::Language => todo!(),
-                    ItemKey::Script => todo!(),
-                    ItemKey::Lyrics => todo!(),
-                    ItemKey::AppleXid => todo!(),
-                    ItemKey::AppleId3v2ContentGroup => todo!(),
-
-                    ItemKey::Unknown(item) => {
-                        ret.push_custom_tag(item, Box::new(v.into_binary().unwrap()));
+        // the receiver is only gone if the scan was, so there is nobody to tell
+        let _ = tx.send(read_tags(&inp, read_properties));
+    });
+
+    // a panic while reading drops the sender, which counts as unreadable
+    let ret = rx.recv().ok().flatten();
+    drop(_lock);
+    ret
+}
+
+fn read_tags(inp: &Path, read_properties: bool) -> Option<(tag_set::TagSet, Option<AudioField>)> {
+    let mut ret = tag_set::TagSet::new();
+    let mut dates = DateCandidates::default();
+    let mut positions = PositionCandidates::default();
+    let mut names = NameCandidates::default();
+    let mut artist_ids = vec![];
+    let mut release_artist_ids = vec![];
+    let mut podcast_keywords = vec![];
+    let mut genres = vec![];
+    let mut comments = vec![];
+    let mut lyrics = vec![];
+    let mut r128_track_gain = None;
+    let mut r128_album_gain = None;
+    // probe the item
+    let probe = lofty::probe::Probe::open(inp)
+        .ok()?
+        .options(
+            lofty::config::ParseOptions::new()
+                .parsing_mode(lofty::config::ParsingMode::Relaxed)
+                .max_junk_bytes(4096)
+                .read_cover_art(true)
+                .read_tags(true)
+                .read_properties(read_properties),
+        )
+        .guess_file_type()
+        .ok()?
+        .read()
+        .ok()?;
+
+    for tag in probe.tags() {
+        for item in tag.items() {
+            let (k, v) = (item.key(), item.value());
+            match k {
+                ItemKey::AlbumTitle
+                | ItemKey::AlbumTitleSortOrder
+                | ItemKey::TrackTitle
+                | ItemKey::TrackTitleSortOrder => {
+                    let Some(text) = v.text().map(str::trim).filter(|x| !x.is_empty()) else {
+                        continue;
+                    };
+                    let slot = match k {
+                        ItemKey::AlbumTitle => &mut names.album,
+                        ItemKey::AlbumTitleSortOrder => &mut names.album_sort,
+                        ItemKey::TrackTitle => &mut names.track,
+                        _ => &mut names.track_sort,
+                    };
+                    slot.get_or_insert_with(|| text.to_owned());
+                }
+                ItemKey::SetSubtitle => {
+                    if let Some(text) = v.text().map(str::trim).filter(|x| !x.is_empty()) {
+                        names.disc.get_or_insert_with(|| text.to_owned());
+                    }
+                }
+                ItemKey::AlbumArtistSortOrder
+                | ItemKey::TrackArtistSortOrder
+                | ItemKey::AlbumArtist
+                | ItemKey::TrackArtist
+                | ItemKey::TrackArtists => {
+                    let Some(text) = v.text() else {
+                        continue;
+                    };
+                    let list = match k {
+                        ItemKey::AlbumArtistSortOrder => &mut names.album_artist_sort,
+                        ItemKey::TrackArtistSortOrder => &mut names.track_artist_sort,
+                        ItemKey::AlbumArtist => &mut names.album_artist,
+                        _ => &mut names.track_artist,
+                    };
+                    NameCandidates::push_name(list, text);
+                }
+                ItemKey::DiscNumber
+                | ItemKey::DiscTotal
+                | ItemKey::TrackNumber
+                | ItemKey::TrackTotal
+                | ItemKey::MovementNumber
+                | ItemKey::MovementTotal => {
+                    let Some(pos) = v.text().and_then(Position::parse) else {
+                        // keep garbage around rather than dropping it
+                        let _ = ret.push_custom_tag(
+                            tag.tag_type(),
+                            original_key(tag.tag_type(), k),
+                            v.clone().into(),
+                        );
                         continue;
+                    };
+                    // first readable value wins
+                    match k {
+                        ItemKey::DiscNumber => {
+                            positions.disc.get_or_insert(pos);
+                        }
+                        ItemKey::TrackNumber => {
+                            positions.track.get_or_insert(pos);
+                        }
+                        ItemKey::MovementNumber => {
+                            positions.movement.get_or_insert(pos);
+                        }
+                        // totals are sometimes written as "12" or "/12"
+                        ItemKey::DiscTotal => {
+                            positions.disc_total = positions.disc_total.or(pos.total.or(pos.pos));
+                        }
+                        ItemKey::TrackTotal => {
+                            positions.track_total = positions.track_total.or(pos.total.or(pos.pos));
+                        }
+                        _ => {
+                            positions.movement_total =
+                                positions.movement_total.or(pos.total.or(pos.pos));
+                        }
                     }
-                    _ => unreachable!(),
-                };
-                ret.push_typed_tag(tag);
+                }
+                ItemKey::Popularimeter => {
+                    let rating = match v {
+                        ItemValue::Binary(x) => parse_popm_rating(x),
+                        ItemValue::Text(x) => parse_text_rating(x),
+                        ItemValue::Locator(_) => None,
+                    };
+                    match rating {
+                        // unrated
+                        Some(0) => {}
+                        Some(rating) => {
+                            let _ = ret.push_typed_tag(tag_set::Rating(rating));
+                        }
+                        // keep garbage around rather than dropping it
+                        None => {
+                            let _ = ret.push_custom_tag(
+                                tag.tag_type(),
+                                original_key(tag.tag_type(), k),
+                                v.clone().into(),
+                            );
+                        }
+                    }
+                }
+                ItemKey::RecordingDate
+                | ItemKey::Year
+                | ItemKey::ReleaseDate
+                | ItemKey::OriginalReleaseDate => {
+                    let slot = match k {
+                        ItemKey::RecordingDate => &mut dates.recording,
+                        ItemKey::Year => &mut dates.year,
+                        ItemKey::ReleaseDate => &mut dates.release,
+                        _ => &mut dates.original,
+                    };
+                    match v.text().and_then(PartialDate::parse) {
+                        // first readable value wins
+                        Some(date) => {
+                            slot.get_or_insert(date);
+                        }
+                        // keep garbage around rather than dropping it
+                        None => {
+                            let _ = ret.push_custom_tag(
+                                tag.tag_type(),
+                                original_key(tag.tag_type(), k),
+                                v.clone().into(),
+                            );
+                        }
+                    }
+                }
+                ItemKey::Work
+                | ItemKey::Movement
+                | ItemKey::Composer
+                | ItemKey::ComposerSortOrder => {
+                    let Some(text) = v.text().map(str::trim).filter(|x| !x.is_empty()) else {
+                        continue;
+                    };
+                    let slot = match k {
+                        ItemKey::Work => &mut names.work,
+                        ItemKey::Movement => &mut names.movement,
+                        ItemKey::Composer => &mut names.composer,
+                        _ => &mut names.composer_sort,
+                    };
+                    slot.get_or_insert_with(|| text.to_owned());
+                }
+                ItemKey::Conductor | ItemKey::Performer | ItemKey::Remixer => {
+                    let Some(text) = v.text() else {
+                        continue;
+                    };
+                    let list = match k {
+                        ItemKey::Conductor => &mut names.conductor,
+                        ItemKey::Performer => &mut names.performer,
+                        _ => &mut names.remixer,
+                    };
+                    NameCandidates::push_name(list, text);
+                }
+                ItemKey::MusicBrainzRecordingId
+                | ItemKey::MusicBrainzTrackId
+                | ItemKey::MusicBrainzReleaseId
+                | ItemKey::MusicBrainzReleaseGroupId
+                | ItemKey::MusicBrainzArtistId
+                | ItemKey::MusicBrainzReleaseArtistId
+                | ItemKey::MusicBrainzWorkId => {
+                    let Some(ids) = v.text().and_then(parse_musicbrainz_ids) else {
+                        // keep garbage around rather than dropping it
+                        let _ = ret.push_custom_tag(
+                            tag.tag_type(),
+                            original_key(tag.tag_type(), k),
+                            v.clone().into(),
+                        );
+                        continue;
+                    };
+                    // first id wins for the single valued ones
+                    let _ = match k {
+                        ItemKey::MusicBrainzArtistId => {
+                            artist_ids.extend(ids);
+                            continue;
+                        }
+                        ItemKey::MusicBrainzReleaseArtistId => {
+                            release_artist_ids.extend(ids);
+                            continue;
+                        }
+                        ItemKey::MusicBrainzRecordingId => ret
+                            .push_typed_tag(tag_set::MusicBrainzRecordingId(ids[0]))
+                            .map_err(drop),
+                        ItemKey::MusicBrainzTrackId => ret
+                            .push_typed_tag(tag_set::MusicBrainzTrackId(ids[0]))
+                            .map_err(drop),
+                        ItemKey::MusicBrainzReleaseId => ret
+                            .push_typed_tag(tag_set::MusicBrainzReleaseId(ids[0]))
+                            .map_err(drop),
+                        ItemKey::MusicBrainzReleaseGroupId => ret
+                            .push_typed_tag(tag_set::MusicBrainzReleaseGroupId(ids[0]))
+                            .map_err(drop),
+                        _ => ret
+                            .push_typed_tag(tag_set::MusicBrainzWorkId(ids[0]))
+                            .map_err(drop),
+                    };
+                }
+                ItemKey::FlagCompilation => {
+                    let flag = v
+                        .text()
+                        .map(str::trim)
+                        .is_some_and(|x| x == "1" || x.eq_ignore_ascii_case("true"));
+                    let _ = ret.push_typed_tag(tag_set::Compilation(flag));
+                }
+                ItemKey::ReplayGainAlbumGain
+                | ItemKey::ReplayGainAlbumPeak
+                | ItemKey::ReplayGainTrackGain
+                | ItemKey::ReplayGainTrackPeak => {
+                    let value = match k {
+                        ItemKey::ReplayGainAlbumGain | ItemKey::ReplayGainTrackGain => {
+                            v.text().and_then(parse_replay_gain)
+                        }
+                        _ => v.text().and_then(parse_replay_gain_peak),
+                    };
+                    let Some(value) = value else {
+                        // keep garbage around rather than dropping it
+                        let _ = ret.push_custom_tag(
+                            tag.tag_type(),
+                            original_key(tag.tag_type(), k),
+                            v.clone().into(),
+                        );
+                        continue;
+                    };
+                    // first readable value wins
+                    let _ = match k {
+                        ItemKey::ReplayGainAlbumGain => ret
+                            .push_typed_tag(tag_set::ReplayGainAlbumGain(value))
+                            .map_err(drop),
+                        ItemKey::ReplayGainAlbumPeak => ret
+                            .push_typed_tag(tag_set::ReplayGainAlbumPeak(value))
+                            .map_err(drop),
+                        ItemKey::ReplayGainTrackGain => ret
+                            .push_typed_tag(tag_set::ReplayGainTrackGain(value))
+                            .map_err(drop),
+                        _ => ret
+                            .push_typed_tag(tag_set::ReplayGainTrackPeak(value))
+                            .map_err(drop),
+                    };
+                }
+                ItemKey::Genre => {
+                    if let Some(text) = v.text() {
+                        for genre in split_genres(text) {
+                            NameCandidates::push_name(&mut genres, &genre);
+                        }
+                    }
+                }
+                ItemKey::ShowName | ItemKey::ShowNameSortOrder => {
+                    let Some(text) = v.text().map(str::trim).filter(|x| !x.is_empty()) else {
+                        continue;
+                    };
+                    let slot = match k {
+                        ItemKey::ShowName => &mut names.show,
+                        _ => &mut names.show_sort,
+                    };
+                    slot.get_or_insert_with(|| text.to_owned());
+                }
+                ItemKey::FlagPodcast => {
+                    let flag = v
+                        .text()
+                        .map(str::trim)
+                        .is_some_and(|x| x == "1" || x.eq_ignore_ascii_case("true"));
+                    let _ = ret.push_typed_tag(tag_set::PodcastFlag(flag));
+                }
+                ItemKey::PodcastKeywords => {
+                    if let Some(text) = v.text() {
+                        podcast_keywords.extend(
+                            text.split([',', ';'])
+                                .map(str::trim)
+                                .filter(|x| !x.is_empty())
+                                .map(ToOwned::to_owned),
+                        );
+                    }
+                }
+                // first value wins for the rest
+                ItemKey::PodcastDescription
+                | ItemKey::PodcastSeriesCategory
+                | ItemKey::PodcastUrl
+                | ItemKey::PodcastGlobalUniqueId => {
+                    let Some(text) = v.clone().into_string() else {
+                        continue;
+                    };
+                    let _ = match k {
+                        ItemKey::PodcastDescription => ret
+                            .push_typed_tag(tag_set::PodcastDescription(text))
+                            .map_err(drop),
+                        ItemKey::PodcastSeriesCategory => ret
+                            .push_typed_tag(tag_set::PodcastSeriesCategory(text))
+                            .map_err(drop),
+                        ItemKey::PodcastUrl => {
+                            ret.push_typed_tag(tag_set::PodcastUrl(text)).map_err(drop)
+                        }
+                        _ => ret
+                            .push_typed_tag(tag_set::PodcastGlobalUniqueId(text))
+                            .map_err(drop),
+                    };
+                }
+                ItemKey::Comment | ItemKey::Lyrics => {
+                    let Some(text) = v.text() else {
+                        continue;
+                    };
+                    let text =
+                        LocalizedText::new(*item.lang(), item.description(), text.to_owned());
+                    if k == &ItemKey::Comment {
+                        comments.push(text);
+                    } else {
+                        lyrics.push(text);
+                    }
+                }
+
+                // ID3v2.3 splits the date off from the year, and lofty only
+                // merges it back into TDRC when it is valid
+                ItemKey::Unknown(item) if item == "TDAT" => {
+                    dates.id3_tdat = v.text().map(ToOwned::to_owned);
+                }
+                // Opus uses its own gain tags instead of ReplayGain
+                ItemKey::Unknown(item)
+                    if item.eq_ignore_ascii_case("R128_TRACK_GAIN")
+                        || item.eq_ignore_ascii_case("R128_ALBUM_GAIN") =>
+                {
+                    let Some(gain) = v.text().and_then(parse_r128_gain) else {
+                        let _ = ret.push_custom_tag(tag.tag_type(), item, v.clone().into());
+                        continue;
+                    };
+                    if item.eq_ignore_ascii_case("R128_TRACK_GAIN") {
+                        r128_track_gain.get_or_insert(gain);
+                    } else {
+                        r128_album_gain.get_or_insert(gain);
+                    }
+                }
+                // everything else is kept as a custom tag
+                _ => {
+                    let _ = ret.push_custom_tag(
+                        tag.tag_type(),
+                        original_key(tag.tag_type(), k),
+                        v.clone().into(),
+                    );
+                }
             }
         }
-        tx.send(ret)
-            .expect("the outer thread closed the recv, and therefore we cannot send the tags");
-    });
+    }
 
-    let ret = rx
-        .recv()
-        .expect("the inner thread did not return, and instead dropped the oneshot");
-    drop(_lock);
-    ret
+    // ReplayGain tags win, should a file have both
+    if let Some(gain) = r128_track_gain {
+        let _ = ret.push_typed_tag(tag_set::ReplayGainTrackGain(gain));
+    }
+    if let Some(gain) = r128_album_gain {
+        let _ = ret.push_typed_tag(tag_set::ReplayGainAlbumGain(gain));
+    }
+    positions.resolve_into(&mut ret);
+    names.resolve_into(&mut ret);
+    if !artist_ids.is_empty() {
+        let _ = ret.push_typed_tag(tag_set::MusicBrainzArtistId(artist_ids));
+    }
+    if !release_artist_ids.is_empty() {
+        let _ = ret.push_typed_tag(tag_set::MusicBrainzReleaseArtistId(release_artist_ids));
+    }
+    if !podcast_keywords.is_empty() {
+        let _ = ret.push_typed_tag(tag_set::PodcastKeywords(podcast_keywords));
+    }
+    if !genres.is_empty() {
+        let _ = ret.push_typed_tag(tag_set::Genre(genres));
+    }
+    let chapters = read_chapters(inp, probe.file_type());
+    if !chapters.is_empty() {
+        let _ = ret.push_typed_tag(tag_set::Chapters(chapters));
+    }
+    if !comments.is_empty() {
+        let _ = ret.push_typed_tag(tag_set::Comment(comments));
+    }
+    if !lyrics.is_empty() {
+        let _ = ret.push_typed_tag(tag_set::Lyrics(lyrics));
+    }
+    let (release, original) = dates.resolve();
+    if let Some(release) = release {
+        let _ = ret.push_typed_tag(tag_set::ReleaseDate(release));
+    }
+    if let Some(original) = original {
+        let _ = ret.push_typed_tag(tag_set::OriginalReleaseDate(original));
+    }
+    let audio = read_properties.then(|| AudioField::new(probe.properties(), probe.file_type()));
+    Some((ret, audio))
+}
+
+/// The key of an item as it is spelled in the file's tag format, for custom
+/// tags. Format specific decoration is stripped off of unknown keys, so that
+/// the same custom tag has the same key in every format. Case is handled by
+/// the `TagSet`.
+fn original_key(tag_type: TagType, key: &ItemKey) -> Cow<'_, str> {
+    match (key, tag_type) {
+        // freeform atoms are keyed as "----:mean:name"
+        (ItemKey::Unknown(key), TagType::Mp4Ilst) => Cow::from(
+            key.strip_prefix("----:")
+                .and_then(|x| x.split_once(':'))
+                .map_or(key.as_str(), |(_, name)| name),
+        ),
+        _ => key
+            .map_key(tag_type, true)
+            .map_or_else(|| Cow::from(format!("{key:?}")), Cow::from),
+    }
+}
+
+/// Parse one or more MusicBrainz ids out of a tag value. Multiple ids may be
+/// separated by ';', '/' or ','. Returns `None` if any of them are invalid.
+fn parse_musicbrainz_ids(inp: &str) -> Option<Vec<uuid::Uuid>> {
+    let ret = inp
+        .split([';', '/', ','])
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| uuid::Uuid::parse_str(x).ok())
+        .collect::<Option<Vec<_>>>()?;
+    (!ret.is_empty()).then_some(ret)
+}
+
+/// Split a genre value into its genres. Multiple genres may be separated by
+/// ';' or a null byte. '/' is left alone, as it shows up inside of genre names
+/// ("Rap/Hip Hop"). ID3v1 style genre numbers ("(17)" or "17") are replaced
+/// with their names.
+fn split_genres(inp: &str) -> impl Iterator<Item = String> {
+    inp.split([';', '\0'])
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            x.strip_prefix('(')
+                .and_then(|x| x.strip_suffix(')'))
+                .unwrap_or(x)
+                .parse::<usize>()
+                .ok()
+                .and_then(|x| lofty::id3::v1::GENRES.get(x))
+                .map_or_else(|| x.to_owned(), |x| (*x).to_owned())
+        })
+}
+
+/// Parse a ReplayGain gain, eg. "-7.03 dB".
+fn parse_replay_gain(inp: &str) -> Option<f32> {
+    inp.trim()
+        .trim_end_matches(|x: char| x.is_ascii_alphabetic())
+        .trim_end()
+        .parse::<f32>()
+        .ok()
+        .filter(|x| x.is_finite())
+}
+
+/// Parse a ReplayGain peak, eg. "0.988312", where 1 is full scale.
+fn parse_replay_gain_peak(inp: &str) -> Option<f32> {
+    inp.trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|x| x.is_finite() && *x >= 0.0)
+}
+
+/// Parse an Opus `R128_*_GAIN` tag into a ReplayGain gain. These are in
+/// 1/256 dB, and relative to -23 LUFS rather than ReplayGain's -18 LUFS.
+fn parse_r128_gain(inp: &str) -> Option<f32> {
+    let gain = inp.trim().parse::<i16>().ok()?;
+    Some(f32::from(gain) / 256.0 + 5.0)
+}
+
+/// Read the star rating out of an ID3v2 `POPM` frame: an email address, then
+/// a rating of 1 to 255 (0 being unrated), then a play counter.
+///
+/// Players disagree on how 1-255 maps to stars; this uses the Windows Media
+/// Player steps (1, 64, 128, 196, 255), which most others follow.
+fn parse_popm_rating(inp: &[u8]) -> Option<u8> {
+    let email_end = inp.iter().position(|x| *x == 0)?;
+    let rating = *inp.get(email_end + 1)?;
+    Some(match rating {
+        0 => 0,
+        1..=63 => 1,
+        64..=127 => 2,
+        128..=195 => 3,
+        196..=254 => 4,
+        255 => 5,
+    })
+}
+
+/// Read a star rating out of a text rating, which is either out of 5 or out
+/// of 100 depending on the tagger.
+fn parse_text_rating(inp: &str) -> Option<u8> {
+    let rating = inp.trim().parse::<f64>().ok()?;
+    let stars = match rating {
+        ..0.0 => return None,
+        0.0..=5.0 => rating,
+        5.0..=100.0 => rating / 20.0,
+        _ => return None,
+    };
+    Some(stars.round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn original_keys() {
+        assert_eq!(
+            original_key(TagType::VorbisComments, &ItemKey::Label),
+            "LABEL"
+        );
+        assert_eq!(original_key(TagType::Id3v2, &ItemKey::Publisher), "TPUB");
+        assert_eq!(
+            original_key(
+                TagType::Mp4Ilst,
+                &ItemKey::Unknown("----:com.apple.iTunes:MOOD".to_owned())
+            ),
+            "MOOD"
+        );
+        assert_eq!(
+            original_key(
+                TagType::VorbisComments,
+                &ItemKey::Unknown("mood".to_owned())
+            ),
+            "mood"
+        );
+    }
+
+    /// A FLAC file with no audio, 44.1 kHz stereo 16 bit, with these Vorbis
+    /// comments.
+    fn flac_with_comments(comments: &[&str]) -> Vec<u8> {
+        let push = |block: &mut Vec<u8>, x: &[u8]| {
+            block.extend((x.len() as u32).to_le_bytes());
+            block.extend(x);
+        };
+        let mut block = vec![];
+        push(&mut block, b"mioplays");
+        block.extend((comments.len() as u32).to_le_bytes());
+        for x in comments {
+            push(&mut block, x.as_bytes());
+        }
+
+        let mut ret = b"fLaC".to_vec();
+        // STREAMINFO
+        ret.extend([0x00, 0x00, 0x00, 0x22, 0x10, 0x00, 0x10, 0x00]);
+        ret.extend([0; 6]);
+        ret.extend([0x0a, 0xc4, 0x42, 0xf0, 0x00, 0x00, 0x00, 0x00]);
+        ret.extend([0; 16]);
+        // VORBIS_COMMENT, the last block
+        ret.push(0x84);
+        ret.extend(&(block.len() as u32).to_be_bytes()[1..]);
+        ret.extend(block);
+        ret
+    }
+
+    #[test]
+    fn credits() {
+        let path =
+            std::env::temp_dir().join(format!("mioplays-{}-credits.flac", std::process::id()));
+        let file = flac_with_comments(&[
+            "PERFORMER=Glenn Gould",
+            "PERFORMER=Yo-Yo Ma",
+            "REMIXER=Aphex Twin",
+            "CONDUCTOR=Herbert von Karajan",
+        ]);
+        std::fs::write(&path, file).unwrap();
+        let (tags, _) = smol::block_on(decode_tags(path.clone(), false)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            tags.get_typed_tag::<tag_set::Performer>().unwrap().0,
+            ["Glenn Gould", "Yo-Yo Ma"]
+        );
+        assert_eq!(
+            tags.get_typed_tag::<tag_set::Remixer>().unwrap().0,
+            ["Aphex Twin"]
+        );
+        assert_eq!(
+            tags.get_typed_tag::<tag_set::Conductor>().unwrap().0,
+            ["Herbert von Karajan"]
+        );
+        assert!(
+            tags.get_custom_tag(TagType::VorbisComments, "PERFORMER")
+                .is_none()
+        );
+        assert!(
+            tags.get_custom_tag(TagType::VorbisComments, "REMIXER")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn unreadable_files() {
+        let path = std::env::temp_dir().join(format!("mioplays-{}-cover.jpg", std::process::id()));
+        std::fs::write(&path, b"not an image, nor audio").unwrap();
+        assert!(smol::block_on(decode_tags(path.clone(), true)).is_none());
+        std::fs::remove_file(&path).unwrap();
+        assert!(smol::block_on(decode_tags(path, true)).is_none());
+    }
 }
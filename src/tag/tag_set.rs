@@ -1,9 +1,14 @@
 use std::{
     any::{Any, TypeId},
-    collections::HashMap,
-    fmt::{self, Debug},
+    collections::{HashMap, hash_map::Entry},
+    fmt::Debug,
 };
 
+use lofty::tag::TagType;
+use uuid::Uuid;
+
+use crate::tag::{Chapter, LocalizedText, PartialDate};
+
 /// `Tag`: a sealed marker trait for interacting with the `TagMap` in a
 /// typed manner.
 mod private {
@@ -11,34 +16,44 @@ mod private {
 }
 pub trait Tag: private::Sealed + Any + Debug {
     fn to_any(&self) -> &(dyn Any + 'static);
+    fn to_any_mut(&mut self) -> &mut (dyn Any + 'static);
+    #[allow(dead_code, reason = "part of the TagSet API, for taking tags out")]
     fn to_any_boxed(self: Box<Self>) -> Box<dyn Any + 'static>;
     fn display_name(&self) -> Option<&str>;
+    /// The value as a line of text, or `None` if it does not fit on one.
+    fn display_value(&self) -> Option<String>;
 }
 macro_rules! tag_impl {
-    ($tag:ident {$($name:ident : $typ:ty),+} => $display_name:expr) => {
+    // the first field is the value, the rest (eg. sort orders) are not shown
+    ($tag:ident {$value:ident : $value_typ:ty $(, $name:ident : $typ:ty)*} => $display_name:expr) => {
         #[derive(Debug)]
         pub struct $tag {
-            $( $name: $typ, )+
+            pub $value: $value_typ,
+            $( pub $name: $typ, )*
         }
 
-        tag_trait_impl!($tag: $display_name);
+        tag_trait_impl!($tag: $display_name, |x: &$tag| x.$value.display_value());
     };
     ($tag:ident as $inner:ty => $display_name:expr) => {
         #[derive(Debug)]
         pub struct $tag(pub $inner);
 
-        tag_trait_impl!($tag: $display_name);
+        tag_trait_impl!($tag: $display_name, |x: &$tag| x.0.display_value());
     };
 
 }
 macro_rules! tag_trait_impl {
-    ($tag:ident: $display_name:expr) => {
+    ($tag:ident: $display_name:expr, $display_value:expr) => {
         impl private::Sealed for $tag {}
         impl Tag for $tag {
             fn to_any(&self) -> &(dyn Any + 'static) {
                 self
             }
 
+            fn to_any_mut(&mut self) -> &mut (dyn Any + 'static) {
+                self
+            }
+
             fn to_any_boxed(self: Box<Self>) -> Box<dyn Any + 'static> {
                 self
             }
@@ -46,10 +61,65 @@ macro_rules! tag_trait_impl {
             fn display_name(&self) -> Option<&str> {
                 Some($display_name)
             }
+
+            fn display_value(&self) -> Option<String> {
+                $display_value(self)
+            }
         }
     };
 }
 
+/// Values of typed tags as text, for `Tag::display_value`.
+trait DisplayValue {
+    fn display_value(&self) -> Option<String>;
+}
+
+macro_rules! display_value_impl {
+    ($($typ:ty),+) => {
+        $(
+            impl DisplayValue for $typ {
+                fn display_value(&self) -> Option<String> {
+                    Some(self.to_string())
+                }
+            }
+        )+
+    };
+}
+
+display_value_impl!(String, u8, u32, Uuid, PartialDate);
+
+impl DisplayValue for bool {
+    fn display_value(&self) -> Option<String> {
+        Some(if *self { "Yes" } else { "No" }.to_owned())
+    }
+}
+
+impl DisplayValue for f32 {
+    fn display_value(&self) -> Option<String> {
+        Some(format!("{self:.2}"))
+    }
+}
+
+impl<T: DisplayValue> DisplayValue for Vec<T> {
+    fn display_value(&self) -> Option<String> {
+        let ret = self.iter().filter_map(T::display_value).collect::<Vec<_>>();
+        (!ret.is_empty()).then(|| ret.join(", "))
+    }
+}
+
+// shown on their own rather than in a line
+impl DisplayValue for Chapter {
+    fn display_value(&self) -> Option<String> {
+        None
+    }
+}
+
+impl DisplayValue for LocalizedText {
+    fn display_value(&self) -> Option<String> {
+        None
+    }
+}
+
 // List of all tag structs being mapped to the appropriate item
 
 // some notes before this arduous hell:
@@ -60,75 +130,133 @@ macro_rules! tag_trait_impl {
 // (AlbumTitleSortOrder -> AlbumTitle {inner: String, sort_order: Option<String>})
 
 // Titles/Subtitles
-tag_impl!(AlbumTitle {inner: String, sort_order: String} => "Album Title");
-tag_impl!(TrackTitle {inner: String, sort_order: String} => "Track Title");
+tag_impl!(AlbumTitle {inner: String, sort_order: Option<String>} => "Album Title");
+tag_impl!(TrackTitle {inner: String, sort_order: Option<String>} => "Track Title");
 tag_impl!(DiscTitle as String => "Disc Title"); // title of individual disk
+tag_impl!(Work as String => "Work");
+tag_impl!(Movement as String => "Movement");
+tag_impl!(Compilation as bool => "Compilation"); // various artists release
 
 // Creators (Artists) & Credits
 tag_impl!(AlbumArtist {inner: Vec<String>, sort_order: Vec<String>} => "Album Artist");
 tag_impl!(TrackArtist {inner: Vec<String>, sort_order: Vec<String>} => "Track Artist");
-tag_impl!(Composer {inner: String, sort_order: String} => "Composer");
+tag_impl!(Composer {inner: String, sort_order: Option<String>} => "Composer");
+tag_impl!(Conductor as Vec<String> => "Conductor");
 tag_impl!(Performer as Vec<String> => "Performer");
 tag_impl!(Remixer as Vec<String> => "Remixer");
 
 // Music information
-//ReplayGainAlbumGain
-//ReplayGainAlbumPeak
-//ReplayGainTrackGain
-//ReplayGainTrackPeak
-//Genre
+tag_impl!(ReplayGainAlbumGain as f32 => "ReplayGain Album Gain"); // dB
+tag_impl!(ReplayGainAlbumPeak as f32 => "ReplayGain Album Peak"); // 1.0 is full scale
+tag_impl!(ReplayGainTrackGain as f32 => "ReplayGain Track Gain"); // dB
+tag_impl!(ReplayGainTrackPeak as f32 => "ReplayGain Track Peak"); // 1.0 is full scale
+tag_impl!(Genre as Vec<String> => "Genre");
 //InitialKey
 //Color
 //Mood
 //Bpm
 //IntegerBpm
+tag_impl!(Rating as u8 => "Rating"); // stars, 1 to 5
+
+// Identifiers
+tag_impl!(MusicBrainzRecordingId as Uuid => "MusicBrainz Recording Id");
+tag_impl!(MusicBrainzTrackId as Uuid => "MusicBrainz Track Id");
+tag_impl!(MusicBrainzReleaseId as Uuid => "MusicBrainz Release Id");
+tag_impl!(MusicBrainzReleaseGroupId as Uuid => "MusicBrainz Release Group Id");
+tag_impl!(MusicBrainzArtistId as Vec<Uuid> => "MusicBrainz Artist Id");
+tag_impl!(MusicBrainzReleaseArtistId as Vec<Uuid> => "MusicBrainz Release Artist Id");
+tag_impl!(MusicBrainzWorkId as Uuid => "MusicBrainz Work Id");
+
+// Podcasts & spoken word
+tag_impl!(ShowName {inner: String, sort_order: Option<String>} => "Show Name");
+tag_impl!(PodcastFlag as bool => "Podcast");
+tag_impl!(PodcastDescription as String => "Podcast Description");
+tag_impl!(PodcastSeriesCategory as String => "Podcast Category");
+tag_impl!(PodcastUrl as String => "Podcast Feed");
+tag_impl!(PodcastGlobalUniqueId as String => "Podcast Episode Id");
+tag_impl!(PodcastKeywords as Vec<String> => "Podcast Keywords");
+tag_impl!(Chapters as Vec<Chapter> => "Chapters");
 
 // Other
 tag_impl!(DiscPos as u32 => "Disk Number");
 tag_impl!(DiscTotal as u32 => "Number of Disks");
 tag_impl!(TrackPos as u32 => "Track Number");
 tag_impl!(TrackTotal as u32 => "Number of Tracks");
-tag_impl!(ReleaseDate as jiff::Timestamp => "Release Date");
+tag_impl!(MovementPos as u32 => "Movement Number");
+tag_impl!(MovementTotal as u32 => "Number of Movements");
+tag_impl!(TrackSide {side: String, label: String} => "Side"); // vinyl side, and the raw position ("B2")
+tag_impl!(ReleaseDate as PartialDate => "Release Date");
+tag_impl!(OriginalReleaseDate as PartialDate => "Original Release Date");
 
-//Comment
+// Text, which may be in multiple languages
+tag_impl!(Comment as Vec<LocalizedText> => "Comment");
+tag_impl!(Lyrics as Vec<LocalizedText> => "Lyrics");
 //Description
 //Language
 //Script
-//Lyrics
+
+/// The value of a custom tag, mirroring lofty's `ItemValue`. Text values
+/// are kept as a list, as formats like Vorbis comments allow repeating a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CustomValue {
+    Text(Vec<String>),
+    Locator(String),
+    Binary(Vec<u8>),
+}
+
+impl From<lofty::tag::ItemValue> for CustomValue {
+    fn from(value: lofty::tag::ItemValue) -> Self {
+        match value {
+            lofty::tag::ItemValue::Text(x) => Self::Text(vec![x]),
+            lofty::tag::ItemValue::Locator(x) => Self::Locator(x),
+            lofty::tag::ItemValue::Binary(x) => Self::Binary(x),
+        }
+    }
+}
 
 // Special tag for the string insert
 #[derive(Debug)]
-pub struct UnknownItem(Box<dyn Any + Send + Sync + 'static>);
-impl private::Sealed for UnknownItem {}
-impl Tag for UnknownItem {
-    fn to_any(&self) -> &(dyn Any + 'static) {
-        self
-    }
+pub struct UnknownItem {
+    tag_type: TagType,
+    key: String,
+    value: CustomValue,
+}
 
-    fn to_any_boxed(self: Box<Self>) -> Box<dyn Any + 'static> {
-        self
+impl UnknownItem {
+    /// The key, spelled as it was first seen in the file.
+    pub fn key(&self) -> &str {
+        &self.key
     }
 
-    fn display_name(&self) -> Option<&str> {
-        None
+    pub fn value(&self) -> &CustomValue {
+        &self.value
     }
 }
 
-// Special tag for the Cover Art
-#[derive(Debug)]
-pub struct EncodedCoverArt(Box<[u8]>);
-impl private::Sealed for EncodedCoverArt {}
-impl Tag for EncodedCoverArt {
+impl private::Sealed for UnknownItem {}
+impl Tag for UnknownItem {
     fn to_any(&self) -> &(dyn Any + 'static) {
         self
     }
 
+    fn to_any_mut(&mut self) -> &mut (dyn Any + 'static) {
+        self
+    }
+
     fn to_any_boxed(self: Box<Self>) -> Box<dyn Any + 'static> {
         self
     }
 
     fn display_name(&self) -> Option<&str> {
-        None
+        Some(self.key())
+    }
+
+    fn display_value(&self) -> Option<String> {
+        match &self.value {
+            CustomValue::Text(x) => x.display_value(),
+            CustomValue::Locator(x) => Some(x.clone()),
+            CustomValue::Binary(_) => None,
+        }
     }
 }
 
@@ -138,7 +266,13 @@ impl Tag for EncodedCoverArt {
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
 enum TIDOrCustom {
     TypeId(TypeId),
-    Custom(String),
+    /// The key as the format compares it, and whether that ignores case.
+    /// Keeping the two apart means a folded Vorbis key never collides with
+    /// an ID3v2 key that happens to be spelled in lowercase.
+    Custom {
+        key: String,
+        folded: bool,
+    },
 }
 
 impl From<TypeId> for TIDOrCustom {
@@ -147,23 +281,30 @@ impl From<TypeId> for TIDOrCustom {
     }
 }
 
-impl From<String> for TIDOrCustom {
-    fn from(value: String) -> Self {
-        Self::Custom(value)
-    }
-}
-
-impl From<&str> for TIDOrCustom {
-    fn from(value: &str) -> Self {
-        Self::Custom(value.to_owned())
+impl TIDOrCustom {
+    /// Vorbis comment and APE keys are case-insensitive, so they are
+    /// lowercased, while ID3v2, MP4 and RIFF keys are compared as they are.
+    /// Any character is folded rather than ASCII only, so that "ÄRA" and
+    /// "Ära" are one key.
+    fn custom(key: &str, tag_type: TagType) -> Self {
+        match tag_type {
+            TagType::VorbisComments | TagType::Ape => Self::Custom {
+                key: key.to_lowercase(),
+                folded: true,
+            },
+            _ => Self::Custom {
+                key: key.to_owned(),
+                folded: false,
+            },
+        }
     }
 }
 
 /// A Mapping of tags to custom structures. The tags may be
 /// defined by a specific struct with the `Tag` trait, or a string.
 /// Fetching a typed item will return it's associated struct, while a
-/// string will only ever return a `dyn Tag` which can be cast into a
-/// `dyn Any` using the `Tag::to_any` and `Tag::to_any_boxed` methods.
+/// string will only ever return an `UnknownItem`. Boxed tags can be cast into
+/// a `dyn Any` using the `Tag::to_any` and `Tag::to_any_boxed` methods.
 #[derive(Debug)]
 pub struct TagSet {
     map: HashMap<TIDOrCustom, Box<dyn Tag + Send + Sync + 'static>>,
@@ -183,14 +324,12 @@ impl TagSet {
     /// Add tag to set. If the associated tag is already in the set,
     /// return the argument as an error.
     pub fn push_typed_tag<K: Tag + Send + Sync + 'static>(&mut self, tag: K) -> Result<(), K> {
-        if self.map.contains_key(&tag.type_id().into()) {
-            Err(tag)
-        } else {
-            let ret = self.map.insert(tag.type_id().into(), Box::new(tag));
-            if ret.is_some() {
-                panic!("somehow, we don't contain a key we have access to now")
+        match self.map.entry(tag.type_id().into()) {
+            Entry::Occupied(_) => Err(tag),
+            Entry::Vacant(x) => {
+                x.insert(Box::new(tag));
+                Ok(())
             }
-            Ok(())
         }
     }
 
@@ -201,6 +340,7 @@ impl TagSet {
     }
 
     /// Fetch and return a typed `Tag`, removing it from the `TagMap`.
+    #[allow(dead_code, reason = "part of the TagSet API")]
     pub fn drop_typed_tag<K: Tag + Send + Sync + 'static>(&mut self) -> Option<K> {
         let type_id = TypeId::of::<K>();
         let ret = self
@@ -208,14 +348,13 @@ impl TagSet {
             .remove(&type_id.into())?
             .to_any_boxed()
             .downcast::<K>();
-        if ret.is_err() {
-            panic!(
+        match ret {
+            Ok(x) => Some(*x),
+            Err(_) => panic!(
                 "tag map type mismatch: expected {:?} (aka {}) but the key was not that",
                 type_id,
                 std::any::type_name::<K>(),
-            );
-        } else {
-            Some(*ret.unwrap())
+            ),
         }
     }
 }
@@ -223,38 +362,204 @@ impl TagSet {
 // Stringly accessing fields
 impl TagSet {
     /// Add tag to set which does not have an associated type, but a custom string.
-    /// If the associated tag is already in the set, return the argument as an error.
+    /// Keys are matched case-insensitively for the formats that treat them so.
+    /// If the key is already in the set, text values are appended to the
+    /// existing list; otherwise the value is returned as an error.
     pub fn push_custom_tag(
         &mut self,
+        tag_type: TagType,
         key: impl AsRef<str>,
-        value: Box<dyn Any + Send + Sync + 'static>,
-    ) -> Result<(), Box<dyn Tag + Send + Sync + 'static>> {
-        let key = key.as_ref().into();
-        let value = Box::new(UnknownItem(value));
-        if self.map.contains_key(&key) {
-            Err(value)
-        } else {
-            let ret = self.map.insert(key, value);
-            if ret.is_some() {
-                panic!("somehow, we don't contain a *string* key that exists during insertion")
+        value: CustomValue,
+    ) -> Result<(), CustomValue> {
+        let key = key.as_ref();
+        let existing = match self.map.entry(TIDOrCustom::custom(key, tag_type)) {
+            Entry::Occupied(x) => x.into_mut(),
+            Entry::Vacant(x) => {
+                x.insert(Box::new(UnknownItem {
+                    tag_type,
+                    key: key.to_owned(),
+                    value,
+                }));
+                return Ok(());
             }
-            Ok(())
+        };
+        let existing = existing
+            .to_any_mut()
+            .downcast_mut::<UnknownItem>()
+            .expect("custom keys only ever hold an UnknownItem");
+        match (&mut existing.value, value) {
+            (CustomValue::Text(existing), CustomValue::Text(value)) => {
+                existing.extend(value);
+                Ok(())
+            }
+            (_, value) => Err(value),
         }
     }
 
-    /// Fetch a reference to an associated custom tag object.
-    pub fn get_custom_tag(
-        &self,
-        key: impl AsRef<str>,
-    ) -> Option<&Box<dyn Tag + Send + Sync + 'static>> {
-        self.map.get(&key.as_ref().into())
+    /// Fetch a reference to an associated custom tag object, matching the
+    /// key the way `tag_type` compares keys.
+    #[allow(dead_code, reason = "part of the TagSet API")]
+    pub fn get_custom_tag(&self, tag_type: TagType, key: impl AsRef<str>) -> Option<&UnknownItem> {
+        let ret = self.map.get(&TIDOrCustom::custom(key.as_ref(), tag_type))?;
+        Some(
+            ret.to_any()
+                .downcast_ref()
+                .expect("custom keys only ever hold an UnknownItem"),
+        )
     }
 
     /// Fetch and return an associated custom tag object, removing it from the `TagMap`.
+    #[allow(dead_code, reason = "part of the TagSet API")]
     pub fn drop_custom_tag(
         &mut self,
+        tag_type: TagType,
         key: impl AsRef<str>,
-    ) -> Option<Box<dyn Tag + Send + Sync + 'static>> {
-        self.map.remove(&key.as_ref().into())
+    ) -> Option<UnknownItem> {
+        let ret = self
+            .map
+            .remove(&TIDOrCustom::custom(key.as_ref(), tag_type))?;
+        Some(
+            *ret.to_any_boxed()
+                .downcast()
+                .expect("custom keys only ever hold an UnknownItem"),
+        )
+    }
+
+    /// Every custom tag that `key` names, whatever its format, with each
+    /// matched the way its own format compares keys.
+    pub fn find_custom_tags<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a UnknownItem> {
+        self.map.iter().filter_map(move |(id, x)| {
+            let x = x.to_any().downcast_ref::<UnknownItem>()?;
+            (*id == TIDOrCustom::custom(key, x.tag_type)).then_some(x)
+        })
+    }
+}
+
+// Accessing every field
+impl TagSet {
+    /// Iterate over all tags in the set, typed and custom, in no particular
+    /// order.
+    pub fn iter(&self) -> impl Iterator<Item = &(dyn Tag + Send + Sync + 'static)> {
+        self.map.values().map(|x| &**x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_keys_ignore_case() {
+        let mut set = TagSet::new();
+        let text = |x: &str| CustomValue::Text(vec![x.to_owned()]);
+        let vorbis = TagType::VorbisComments;
+        assert_eq!(set.push_custom_tag(vorbis, "MOOD", text("calm")), Ok(()));
+        assert_eq!(set.push_custom_tag(vorbis, "mood", text("dark")), Ok(()));
+        assert_eq!(
+            set.push_custom_tag(TagType::Ape, "ÄRA", text("late")),
+            Ok(())
+        );
+        assert_eq!(
+            set.push_custom_tag(vorbis, "Mood", CustomValue::Binary(vec![1])),
+            Err(CustomValue::Binary(vec![1]))
+        );
+
+        let mood = set.get_custom_tag(vorbis, "Mood").unwrap();
+        assert_eq!(mood.key(), "MOOD");
+        assert_eq!(
+            mood.value(),
+            &CustomValue::Text(vec!["calm".to_owned(), "dark".to_owned()])
+        );
+        assert_eq!(
+            set.get_custom_tag(TagType::Ape, "ära").unwrap().key(),
+            "ÄRA"
+        );
+        assert_eq!(set.iter().count(), 2);
+        let mut lines = set
+            .iter()
+            .filter_map(|x| Some(format!("{}: {}", x.display_name()?, x.display_value()?)))
+            .collect::<Vec<_>>();
+        lines.sort();
+        assert_eq!(lines, ["MOOD: calm, dark", "ÄRA: late"]);
+    }
+
+    #[test]
+    fn custom_keys_keep_case() {
+        let mut set = TagSet::new();
+        let text = |x: &str| CustomValue::Text(vec![x.to_owned()]);
+        assert_eq!(
+            set.push_custom_tag(TagType::Id3v2, "MOOD", text("calm")),
+            Ok(())
+        );
+        assert_eq!(
+            set.push_custom_tag(TagType::Mp4Ilst, "Mood", text("dark")),
+            Ok(())
+        );
+        assert_eq!(set.iter().count(), 2);
+        let id3 = |x| set.get_custom_tag(TagType::Id3v2, x).map(|x| x.value());
+        assert_eq!(id3("MOOD"), Some(&text("calm")));
+        assert_eq!(id3("Mood"), Some(&text("dark")));
+        assert_eq!(id3("mood"), None);
+
+        // a Vorbis key is found by any case, but only among Vorbis keys
+        assert_eq!(
+            set.push_custom_tag(TagType::VorbisComments, "mood", text("late")),
+            Ok(())
+        );
+        assert_eq!(set.iter().count(), 3);
+        let vorbis = |x| {
+            set.get_custom_tag(TagType::VorbisComments, x)
+                .map(|x| x.value())
+        };
+        assert_eq!(vorbis("Mood"), Some(&text("late")));
+        assert!(set.get_custom_tag(TagType::Id3v2, "mood").is_none());
+        let mut found = set
+            .find_custom_tags("MOOD")
+            .map(|x| x.value().clone())
+            .collect::<Vec<_>>();
+        found.sort_by_key(|x| format!("{x:?}"));
+        assert_eq!(found, [text("calm"), text("late")]);
+    }
+
+    #[test]
+    fn drop_tags() {
+        let mut set = TagSet::new();
+        let text = |x: &str| CustomValue::Text(vec![x.to_owned()]);
+        assert!(set.push_typed_tag(Rating(4)).is_ok());
+        assert_eq!(
+            set.push_custom_tag(TagType::Ape, "Era", text("late")),
+            Ok(())
+        );
+        assert_eq!(set.drop_typed_tag::<Rating>().map(|x| x.0), Some(4));
+        assert!(set.drop_typed_tag::<Rating>().is_none());
+        assert!(set.drop_custom_tag(TagType::Id3v2, "era").is_none());
+        assert_eq!(
+            set.drop_custom_tag(TagType::Ape, "ERA")
+                .map(|x| x.key().to_owned()),
+            Some("Era".to_owned())
+        );
+        assert_eq!(set.iter().count(), 0);
+    }
+
+    #[test]
+    fn display_values() {
+        let title = TrackTitle {
+            inner: "Title".to_owned(),
+            sort_order: Some("Sort".to_owned()),
+        };
+        assert_eq!(title.display_value().as_deref(), Some("Title"));
+        let performers = Performer(vec!["A".to_owned(), "B".to_owned()]);
+        assert_eq!(performers.display_value().as_deref(), Some("A, B"));
+        assert_eq!(Performer(vec![]).display_value(), None);
+        assert_eq!(Compilation(true).display_value().as_deref(), Some("Yes"));
+        assert_eq!(
+            ReplayGainTrackGain(-6.5).display_value().as_deref(),
+            Some("-6.50")
+        );
+        assert_eq!(Lyrics(vec![]).display_value(), None);
+        assert_eq!(
+            ReplayGainTrackGain(0.0).display_name(),
+            Some("ReplayGain Track Gain")
+        );
     }
 }